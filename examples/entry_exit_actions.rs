@@ -0,0 +1,56 @@
+//! Entry and exit actions example
+//!
+//! An example of running actions whenever a state is entered or left, regardless of which
+//! transition caused it.
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+/// State data
+pub struct Meter(pub u32);
+
+statemachine! {
+    transitions: {
+        *Idle + Plug / start_charging = Charging(Meter),
+        Charging(Meter) + Unplug = Idle,
+        Charging(Meter) + Fault = Idle,
+    },
+    states: {
+        Charging: { entry: start_meter, exit: stop_meter },
+    }
+}
+
+/// Context
+pub struct Context {
+    /// Whether the meter is running
+    pub meter_running: bool,
+}
+
+impl StateMachineContext for Context {
+    fn start_charging(&mut self) -> Meter {
+        Meter(0)
+    }
+
+    // Called every time `Charging` is entered, with access to its data
+    fn start_meter(&mut self, _state_data: &Meter) {
+        self.meter_running = true;
+    }
+
+    // Called every time `Charging` is left, no matter which event caused it
+    fn stop_meter(&mut self, _state_data: &Meter) {
+        self.meter_running = false;
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context {
+        meter_running: false,
+    });
+
+    sm.process_event(Events::Plug).unwrap();
+    assert!(sm.context().meter_running);
+
+    sm.process_event(Events::Fault).unwrap();
+    assert!(!sm.context().meter_running);
+}
@@ -0,0 +1,52 @@
+//! An example of using the event queue to propagate events, which the dominos example does with
+//! state data
+
+#![deny(missing_docs)]
+
+use smlang::{statemachine, EventSender};
+
+statemachine! {
+    event_queue: true,
+    transitions: {
+        *D0 +  ToD1 / to_d2  = D1,
+        D1 +  ToD2 / to_d3  = D2,
+        D2 +  ToD3 / to_d4  = D3,
+        D3 +  ToD4 / to_d5  = D4,
+        D4 +  ToD5  = D5,
+    }
+}
+
+/// Context
+pub struct Context;
+
+impl StateMachineContext for Context {
+    fn to_d2(&mut self, queue: &mut EventSender<'_, Events>) {
+        queue.post(Events::ToD2).ok();
+    }
+
+    fn to_d3(&mut self, queue: &mut EventSender<'_, Events>) {
+        queue.post(Events::ToD3).ok();
+    }
+
+    fn to_d4(&mut self, queue: &mut EventSender<'_, Events>) {
+        queue.post(Events::ToD4).ok();
+    }
+
+    fn to_d5(&mut self, queue: &mut EventSender<'_, Events>) {
+        queue.post(Events::ToD5).ok();
+    }
+}
+
+fn main() {
+    // Each action posts one event at a time, so a queue holding a single event is enough
+    let mut sm: StateMachine<Context, 1> = StateMachine::new(Context);
+
+    // The first event starts the dominos, and the posted events are processed before
+    // `process_event` returns
+    let result = sm.process_event(Events::ToD1).unwrap();
+    assert_eq!(result.processed, 5);
+    assert!(!result.overflowed);
+
+    // All the dominos fell!
+    assert!(matches!(sm.state(), Ok(&States::D5)));
+}
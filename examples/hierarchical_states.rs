@@ -0,0 +1,64 @@
+//! Hierarchical states example
+//!
+//! An example of grouping states into a composite state, so that transitions shared by all of
+//! them only have to be written once.
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug = Session,
+        // Handled the same way in every substate of `Session`
+        Session + EmergencyStop = Fault,
+        Session + Unplug = Idle,
+        Authorizing + Authorized = Charging,
+        Charging + Pause = Paused,
+        Paused + Resume = Charging,
+        Fault + Reset = Idle,
+    },
+    states: {
+        Session: {
+            entry: lock_cable,
+            exit: unlock_cable,
+            substates: { *Authorizing, Charging, Paused },
+        },
+    }
+}
+
+/// Context
+pub struct Context {
+    /// Whether the cable is locked
+    pub cable_locked: bool,
+}
+
+impl StateMachineContext for Context {
+    fn lock_cable(&mut self) {
+        self.cable_locked = true;
+    }
+
+    fn unlock_cable(&mut self) {
+        self.cable_locked = false;
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context {
+        cable_locked: false,
+    });
+
+    // Entering `Session` enters its initial substate
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Authorizing)));
+    assert!(sm.state().unwrap().is_in(SuperStates::Session));
+    assert!(sm.context().cable_locked);
+
+    sm.process_event(Events::Authorized).unwrap();
+    sm.process_event(Events::Pause).unwrap();
+
+    // `Paused` does not handle `EmergencyStop` itself, so the transition of `Session` is taken
+    sm.process_event(Events::EmergencyStop).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Fault)));
+    assert!(!sm.context().cable_locked);
+}
@@ -0,0 +1,61 @@
+//! Orthogonal regions example
+//!
+//! An example of a device with independent concerns, each tracked in its own region of a single
+//! state machine and reacting to the same events.
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+statemachine! {
+    derive_states: [Debug],
+    // Events handled by several regions are cloned for each of them
+    derive_events: [Clone, Debug],
+    regions: {
+        Connectivity: {
+            transitions: {
+                *Offline + Connect = Online,
+                Online + Disconnect = Offline,
+                _ + PowerLoss = Offline,
+            },
+        },
+        ChargePoint: {
+            transitions: {
+                *Available + Plug = Charging,
+                Charging + Unplug = Available,
+                Charging + PowerLoss / stop_session = Faulted,
+                Faulted + Repaired = Available,
+            },
+        },
+    }
+}
+
+/// Context
+pub struct Context;
+
+impl StateMachineContext for Context {
+    fn stop_session(&mut self) {
+        println!("Session stopped");
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context);
+    let state = sm.state().unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Available));
+
+    // Only the regions handling an event react to it
+    sm.process_event(Events::Connect).unwrap();
+    let state = sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Online));
+    assert!(matches!(state.charge_point, &ChargePointStates::Charging));
+
+    // Both regions react to a power loss
+    let state = sm.process_event(Events::PowerLoss).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Faulted));
+
+    // The event fails when no region has a transition for it
+    assert!(sm.process_event(Events::Unplug).is_err());
+}
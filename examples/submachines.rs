@@ -0,0 +1,75 @@
+//! Submachines example
+//!
+//! An example of a state holding another state machine as its data, which the events of that
+//! state machine are forwarded to until it reaches a final state.
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+
+statemachine! {
+    name: Auth,
+    transitions: {
+        *WaitingForCard + CardPresented(u32) [known_card] = Accepted!,
+        WaitingForCard + CardPresented(u32) = Rejected!,
+    }
+}
+
+/// Context of the authorization submachine
+pub struct AuthContext;
+
+impl AuthStateMachineContext for AuthContext {
+    fn known_card(&mut self, card: &u32) -> Result<(), ()> {
+        if *card == 42 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+}
+
+statemachine! {
+    transitions: {
+        *Idle + Plug / start_authorization = Authorizing(AuthStateMachine<AuthContext>),
+        // Taken once the submachine has reached a final state
+        Authorizing(AuthStateMachine<AuthContext>) [authorized] = Charging,
+        Authorizing(AuthStateMachine<AuthContext>) = Idle,
+        Authorizing(AuthStateMachine<AuthContext>) + Unplug = Idle,
+        Charging + Unplug = Idle,
+    },
+    states: {
+        Authorizing: { submachine: Auth },
+    }
+}
+
+/// Context
+pub struct Context;
+
+impl StateMachineContext for Context {
+    fn start_authorization(&mut self) -> AuthStateMachine<AuthContext> {
+        AuthStateMachine::new(AuthContext)
+    }
+
+    fn authorized(&mut self, auth: &AuthStateMachine<AuthContext>) -> Result<(), ()> {
+        match auth.state() {
+            Ok(AuthStates::Accepted) => Ok(()),
+            _ => Err(()),
+        }
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Plug).unwrap();
+
+    // The events of the submachine are wrapped in an event named after it
+    sm.process_event(Events::Auth(AuthEvents::CardPresented(42)))
+        .unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Charging)));
+
+    sm.process_event(Events::Unplug).unwrap();
+    sm.process_event(Events::Plug).unwrap();
+    sm.process_event(Events::Auth(AuthEvents::CardPresented(7)))
+        .unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Idle)));
+}
@@ -0,0 +1,53 @@
+//! An example of timed transitions, driven by the system clock
+
+#![deny(missing_docs)]
+
+use smlang::statemachine;
+use std::time::{Duration, Instant};
+
+statemachine! {
+    clock: SystemClock,
+    transitions: {
+        *Idle + Reserve = WaitingForCar,
+        WaitingForCar + Plug = Charging,
+        WaitingForCar after(Duration::from_millis(50)) / cancel_reservation = Idle,
+    },
+}
+
+/// A clock reading the system time
+pub struct SystemClock;
+
+impl smlang::Clock for SystemClock {
+    type Instant = Instant;
+    type Duration = Duration;
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Context
+pub struct Context;
+
+impl StateMachineContext for Context {
+    fn clock(&self) -> &SystemClock {
+        &SystemClock
+    }
+
+    fn cancel_reservation(&mut self) {
+        println!("Reservation expired");
+    }
+}
+
+fn main() {
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Reserve).unwrap();
+
+    // Sleep until the reservation expires, as a timer interrupt would
+    while let Some(deadline) = sm.next_deadline() {
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        sm.tick(Instant::now()).unwrap();
+    }
+
+    assert!(matches!(sm.state(), Ok(&States::Idle)));
+}
@@ -1,211 +1,489 @@
 // Move guards to return a Result
 
-use crate::parser::{lifetimes::Lifetimes, AsyncIdent, ParsedStateMachine};
+use crate::parser::{
+    event::EventMapping, guard::GuardExpression, lifetimes::Lifetimes, output_state::History,
+    AsyncIdent, ParsedStateMachine,
+};
 use proc_macro2::Span;
 use quote::{format_ident, quote};
+use std::collections::HashMap;
 use syn::{punctuated::Punctuated, token::Paren, Type, TypeTuple};
 
-pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
-    let (sm_name, sm_name_span) = sm
-        .name
-        .as_ref()
-        .map(|name| (name.to_string(), name.span()))
-        .unwrap_or_else(|| (String::new(), Span::call_site()));
-    let states_type_name = format_ident!("{sm_name}States", span = sm_name_span);
-    let events_type_name = format_ident!("{sm_name}Events", span = sm_name_span);
-    let error_type_name = format_ident!("{sm_name}Error", span = sm_name_span);
-    let state_machine_type_name = format_ident!("{sm_name}StateMachine", span = sm_name_span);
-    let state_machine_context_type_name =
-        format_ident!("{sm_name}StateMachineContext", span = sm_name_span);
-
-    // Get only the unique states
-    let mut state_list: Vec<_> = sm.states.values().collect();
-    state_list.sort_by_key(|state| state.to_string());
-
-    let state_list: Vec<_> = state_list
-        .iter()
-        .map(
-            |value| match sm.state_data.data_types.get(&value.to_string()) {
-                None => {
-                    quote! {
-                        #value
-                    }
-                }
-                Some(t) => {
-                    quote! {
-                        #value(#t)
-                    }
-                }
-            },
-        )
-        .collect();
+// Names of the generated items holding and reporting the active state of a state machine, or of
+// one of its regions
+struct MachineNames {
+    states_type_name: syn::Ident,
+    super_states_type_name: syn::Ident,
+    state_id_type_name: syn::Ident,
+    state_field: syn::Ident,
+    log_state_change: syn::Ident,
+    history_field: syn::Ident,
+    process_completion: syn::Ident,
+    process_timed: syn::Ident,
+    start_timers: syn::Ident,
+    // What the processing code returns once a transition has been taken
+    transition_result: proc_macro2::TokenStream,
+    // What the processing code returns for an event the active state does not handle
+    unhandled_result: proc_macro2::TokenStream,
+}
 
-    // Extract events
-    let mut event_list: Vec<_> = sm.events.values().collect();
-    event_list.sort_by_key(|event| event.to_string());
+// What the transitions tried together are taken on
+#[derive(Clone, Copy)]
+enum Trigger<'a> {
+    Event(&'a String),
+    // Entering the input state
+    Completion,
+    // The input state having been active for the duration of the transition
+    Timeout,
+}
 
-    // Extract events
-    let event_list: Vec<_> = event_list
-        .iter()
-        .map(
-            |value| match sm.event_data.data_types.get(&value.to_string()) {
-                None => {
-                    quote! {
-                        #value
-                    }
+// helper function for generating the code evaluating a guard expression with short-circuiting,
+// which evaluates to the name of the guard deciding the result, along with its error if it failed
+fn generate_guard_expression(
+    expression: &GuardExpression,
+    call: &dyn Fn(&AsyncIdent) -> proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    match expression {
+        GuardExpression::Guard(guard) => {
+            let ident = &guard.ident;
+            let call = call(guard);
+            quote! {
+                {
+                    let guard_result = #call;
+                    self.context.log_guard(stringify!(#ident), &guard_result);
+                    guard_result
+                        .map(|()| stringify!(#ident))
+                        .map_err(|e| (stringify!(#ident), Some(e)))
                 }
-                Some(t) => {
-                    quote! {
-                        #value(#t)
-                    }
+            }
+        }
+        GuardExpression::Not(expression) => {
+            let expression = generate_guard_expression(expression, call);
+            quote! {
+                match #expression {
+                    Ok(guard) => Err((guard, None)),
+                    Err((guard, _)) => Ok(guard),
                 }
-            },
-        )
-        .collect();
+            }
+        }
+        GuardExpression::And(left, right) => {
+            let left = generate_guard_expression(left, call);
+            let right = generate_guard_expression(right, call);
+            quote! {
+                match #left {
+                    Ok(_) => #right,
+                    Err(e) => Err(e),
+                }
+            }
+        }
+        GuardExpression::Or(left, right) => {
+            let left = generate_guard_expression(left, call);
+            let right = generate_guard_expression(right, call);
+            quote! {
+                match #left {
+                    Ok(guard) => Ok(guard),
+                    Err(_) => #right,
+                }
+            }
+        }
+    }
+}
 
-    let transitions = &sm.states_events_mapping;
+// helper function for documenting the events accepted in a state and the states they lead to,
+// such as "- `Plug` `[is_authorized]` → `Charging`"
+fn accepted_events_doc(machine: &ParsedStateMachine, state: &syn::Ident) -> Vec<String> {
+    let state_name = state.to_string();
+    let mut lines = Vec::new();
+
+    if let Some(mappings) = machine.states_events_mapping.get(&state_name) {
+        // The wildcard event takes the remaining events, so it is listed last
+        let mut events: Vec<_> = mappings.iter().collect();
+        events.sort_by_key(|(event, _)| (*event == "_", event.to_string()));
+
+        for (event, mappings) in events {
+            let event = match event.as_str() {
+                "_" => "Any other event".to_string(),
+                event => format!("`{}`", event),
+            };
+
+            for mapping in mappings {
+                let guard = mapping
+                    .guard
+                    .as_ref()
+                    .map(|guard| format!(" `[{}]`", guard))
+                    .unwrap_or_default();
+                let target = if mapping.internal {
+                    "stays in the state".to_string()
+                } else if !mapping.targets.is_empty() {
+                    mapping
+                        .targets
+                        .iter()
+                        .map(|target| format!("`{}`", target))
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                } else {
+                    match mapping.history {
+                        Some(History::Shallow) => format!("`{}.H`", mapping.out_state),
+                        Some(History::Deep) => format!("`{}.H*`", mapping.out_state),
+                        None => format!("`{}`", mapping.out_state),
+                    }
+                };
 
-    let in_states: Vec<_> = transitions
-        .iter()
-        .map(|(name, _)| {
-            let state_name = sm.states.get(name).unwrap();
+                lines.push(format!(" - {}{} → {}", event, guard, target));
+            }
+        }
+    }
 
-            match sm.state_data.data_types.get(name) {
-                None => {
-                    quote! {
-                        #state_name
-                    }
-                }
-                Some(_) => {
-                    quote! {
-                        #state_name(state_data)
-                    }
+    if let Some(submachine) = machine.submachines.get(&state_name) {
+        lines.push(format!(
+            " - `{}` is forwarded to the submachine",
+            submachine.ident
+        ));
+    }
+
+    for event in machine
+        .deferred_events
+        .get(&state_name)
+        .into_iter()
+        .flatten()
+    {
+        lines.push(format!(" - `{}` is deferred", event));
+    }
+
+    if !lines.is_empty() {
+        lines.splice(0..0, [" Accepted events:".to_string(), String::new()]);
+    }
+
+    lines
+}
+
+// helper function for naming the field and functions belonging to a region
+fn snake_case(ident: &syn::Ident) -> String {
+    let mut name = String::new();
+
+    for (index, c) in ident.to_string().chars().enumerate() {
+        if c.is_uppercase() {
+            if index > 0 {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        } else {
+            name.push(c);
+        }
+    }
+
+    name
+}
+
+// helper function for generating the enum of the ids of the states or events, which have no data
+// so they can be stored, sent or used as keys, along with the names of the ids
+fn generate_ids(
+    visibility: &syn::Visibility,
+    id_type_name: &syn::Ident,
+    names: &[&syn::Ident],
+    docs: &HashMap<String, Vec<syn::Attribute>>,
+    kind: &str,
+) -> proc_macro2::TokenStream {
+    let type_doc = format!(
+        "List of auto-generated {} ids, the {}s without their data.",
+        kind, kind
+    );
+    let name_doc = format!("Returns the name of the {}.", kind);
+    let len = names.len();
+    let variants = names.iter().enumerate().map(|(index, name)| {
+        let docs = docs.get(&name.to_string()).into_iter().flatten();
+        let index = proc_macro2::Literal::usize_unsuffixed(index);
+        quote! {
+            #(#docs)*
+            #name = #index
+        }
+    });
+    let name_strings = names.iter().map(|name| name.to_string());
+
+    quote! {
+        #[doc = #type_doc]
+        #[allow(missing_docs)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[repr(u8)]
+        #visibility enum #id_type_name { #(#variants),* }
+
+        impl #id_type_name {
+            /// All ids, ordered by their values.
+            pub const ALL: [#id_type_name; #len] = [#(#id_type_name::#names),*];
+
+            #[doc = #name_doc]
+            pub const fn name(self) -> &'static str {
+                match self {
+                    #(#id_type_name::#names => #name_strings),*
                 }
             }
-        })
-        .collect();
+        }
 
-    let events: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(_, value)| {
-            value
-                .iter()
-                .map(|(name, value)| {
-                    let value = &value.event;
+        impl core::convert::TryFrom<u8> for #id_type_name {
+            type Error = u8;
 
-                    match sm.event_data.data_types.get(name) {
-                        None => {
-                            quote! {
-                                #value
-                            }
-                        }
-                        Some(_) => {
-                            quote! {
-                                #value(event_data)
-                            }
-                        }
-                    }
-                })
-                .collect()
-        })
-        .collect();
+            /// Returns the id with the value, or the value if there is none.
+            fn try_from(value: u8) -> Result<Self, u8> {
+                Self::ALL.get(value as usize).copied().ok_or(value)
+            }
+        }
+    }
+}
 
-    // println!("sm: {:#?}", sm);
-    // println!("in_states: {:#?}", in_states);
-    // println!("events: {:#?}", events);
-    // println!("transitions: {:#?}", transitions);
+// helper function for getting the states a transition of `state` can lead to, which are the states
+// which can be resumed for a history target, and each of the targets chosen by an action
+fn transition_targets(
+    machine: &ParsedStateMachine,
+    state: &syn::Ident,
+    mapping: &EventMapping,
+) -> Vec<syn::Ident> {
+    if mapping.internal {
+        return vec![state.clone()];
+    }
 
-    // Map guards, actions and output states into code blocks
-    let guards: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(_, value)| value.iter().map(|(_, value)| &value.guard).collect())
-        .collect();
+    let mut targets = match mapping.history {
+        Some(history) => machine
+            .states
+            .values()
+            .filter(|state| machine.ancestors(state).contains(&mapping.out_state))
+            .map(|recorded_state| match history {
+                History::Deep => recorded_state.clone(),
+                History::Shallow => {
+                    let resumed_state = std::iter::once(recorded_state.clone())
+                        .chain(machine.ancestors(recorded_state))
+                        .find(|state| {
+                            machine.parents.get(&state.to_string()) == Some(&mapping.out_state)
+                        })
+                        .unwrap();
+                    machine.initial_leaf(&resumed_state)
+                }
+            })
+            .chain(std::iter::once(machine.initial_leaf(&mapping.out_state)))
+            .collect(),
+        None if mapping.targets.is_empty() => vec![machine.initial_leaf(&mapping.out_state)],
+        None => mapping
+            .targets
+            .iter()
+            .map(|target| machine.initial_leaf(target))
+            .collect(),
+    };
+    targets.sort_by_key(|target| target.to_string());
+    targets.dedup();
 
-    let actions: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(_, value)| value.iter().map(|(_, value)| &value.action).collect())
-        .collect();
+    targets
+}
 
-    let guard_action_parameters: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(name, value)| {
-            let state_name = &sm.states.get(name).unwrap().to_string();
+// helper function for generating the table of the transitions taken on events, along with the
+// events accepted by each state and the states they lead to. The items of a region are prefixed
+// with its name.
+fn generate_transition_table(
+    machine: &ParsedStateMachine,
+    state_id_type_name: &syn::Ident,
+    event_id_type_name: &syn::Ident,
+    prefix: &str,
+) -> proc_macro2::TokenStream {
+    let transitions_name = format_ident!("{}TRANSITIONS", prefix.to_uppercase());
+    let accepted_events_name = format_ident!("{}accepted_events", prefix);
+    let successors_name = format_ident!("{}successors", prefix);
+
+    let mut states: Vec<_> = machine.states.iter().collect();
+    states.sort_by_key(|(name, _)| name.to_string());
+
+    let mut entries = Vec::new();
+    let mut accepted_events = Vec::new();
+    let mut successors = Vec::new();
+    for (name, state) in states.iter() {
+        let mut state_entries: Vec<(&syn::Ident, &EventMapping, bool)> = Vec::new();
+
+        // Final states do not accept any event, not even the ones of their composite states
+        if !machine.final_states.contains(state) {
+            let mappings = &machine.states_events_mapping[*name];
+            let mut events: Vec<_> = mappings.keys().filter(|event| *event != "_").collect();
+            events.sort();
+            for event in events {
+                state_entries.extend(
+                    mappings[event]
+                        .iter()
+                        .map(|mapping| (&machine.events[event], mapping, mapping.wildcard)),
+                );
+            }
+
+            // The wildcard event is listed as each of the events it takes, which are the ones
+            // that are not handled, forwarded to a submachine or deferred otherwise
+            if let Some(wildcard_mappings) = mappings.get("_") {
+                let submachine = machine
+                    .submachines
+                    .get(*name)
+                    .map(|submachine| submachine.ident.to_string());
+                let deferred = machine.deferred_events.get(*name);
+
+                let mut events: Vec<_> = machine
+                    .events
+                    .keys()
+                    .filter(|event| !mappings.contains_key(*event))
+                    .filter(|event| submachine.as_ref() != Some(*event))
+                    .filter(|event| {
+                        deferred
+                            .into_iter()
+                            .flatten()
+                            .all(|deferred| deferred != event.as_str())
+                    })
+                    .collect();
+                events.sort();
+                for event in events {
+                    state_entries.extend(
+                        wildcard_mappings
+                            .iter()
+                            .map(|mapping| (&machine.events[event], mapping, true)),
+                    );
+                }
+            }
+        }
 
-            value
+        let mut state_events = Vec::new();
+        let mut state_successors = Vec::new();
+        for (event, mapping, wildcard) in state_entries {
+            let guard = match &mapping.guard {
+                Some(guard) => {
+                    let guard = guard.to_string();
+                    quote! { Some(#guard) }
+                }
+                None => quote! { None },
+            };
+            let actions: Vec<_> = mapping
+                .actions
                 .iter()
-                .map(|(name, _)| {
-                    let state_data = match sm.state_data.data_types.get(state_name) {
-                        Some(_) => quote! { state_data },
-                        None => quote! {},
-                    };
+                .map(|action| action.ident.to_string())
+                .collect();
+            let action = match actions.len() {
+                0 => quote! { None },
+                1 => {
+                    let action = &actions[0];
+                    quote! { Some(#action) }
+                }
+                _ => {
+                    let action = format!("({})", actions.join(", "));
+                    quote! { Some(#action) }
+                }
+            };
+
+            for target in transition_targets(machine, state, mapping) {
+                entries.push(quote! {
+                    smlang::TransitionInfo {
+                        source: #state_id_type_name::#state,
+                        event: #event_id_type_name::#event,
+                        guard: #guard,
+                        action: #action,
+                        target: #state_id_type_name::#target,
+                        wildcard: #wildcard,
+                    }
+                });
+                state_successors.push(target);
+            }
+            state_events.push(event.clone());
+        }
 
-                    let event_data = match sm.event_data.data_types.get(name) {
-                        Some(_) => quote! { event_data },
-                        None => quote! {},
-                    };
+        state_events.sort_by_key(|event| event.to_string());
+        state_events.dedup();
+        state_successors.sort_by_key(|state| state.to_string());
+        state_successors.dedup();
+        accepted_events.push(state_events);
+        successors.push(state_successors);
+    }
 
-                    if state_data.is_empty() || event_data.is_empty() {
-                        quote! { #state_data #event_data }
-                    } else {
-                        quote! { #state_data, #event_data }
-                    }
-                })
-                .collect()
-        })
-        .collect();
+    let states = states.iter().map(|(_, state)| state);
+    let states_copy = states.clone();
 
-    let guard_action_ref_parameters: Vec<Vec<_>> = transitions
-        .iter()
-        .map(|(name, value)| {
-            let state_name = &sm.states.get(name).unwrap().to_string();
+    quote! {
+        /// The transitions taken on events, ordered by their states and events. Completion and
+        /// timed transitions are not listed, as they are not taken on events.
+        pub const #transitions_name: &'static [smlang::TransitionInfo<#state_id_type_name, #event_id_type_name>] = &[
+            #(#entries),*
+        ];
+
+        /// Returns the events which have a transition in the state, ordered by their ids.
+        pub const fn #accepted_events_name(state: #state_id_type_name) -> &'static [#event_id_type_name] {
+            match state {
+                #(#state_id_type_name::#states => &[#(#event_id_type_name::#accepted_events),*]),*
+            }
+        }
 
-            value
-                .iter()
-                .map(|(name, _)| {
-                    let state_data = match sm.state_data.data_types.get(state_name) {
-                        Some(Type::Reference(_)) => quote! { state_data },
-                        Some(_) => quote! { &state_data },
-                        None => quote! {},
-                    };
+        /// Returns the states which the transitions of the state lead to, ordered by their ids.
+        pub const fn #successors_name(state: #state_id_type_name) -> &'static [#state_id_type_name] {
+            match state {
+                #(#state_id_type_name::#states_copy => &[#(#state_id_type_name::#successors),*]),*
+            }
+        }
+    }
+}
 
-                    let event_data = match sm.event_data.data_types.get(name) {
-                        Some(Type::Reference(_)) => quote! { event_data },
-                        Some(_) => quote! { &event_data },
-                        None => quote! {},
-                    };
+pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
+    let (sm_name, sm_name_span) = sm
+        .name
+        .as_ref()
+        .map(|name| (name.to_string(), name.span()))
+        .unwrap_or_else(|| (String::new(), Span::call_site()));
+    let states_type_name = sm.states_type_name();
+    let events_type_name = sm.events_type_name();
+    let error_type_name = sm.error_type_name();
+    let state_machine_type_name = sm.state_machine_type_name();
+    let state_machine_context_type_name = sm.state_machine_context_type_name();
+    let visibility = &sm.visibility;
+
+    // The context and the state machine take all generic parameters, while the states and events
+    // only take the ones used by their data
+    let generics = &sm.generics;
+    let generic_args = generics.args();
+    let state_generic_args = generics.used_by(sm.state_data.data_types.values()).args();
+    let event_generics = generics.used_by(sm.event_data.data_types.values());
+    let event_generic_args = event_generics.args();
+
+    // The type parameter of the context of the state machine, named so it does not clash with the
+    // generic parameters
+    let context_param = generics.unused_ident("T");
 
-                    if state_data.is_empty() || event_data.is_empty() {
-                        quote! { #state_data #event_data }
-                    } else {
-                        quote! { #state_data, #event_data }
-                    }
-                })
-                .collect()
-        })
-        .collect();
+    // Extract events
+    let mut event_list: Vec<_> = sm.events.values().collect();
+    event_list.sort_by_key(|event| event.to_string());
 
-    let out_states: Vec<Vec<_>> = transitions
+    // The ids of the events are ordered like the events
+    let event_id_type_name = sm.event_id_type_name();
+    let event_ids_code = generate_ids(
+        visibility,
+        &event_id_type_name,
+        &event_list,
+        &sm.event_docs,
+        "event",
+    );
+    let event_id_patterns: Vec<_> = event_list
         .iter()
-        .map(|(_, value)| {
-            value
-                .iter()
-                .map(|(_, value)| {
-                    let out_state = &value.out_state;
+        .map(
+            |event| match sm.event_data.data_types.get(&event.to_string()) {
+                None => quote! { #events_type_name::#event },
+                Some(_) => quote! { #events_type_name::#event(..) },
+            },
+        )
+        .collect();
 
-                    match sm.state_data.data_types.get(&out_state.to_string()) {
-                        None => {
-                            quote! {
-                                #out_state
-                            }
-                        }
-                        Some(_) => {
-                            quote! {
-                                #out_state(_data)
-                            }
-                        }
+    // Extract events
+    let event_variants: Vec<_> = event_list
+        .iter()
+        .map(|value| {
+            let docs = sm.event_docs.get(&value.to_string()).into_iter().flatten();
+            match sm.event_data.data_types.get(&value.to_string()) {
+                None => {
+                    quote! {
+                        #(#docs)*
+                        #value
                     }
-                })
-                .collect()
+                }
+                Some(t) => {
+                    quote! {
+                        #(#docs)*
+                        #value(#t)
+                    }
+                }
+            }
         })
         .collect();
 
@@ -218,39 +496,152 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
         }
     };
 
+    // Actions of a state machine with an event queue get a handle to post events to it
+    let (event_sender, event_sender_call) = if sm.event_queue {
+        (
+            quote! { queue: &mut smlang::EventSender<'_, #events_type_name <#event_generic_args>>, },
+            quote! { &mut smlang::EventSender::new(&mut self.event_queue, &mut self.event_queue_overflowed), },
+        )
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // The lifetimes of the data of the targets an action may choose from
+    let target_lifetimes = |mapping: &EventMapping| {
+        let mut lifetimes = Lifetimes::new();
+        for target in mapping.targets.iter() {
+            let target = sm.initial_leaf(target).to_string();
+            if let Some(target_lifetimes) = sm.state_data.lifetimes.get(&target) {
+                lifetimes.extend(target_lifetimes);
+            }
+        }
+        lifetimes
+    };
+
+    // The generic parameters of the data of the targets an action may choose from
+    let target_generics = |mapping: &EventMapping| {
+        generics.used_by(mapping.targets.iter().filter_map(|target| {
+            sm.state_data
+                .data_types
+                .get(&sm.initial_leaf(target).to_string())
+        }))
+    };
+
+    // Create the enums returned by the actions choosing the targets of their transitions, with a
+    // variant for every target
+    let mut target_set: Vec<syn::Ident> = Vec::new();
+    let mut target_list = proc_macro2::TokenStream::new();
+    let mut target_mappings: Vec<_> = sm
+        .all_transitions()
+        .filter(|mapping| !mapping.targets.is_empty())
+        .collect();
+    target_mappings.sort_by_key(|mapping| mapping.actions.last().unwrap().ident.to_string());
+    for mapping in target_mappings {
+        let action = &mapping.actions.last().unwrap().ident;
+        if target_set.contains(action) {
+            continue;
+        }
+        target_set.push(action.clone());
+
+        let target_type_name = sm.target_type_name(action);
+        let lifetimes = target_lifetimes(mapping);
+        let target_generics = target_generics(mapping);
+        let variants = mapping.targets.iter().map(|target| {
+            match sm
+                .state_data
+                .data_types
+                .get(&sm.initial_leaf(target).to_string())
+            {
+                None => quote! { #target },
+                Some(data_type) => quote! { #target(#data_type) },
+            }
+        });
+        let doc = format!("The targets the `{}` action chooses from.", action);
+
+        target_list.extend(quote! {
+            #[doc = #doc]
+            #[allow(missing_docs)]
+            #visibility enum #target_type_name <#lifetimes #target_generics> { #(#variants),* }
+        });
+    }
+
+    // The doc comments of the transitions using a guard or action, which are all added to it as the
+    // transitions sharing it may be documented differently
+    let function_docs = |function: &syn::Ident| {
+        let mut docs: Vec<_> = sm
+            .all_transitions()
+            .filter(|mapping| {
+                mapping
+                    .actions
+                    .iter()
+                    .any(|action| &action.ident == function)
+                    || mapping
+                        .guard
+                        .iter()
+                        .flat_map(|guard| guard.guards())
+                        .any(|guard| &guard.ident == function)
+            })
+            .map(|mapping| &mapping.docs)
+            .filter(|docs| !docs.is_empty())
+            .map(|docs| quote! { #(#docs)* })
+            .collect();
+        docs.sort_by_key(|docs| docs.to_string());
+        docs.dedup_by_key(|docs| docs.to_string());
+
+        let mut tokens = proc_macro2::TokenStream::new();
+        for (index, docs) in docs.into_iter().enumerate() {
+            if index > 0 {
+                tokens.extend(quote! { #[doc = ""] });
+            }
+            tokens.extend(docs);
+        }
+        tokens
+    };
+
     // Keep track of already added actions not to duplicate definitions
     let mut action_set: Vec<syn::Ident> = Vec::new();
     let mut guard_set: Vec<syn::Ident> = Vec::new();
 
     let mut guard_list = proc_macro2::TokenStream::new();
     let mut action_list = proc_macro2::TokenStream::new();
-    for (state, value) in transitions.iter() {
-        // create the state data token stream
-        let state_data = match sm.state_data.data_types.get(state) {
-            Some(st @ Type::Reference(_)) => quote! { state_data: #st, },
-            Some(st) => quote! { state_data: &#st, },
-            None => quote! {},
-        };
+    sm.all_transitions().for_each(|value| {
+            // Completion transitions do not have an event
+            let event = value.event.as_ref().map(|event| event.to_string());
+
+            // Transitions inherited from a composite state do not have access to the data of
+            // the active substate
+            let state = &value.in_state.to_string();
+            let out_state = &sm.initial_leaf(&value.out_state).to_string();
+
+            // create the state data token stream
+            let state_data = match sm.state_data.data_types.get(state) {
+                Some(st @ Type::Reference(_)) => quote! { state_data: #st, },
+                Some(st) => quote! { state_data: &#st, },
+                None => quote! {},
+            };
 
-        value.iter().for_each(|(event, value)| {
             // get input state lifetimes
-            let in_state_lifetimes = sm.state_data.lifetimes.get(&value.in_state.to_string()).cloned().unwrap_or_default();
+            let in_state_lifetimes = sm.state_data.lifetimes.get(state).cloned().unwrap_or_default();
 
             // get output state lifetimes
-            let out_state_lifetimes = sm.state_data.lifetimes.get(&value.out_state.to_string()).cloned().unwrap_or_default();
+            let out_state_lifetimes = sm.state_data.lifetimes.get(out_state).cloned().unwrap_or_default();
 
             // get event lifetimes
-            let event_lifetimes = sm.event_data.lifetimes.get(event).cloned().unwrap_or_default();
+            let event_lifetimes = event.as_ref().and_then(|event| sm.event_data.lifetimes.get(event)).cloned().unwrap_or_default();
 
-            // combine all lifetimes
+            // combine all lifetimes, including the ones of every target an action may choose
             let mut all_lifetimes = Lifetimes::new();
             all_lifetimes.extend(&in_state_lifetimes);
             all_lifetimes.extend(&out_state_lifetimes);
             all_lifetimes.extend(&event_lifetimes);
-
-            // Create the guard traits for user implementation
-            if let Some(AsyncIdent {ident: guard, is_async}) = &value.guard {
-                let event_data = match sm.event_data.data_types.get(event) {
+            let target_lifetimes = target_lifetimes(value);
+            all_lifetimes.extend(&target_lifetimes);
+            let target_generic_args = target_generics(value).args();
+
+            // Create the guard traits for user implementation, once for every guard of an
+            // expression
+            for AsyncIdent {ident: guard, is_async} in value.guard.iter().flat_map(|guard| guard.guards()) {
+                let event_data = match event.as_ref().and_then(|event| sm.event_data.data_types.get(event)) {
                     Some(et @ Type::Reference(_)) => quote! { event_data: #et },
                     Some(et) => quote! { event_data: &#et },
                     None => quote! {},
@@ -269,22 +660,32 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
                         true => quote!{ async },
                         false => quote!{ },
                     };
+                    let docs = function_docs(guard);
                     guard_list.extend(quote! {
+                        #docs
                         #[allow(missing_docs)]
                         #is_async fn #guard <#all_lifetimes> (&mut self, #temporary_context #state_data #event_data) -> Result<(), #guard_error>;
                     });
                 }
             }
 
-            // Create the action traits for user implementation
-            if let Some(AsyncIdent {ident: action, is_async}) = &value.action {
+            // Create the action traits for user implementation, where only the last action of a
+            // chain produces the data of the output state and the others, like the actions run
+            // before the guards of a choice, borrow the data
+            let last_action = value.actions.len().saturating_sub(1);
+            for (index, AsyncIdent {ident: action, is_async}) in value.actions.iter().enumerate() {
                 let is_async = match is_async {
                     true => quote!{ async },
                     false => quote!{ },
                 };
-
-                let return_type = if let Some(output_data) =
-                    sm.state_data.data_types.get(&value.out_state.to_string())
+                let chained = index < last_action || index < value.choice_actions;
+
+                let return_type = if !value.targets.is_empty() && !chained {
+                    // The last action chooses the target of the transition
+                    let target_type_name = sm.target_type_name(action);
+                    syn::parse_quote! { #target_type_name <#target_lifetimes #target_generic_args> }
+                } else if let (Some(output_data), false, false) =
+                    (sm.state_data.data_types.get(out_state), value.internal, chained)
                 {
                     output_data.clone()
                 } else {
@@ -297,7 +698,17 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
                     })
                 };
 
+                // Internal transitions keep the state data, and may update it
                 let state_data = match sm.state_data.data_types.get(state) {
+                    Some(st) if value.internal => {
+                        quote! { state_data: &mut #st, }
+                    }
+                    Some(st @ Type::Reference(_)) if chained => {
+                        quote! { state_data: #st, }
+                    }
+                    Some(st) if chained => {
+                        quote! { state_data: &#st, }
+                    }
                     Some(st) => {
                         quote! { state_data: #st, }
                     }
@@ -305,7 +716,13 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
                         quote! {}
                     }
                 };
-                let event_data = match sm.event_data.data_types.get(event) {
+                let event_data = match event.as_ref().and_then(|event| sm.event_data.data_types.get(event)) {
+                    Some(et @ Type::Reference(_)) if chained => {
+                        quote! { event_data: #et }
+                    }
+                    Some(et) if chained => {
+                        quote! { event_data: &#et }
+                    }
                     Some(et) => {
                         quote! { event_data: #et }
                     }
@@ -317,14 +734,15 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
                 // Only add the action if it hasn't been added before
                 if !action_set.iter().any(|a| a == action) {
                     action_set.push(action.clone());
+                    let docs = function_docs(action);
                     action_list.extend(quote! {
+                        #docs
                         #[allow(missing_docs)]
-                        #is_async fn #action <#all_lifetimes> (&mut self, #temporary_context #state_data #event_data) -> #return_type;
+                        #is_async fn #action <#all_lifetimes> (&mut self, #temporary_context #event_sender #state_data #event_data) -> #return_type;
                     });
                 }
             }
-        })
-    }
+    });
 
     let temporary_context_call = match &sm.temporary_context_type {
         Some(_) => {
@@ -335,162 +753,2366 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
         }
     };
 
-    let mut sm_is_async = false;
+    // The state machine needs to be async as soon as one of its guards or actions is
+    let process_await = if sm.is_async() {
+        quote! { .await }
+    } else {
+        quote! {}
+    };
+
+    // Guard expressions report which of their guards rejected a transition
+    let has_guard_expressions = sm
+        .all_transitions()
+        .any(|mapping| matches!(&mapping.guard, Some(guard) if !matches!(guard, GuardExpression::Guard(_))));
+    let guard_expression_error = if sm.custom_guard_error {
+        quote! { <#context_param as #state_machine_context_type_name <#generic_args>>::GuardError }
+    } else {
+        quote! { () }
+    };
+    let guard_failed = if has_guard_expressions {
+        quote! { #error_type_name::GuardFailed(_) | #error_type_name::GuardExpressionFailed { .. } }
+    } else {
+        quote! { #error_type_name::GuardFailed(_) }
+    };
+
+    // Events processed after a final state was entered are rejected
+    let terminated = if sm.final_states.is_empty() {
+        quote! {}
+    } else {
+        quote! { | Err(#error_type_name::Terminated) }
+    };
+
+    // Create the entry and exit action traits for user implementation
+    let mut hook_set: Vec<syn::Ident> = Vec::new();
+    let mut hook_list = proc_macro2::TokenStream::new();
 
-    // Create the code blocks inside the switch cases
-    let code_blocks: Vec<Vec<_>> = guards
+    let mut hooks: Vec<_> = sm
+        .entry_actions
         .iter()
-        .zip(
-            actions
-                .iter()
-                .zip(in_states.iter().zip(out_states.iter().zip(guard_action_parameters.iter().zip(guard_action_ref_parameters.iter())))),
-        )
-        .map(
-            |(guards, (actions, (in_state, (out_states, (guard_action_parameters, guard_action_ref_parameters)))))| {
-                guards
-                    .iter()
-                    .zip(
-                        actions
-                            .iter()
-                            .zip(out_states.iter().zip(guard_action_parameters.iter().zip(guard_action_ref_parameters.iter()))),
-                    )
-                    .map(|(guard, (action, (out_state, (g_a_param, g_a_ref_param))))| {
-                        if let Some(AsyncIdent {ident: g, is_async: is_g_async}) = guard {
-                            let guard_await = match is_g_async {
-                                true => { sm_is_async = true; quote! { .await } },
-                                false => quote! { },
-                            };
-                            if let Some(AsyncIdent {ident: a, is_async: is_a_async}) = action {
-                                let action_await = match is_a_async {
-                                    true => { sm_is_async = true; quote! { .await } },
-                                    false => quote! { },
-                                };
-                                quote! {
-                                    let guard_result = self.context.#g(#temporary_context_call #g_a_ref_param) #guard_await;
-                                    self.context.log_guard(stringify!(#g), &guard_result);
-                                    if let Err(e) = guard_result {
-                                        self.state = Some(#states_type_name::#in_state);
-                                        return Err(#error_type_name::GuardFailed(e));
-                                    }
-                                    let _data = self.context.#a(#temporary_context_call #g_a_param) #action_await;
-                                    self.context.log_action(stringify!(#a));
-                                    let out_state = #states_type_name::#out_state;
-                                    self.context.log_state_change(&out_state);
-                                    self.state = Some(out_state);
-                                }
-                            } else {
-                                quote! {
-                                    let guard_result = self.context.#g(#temporary_context_call #g_a_ref_param);
-                                    self.context.log_guard(stringify!(#g), &guard_result);
-                                    if let Err(e) = guard_result {
-                                        self.state = Some(#states_type_name::#in_state);
-                                        return Err(#error_type_name::GuardFailed(e));
-                                    }
-                                    let out_state = #states_type_name::#out_state;
-                                    self.context.log_state_change(&out_state);
-                                    self.state = Some(out_state);
-                                }
-                            }
-                        } else if let Some(AsyncIdent {ident: a, is_async: is_a_async}) = action {
-                            let action_await = match is_a_async {
-                                true => { sm_is_async = true; quote! { .await } },
-                                false => quote! { },
-                            };
-                            quote! {
-                                let _data = self.context.#a(#temporary_context_call #g_a_param) #action_await ;
-                                self.context.log_action(stringify!(#a));
-                                let out_state = #states_type_name::#out_state;
-                                self.context.log_state_change(&out_state);
-                                self.state = Some(out_state);
-                            }
-                        } else {
-                            quote! {
-                                let out_state = #states_type_name::#out_state;
-                                self.context.log_state_change(&out_state);
-                                self.state = Some(out_state);
+        .chain(sm.exit_actions.iter())
+        .collect();
+    hooks.sort_by_key(|(_, hook)| hook.ident.to_string());
+
+    for (
+        state,
+        AsyncIdent {
+            ident: hook,
+            is_async,
+        },
+    ) in hooks
+    {
+        if hook_set.iter().any(|h| h == hook) {
+            continue;
+        }
+        hook_set.push(hook.clone());
+
+        let is_async = match is_async {
+            true => quote! { async },
+            false => quote! {},
+        };
+        let state_data = match sm.state_data.data_types.get(state) {
+            Some(st @ Type::Reference(_)) => quote! { state_data: #st },
+            Some(st) => quote! { state_data: &#st },
+            None => quote! {},
+        };
+        let state_lifetimes = sm
+            .state_data
+            .lifetimes
+            .get(state)
+            .cloned()
+            .unwrap_or_default();
+
+        hook_list.extend(quote! {
+            #[allow(missing_docs)]
+            #is_async fn #hook <#state_lifetimes> (&mut self, #temporary_context #state_data);
+        });
+    }
+
+    // Create the code calling the entry or exit action of a state, if it has one
+    let hook_call = |hooks: &HashMap<String, AsyncIdent>,
+                     state: &syn::Ident,
+                     data: proc_macro2::TokenStream| {
+        match hooks.get(&state.to_string()) {
+            Some(AsyncIdent {
+                ident: hook,
+                is_async,
+            }) => {
+                let hook_await = match is_async {
+                    true => quote! { .await },
+                    false => quote! {},
+                };
+                let state_data = match sm.state_data.data_types.get(&state.to_string()) {
+                    Some(Type::Reference(_)) => data,
+                    Some(_) => quote! { &#data },
+                    None => quote! {},
+                };
+                quote! {
+                    self.context.#hook(#temporary_context_call #state_data) #hook_await;
+                    self.context.log_action(stringify!(#hook));
+                }
+            }
+            None => quote! {},
+        }
+    };
+
+    // The states with timed transitions record when they are entered, in this order
+    let mut timed_states: Vec<_> = sm.timed_transitions.keys().collect();
+    timed_states.sort();
+    let timer_index = |state: &syn::Ident| {
+        timed_states
+            .iter()
+            .position(|timed_state| state == timed_state.as_str())
+            .map(proc_macro2::Literal::usize_unsuffixed)
+    };
+
+    // Create the code trying the transitions which may be taken from the active state of a state
+    // machine, or of one of its regions. Transitions are tried in declaration order and the first
+    // one whose guard passes is taken, so a guard failure is only reported when every candidate
+    // has been rejected. Completion transitions, which have no event, keep the active state instead,
+    // while timed transitions whose guard fails leave the active state to the next candidate.
+    let generate_candidates = |machine: &ParsedStateMachine,
+                               names: &MachineNames,
+                               state: &syn::Ident,
+                               in_state: &proc_macro2::TokenStream,
+                               trigger: Trigger,
+                               mappings: &[EventMapping]| {
+        let MachineNames {
+            states_type_name,
+            state_field,
+            log_state_change,
+            history_field,
+            process_completion,
+            transition_result,
+            ..
+        } = names;
+
+        // Composite states with history remember the state they are left from, so it can be
+        // entered again
+        let history_index = |state: &syn::Ident| {
+            machine
+                .history_states
+                .iter()
+                .position(|history_state| history_state == state)
+                .map(proc_macro2::Literal::usize_unsuffixed)
+        };
+
+        let completion = matches!(trigger, Trigger::Completion);
+        let event_data_type = match trigger {
+            Trigger::Event(event) => machine.event_data.data_types.get(event),
+            _ => None,
+        };
+        let last = mappings.len() - 1;
+
+        mappings
+            .iter()
+            .enumerate()
+            .map(|(index, mapping)| {
+                // Transitions inherited from a composite state do not have access to
+                // the data of the active substate
+                let state_data = match machine.state_data.data_types.get(&mapping.in_state.to_string()) {
+                    Some(Type::Reference(_)) => (quote! { state_data }, quote! { state_data }),
+                    Some(_) => (quote! { state_data }, quote! { &state_data }),
+                    None => (quote! {}, quote! {}),
+                };
+
+                let event_data = match event_data_type {
+                    Some(Type::Reference(_)) => (quote! { event_data }, quote! { event_data }),
+                    Some(_) => (quote! { event_data }, quote! { &event_data }),
+                    None => (quote! {}, quote! {}),
+                };
+
+                let (g_a_param, g_a_ref_param) = match (state_data, event_data) {
+                    ((state_data, state_data_ref), (event_data, event_data_ref))
+                        if state_data.is_empty() || event_data.is_empty() =>
+                    {
+                        (
+                            quote! { #state_data #event_data },
+                            quote! { #state_data_ref #event_data_ref },
+                        )
+                    }
+                    ((state_data, state_data_ref), (event_data, event_data_ref)) => (
+                        quote! { #state_data, #event_data },
+                        quote! { #state_data_ref, #event_data_ref },
+                    ),
+                };
+
+                let out_state = machine.initial_leaf(&mapping.out_state);
+                let out_state_data = match machine.state_data.data_types.get(&out_state.to_string()) {
+                    None => quote! { #out_state },
+                    Some(_) => quote! { #out_state(_data) },
+                };
+
+                // Chained actions are run in sequence, borrowing the data, before the last action
+                let call_action = |AsyncIdent {ident: a, is_async: is_a_async}: &AsyncIdent, params: &proc_macro2::TokenStream| {
+                    let action_await = match is_a_async {
+                        true => quote! { .await },
+                        false => quote! { },
+                    };
+                    quote! {
+                        self.context.#a(#temporary_context_call #event_sender_call #params) #action_await
+                    }
+                };
+                let log_action = |AsyncIdent {ident: a, ..}: &AsyncIdent| {
+                    quote! { self.context.log_action(stringify!(#a)); }
+                };
+
+                // The actions of the transition to a choice are run once, before the guard of its
+                // first branch
+                let choice_action: proc_macro2::TokenStream = match index {
+                    0 => &mapping.actions[..mapping.choice_actions],
+                    _ if mappings[index - 1].choice != mapping.choice => &mapping.actions[..mapping.choice_actions],
+                    _ => &[][..],
+                }
+                .iter()
+                .map(|choice_action| {
+                    let call = call_action(choice_action, &g_a_ref_param);
+                    let log = log_action(choice_action);
+                    quote! {
+                        #call;
+                        #log
+                    }
+                })
+                .collect();
+
+                let (chained_actions, last_action) = match mapping.actions[mapping.choice_actions..].split_last() {
+                    Some((last_action, chained_actions)) => (chained_actions, Some(last_action)),
+                    None => (&[][..], None),
+                };
+                let chained_action: proc_macro2::TokenStream = chained_actions
+                    .iter()
+                    .map(|chained_action| {
+                        let call = call_action(chained_action, &g_a_ref_param);
+                        let log = log_action(chained_action);
+                        quote! {
+                            #call;
+                            #log
+                        }
+                    })
+                    .collect();
+
+                let action = match last_action {
+                    Some(last_action) => {
+                        let call = call_action(last_action, &g_a_param);
+                        let log = log_action(last_action);
+                        quote! {
+                            #chained_action
+                            let _data = #call;
+                            #log
+                        }
+                    }
+                    None => quote! {},
+                };
+
+                // The states which are left and entered are the ones below the
+                // innermost composite state containing both the source and target of
+                // the transition, or every target an action may choose
+                let target_ancestors = machine.ancestors(&mapping.out_state);
+                let domain = machine
+                    .ancestors(&mapping.in_state)
+                    .into_iter()
+                    .find(|ancestor| {
+                        target_ancestors.contains(ancestor)
+                            && mapping
+                                .targets
+                                .iter()
+                                .all(|target| machine.ancestors(target).contains(ancestor))
+                    });
+
+                let exit: proc_macro2::TokenStream = std::iter::once(state.clone())
+                    .chain(machine.ancestors(state).into_iter().take_while(|ancestor| Some(ancestor) != domain.as_ref()))
+                    .map(|exited| {
+                        let exit = hook_call(&machine.exit_actions, &exited, quote! { state_data });
+                        match history_index(&exited) {
+                            Some(index) => quote! {
+                                #exit
+                                self.#history_field[#index] = Some(#states_type_name::#state);
+                            },
+                            None => exit,
+                        }
+                    })
+                    .collect();
+
+                let mut entered: Vec<_> = target_ancestors
+                    .iter()
+                    .take_while(|ancestor| Some(*ancestor) != domain.as_ref())
+                    .cloned()
+                    .collect();
+                entered.reverse();
+                entered.push(mapping.out_state.clone());
+
+                // helper for entering the initial substates of the last entered state
+                let enter_initial = |entered: &mut Vec<syn::Ident>| {
+                    while let Some(substate) = machine.initial_substates.get(&entered.last().unwrap().to_string()) {
+                        entered.push(substate.clone());
+                    }
+                };
+                // Entering a state with timed transitions starts its timers
+                let entry_code = |entered: &[syn::Ident]| -> proc_macro2::TokenStream {
+                    entered
+                        .iter()
+                        .map(|entered| {
+                            let entry = hook_call(&machine.entry_actions, entered, quote! { _data });
+                            match timer_index(entered) {
+                                Some(index) => quote! {
+                                    #entry
+                                    self.entered_at[#index] = Some(smlang::Clock::now(self.context.clock()));
+                                    self.checked_at[#index] = None;
+                                },
+                                None => entry,
+                            }
+                        })
+                        .collect()
+                };
+
+                let (entry, out_state) = match (mapping.history, history_index(&mapping.out_state)) {
+                    // The target chosen by the action decides which states are entered
+                    _ if !mapping.targets.is_empty() => {
+                        let target_type_name = sm.target_type_name(&mapping.actions.last().unwrap().ident);
+                        let target_arms = mapping.targets.iter().map(|target| {
+                            let mut target_entered: Vec<_> = machine
+                                .ancestors(target)
+                                .into_iter()
+                                .take_while(|ancestor| Some(ancestor) != domain.as_ref())
+                                .collect();
+                            target_entered.reverse();
+                            target_entered.push(target.clone());
+                            enter_initial(&mut target_entered);
+
+                            let target_entry = entry_code(&target_entered);
+                            let target_leaf = target_entered.last().unwrap();
+                            match machine.state_data.data_types.get(&target_leaf.to_string()) {
+                                None => quote! {
+                                    #target_type_name::#target => {
+                                        #target_entry
+                                        #states_type_name::#target_leaf
+                                    }
+                                },
+                                Some(_) => quote! {
+                                    #target_type_name::#target(_data) => {
+                                        #target_entry
+                                        #states_type_name::#target_leaf(_data)
+                                    }
+                                },
+                            }
+                        });
+
+                        (quote! {}, quote! { match _data { #(#target_arms)* } })
+                    }
+                    (Some(history), Some(index)) => {
+                        // The recorded state decides which substates are entered,
+                        // falling back to the initial substates
+                        let mut recorded_states: Vec<_> = machine
+                            .states
+                            .values()
+                            .filter(|state| machine.ancestors(state).contains(&mapping.out_state))
+                            .collect();
+                        recorded_states.sort_by_key(|state| state.to_string());
+
+                        let mut resumed_states: Vec<(syn::Ident, Vec<&syn::Ident>)> = Vec::new();
+                        for recorded_state in recorded_states {
+                            let resumed_state = match history {
+                                History::Deep => recorded_state.clone(),
+                                History::Shallow => std::iter::once(recorded_state.clone())
+                                    .chain(machine.ancestors(recorded_state))
+                                    .find(|state| machine.parents.get(&state.to_string()) == Some(&mapping.out_state))
+                                    .unwrap(),
+                            };
+                            match resumed_states.iter_mut().find(|(state, _)| state == &resumed_state) {
+                                Some((_, recorded)) => recorded.push(recorded_state),
+                                None => resumed_states.push((resumed_state, vec![recorded_state])),
+                            }
+                        }
+
+                        let resumed_arms = resumed_states.iter().map(|(resumed_state, recorded)| {
+                            let mut resumed_entered: Vec<_> = machine
+                                .ancestors(resumed_state)
+                                .into_iter()
+                                .take_while(|ancestor| ancestor != &mapping.out_state)
+                                .collect();
+                            resumed_entered.reverse();
+                            resumed_entered.push(resumed_state.clone());
+                            enter_initial(&mut resumed_entered);
+
+                            let resumed_entry = entry_code(&resumed_entered);
+                            let resumed_leaf = resumed_entered.last().unwrap();
+                            quote! {
+                                #(Some(#states_type_name::#recorded))|* => {
+                                    #resumed_entry
+                                    #states_type_name::#resumed_leaf
+                                }
+                            }
+                        });
+
+                        let mut initial_entered = vec![mapping.out_state.clone()];
+                        enter_initial(&mut initial_entered);
+                        let initial_entry = entry_code(&initial_entered[1..]);
+                        let initial_leaf = initial_entered.last().unwrap();
+
+                        (
+                            entry_code(&entered),
+                            quote! {
+                                match &self.#history_field[#index] {
+                                    #(#resumed_arms)*
+                                    _ => {
+                                        #initial_entry
+                                        #states_type_name::#initial_leaf
+                                    }
+                                }
+                            },
+                        )
+                    }
+                    _ => {
+                        enter_initial(&mut entered);
+                        (entry_code(&entered), quote! { #states_type_name::#out_state_data })
+                    }
+                };
+
+                // Completion transitions are taken one after another, up to the completion limit, while
+                // the transitions taken for an event continue with the completion transitions of the
+                // entered state
+                let (limit_check, completion_call) = if completion {
+                    (
+                        quote! {
+                            if remaining_completions == 0 {
+                                self.#state_field = Some(#states_type_name::#in_state);
+                                return Err(#error_type_name::CompletionLimitReached);
+                            }
+                            remaining_completions -= 1;
+                        },
+                        quote! {},
+                    )
+                } else if machine.completion_transitions.is_empty() {
+                    (quote! {}, quote! {})
+                } else {
+                    (
+                        quote! {},
+                        quote! { self.#process_completion(#temporary_context_call) #process_await?; },
+                    )
+                };
+
+                let transition = if mapping.internal {
+                    // Internal transitions update the data of the active state
+                    // without leaving it
+                    let mut internal_params = Vec::new();
+                    let mut chained_params = Vec::new();
+                    if machine.state_data.data_types.contains_key(&mapping.in_state.to_string()) {
+                        internal_params.push(quote! { &mut state_data });
+                        chained_params.push(quote! { &mut state_data });
+                    }
+                    match event_data_type {
+                        Some(Type::Reference(_)) => {
+                            internal_params.push(quote! { event_data });
+                            chained_params.push(quote! { event_data });
+                        }
+                        Some(_) => {
+                            internal_params.push(quote! { event_data });
+                            chained_params.push(quote! { &event_data });
+                        }
+                        None => {}
+                    }
+                    let internal_params = quote! { #(#internal_params),* };
+                    let chained_params = quote! { #(#chained_params),* };
+
+                    let action: proc_macro2::TokenStream = mapping
+                        .actions
+                        .iter()
+                        .enumerate()
+                        .map(|(index, action)| {
+                            let params = if index + 1 < mapping.actions.len() {
+                                &chained_params
+                            } else {
+                                &internal_params
+                            };
+                            let call = call_action(action, params);
+                            let log = log_action(action);
+                            quote! {
+                                #call;
+                                #log
+                            }
+                        })
+                        .collect();
+
+                    quote! {
+                        #action
+                        self.#state_field = Some(#states_type_name::#in_state);
+                    }
+                } else {
+                    quote! {
+                        #limit_check
+                        #exit
+                        #action
+                        #entry
+                        let out_state = #out_state;
+                        self.context.#log_state_change(&out_state);
+                        self.#state_field = Some(out_state);
+                        #completion_call
+                    }
+                };
+
+                let candidate = if let Some(expression) = &mapping.guard {
+                    let call = |AsyncIdent {ident: g, is_async: is_g_async}: &AsyncIdent| {
+                        let guard_await = match is_g_async {
+                            true => quote! { .await },
+                            false => quote! { },
+                        };
+                        quote! { self.context.#g(#temporary_context_call #g_a_ref_param) #guard_await }
+                    };
+
+                    let (guard, guard_error, guard_failed) = match expression {
+                        GuardExpression::Guard(guard @ AsyncIdent {ident: g, ..}) => {
+                            let call = call(guard);
+                            (
+                                quote! {
+                                    let guard_result = #call;
+                                    self.context.log_guard(stringify!(#g), &guard_result);
+                                },
+                                quote! { e },
+                                quote! { #error_type_name::GuardFailed(e) },
+                            )
+                        }
+                        _ => {
+                            let expression = generate_guard_expression(expression, &call);
+                            (
+                                quote! {
+                                    let guard_result: Result<&'static str, (&'static str, Option<#guard_expression_error>)> = #expression;
+                                },
+                                quote! { (guard, error) },
+                                quote! { #error_type_name::GuardExpressionFailed { guard, error } },
+                            )
+                        }
+                    };
+
+                    if let Trigger::Timeout = trigger {
+                        quote! {
+                            #guard
+                            if guard_result.is_ok() {
+                                #transition
+                                return Ok(true);
+                            }
+                        }
+                    } else if index == last && completion {
+                        quote! {
+                            #guard
+                            if guard_result.is_err() {
+                                self.#state_field = Some(#states_type_name::#in_state);
+                                return Ok(());
+                            }
+                            #transition
+                        }
+                    } else if index == last {
+                        quote! {
+                            #guard
+                            if let Err(#guard_error) = guard_result {
+                                self.#state_field = Some(#states_type_name::#in_state);
+                                return Err(#guard_failed);
                             }
+                            #transition
+                        }
+                    } else if completion {
+                        quote! {
+                            #guard
+                            if guard_result.is_ok() {
+                                #transition
+                                continue;
+                            }
+                        }
+                    } else {
+                        quote! {
+                            #guard
+                            if guard_result.is_ok() {
+                                #transition
+                                return #transition_result;
+                            }
+                        }
+                    }
+                } else if let Trigger::Timeout = trigger {
+                    quote! {
+                        #transition
+                        return Ok(true);
+                    }
+                } else {
+                    transition
+                };
+
+                quote! {
+                    #choice_action
+                    #candidate
+                }
+            })
+            .collect::<proc_macro2::TokenStream>()
+    };
+
+    // Create the code processing an event in the active state of a state machine, or of one of its
+    // regions
+    let generate_process = |machine: &ParsedStateMachine, names: &MachineNames| {
+        let MachineNames {
+            states_type_name,
+            state_field,
+            transition_result,
+            unhandled_result,
+            ..
+        } = names;
+
+        let transitions = &machine.states_events_mapping;
+
+        let in_states: Vec<_> = transitions
+            .keys()
+            .map(|name| {
+                let state_name = machine.states.get(name).unwrap();
+
+                match machine.state_data.data_types.get(name) {
+                    None => {
+                        quote! {
+                            #state_name
+                        }
+                    }
+                    Some(_) => {
+                        quote! {
+                            #state_name(state_data)
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        // The data of states with internal transitions is bound mutably, so it can be updated
+        let in_state_patterns: Vec<_> = transitions
+            .iter()
+            .zip(in_states.iter())
+            .map(|((name, value), in_state)| {
+                let state_name = machine.states.get(name).unwrap();
+                let updates_data = machine.submachines.contains_key(name)
+                    || value
+                        .values()
+                        .flatten()
+                        .any(|mapping| mapping.internal && !mapping.actions.is_empty());
+
+                match machine.state_data.data_types.get(name) {
+                    Some(_) if updates_data => quote! { #state_name(mut state_data) },
+                    _ => in_state.clone(),
+                }
+            })
+            .collect();
+
+        let events: Vec<Vec<_>> = transitions
+            .values()
+            .map(|value| {
+                value
+                    .keys()
+                    .filter(|name| *name != "_")
+                    .map(|name| {
+                        let value = machine.events.get(name).unwrap();
+
+                        match machine.event_data.data_types.get(name) {
+                            None => {
+                                quote! {
+                                    #value
+                                }
+                            }
+                            Some(_) => {
+                                quote! {
+                                    #value(event_data)
+                                }
+                            }
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // Create the code blocks inside the switch cases
+        let code_blocks: Vec<Vec<_>> = transitions
+            .iter()
+            .zip(in_states.iter())
+            .map(|((state, value), in_state)| {
+                let state = machine.states.get(state).unwrap();
+
+                value
+                    .iter()
+                    .filter(|(event, _)| *event != "_")
+                    .map(|(event, mappings)| {
+                        generate_candidates(
+                            machine,
+                            names,
+                            state,
+                            in_state,
+                            Trigger::Event(event),
+                            mappings,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // The events which are not handled otherwise are rejected, unless the active state has
+        // transitions for the wildcard event, which get the whole event
+        let fallbacks: Vec<_> = transitions
+            .iter()
+            .zip(in_states.iter())
+            .map(
+                |((state, value), in_state)| match value.get_key_value("_") {
+                    Some((event, mappings)) => {
+                        let state = machine.states.get(state).unwrap();
+                        let code_block = generate_candidates(
+                            machine,
+                            names,
+                            state,
+                            in_state,
+                            Trigger::Event(event),
+                            mappings,
+                        );
+
+                        quote! {
+                            event_data => {
+                                #code_block
+
+                                #transition_result
+                            }
+                        }
+                    }
+                    None => quote! {
+                        _ => {
+                            self.#state_field = Some(#states_type_name::#in_state);
+                            #unhandled_result
+                        }
+                    },
+                },
+            )
+            .collect();
+
+        // The events of a submachine are forwarded to it, and once it has reached a final state the
+        // completion transitions of its state are taken
+        let forwards: Vec<_> = transitions
+            .keys()
+            .zip(in_states.iter())
+            .map(|(name, in_state)| match machine.submachines.get(name) {
+                Some(AsyncIdent {
+                    ident: submachine,
+                    is_async,
+                }) => {
+                    let submachine_await = match is_async {
+                        true => quote! { .await },
+                        false => quote! {},
+                    };
+                    let completion_call = if machine.completion_transitions.contains_key(name) {
+                        let process_completion = &names.process_completion;
+                        quote! { self.#process_completion(#temporary_context_call) #process_await?; }
+                    } else {
+                        quote! {}
+                    };
+
+                    quote! {
+                        #events_type_name::#submachine(event_data) => {
+                            let result = state_data.process_event(event_data) #submachine_await;
+                            let rejected = result.is_err();
+                            self.#state_field = Some(#states_type_name::#in_state);
+                            if rejected {
+                                return Err(#error_type_name ::SubmachineRejected);
+                            }
+                            #completion_call
+
+                            #transition_result
+                        }
+                    }
+                }
+                None => quote! {},
+            })
+            .collect();
+
+        // Events deferred by the active state are stored until a state is entered which does not
+        // defer them
+        let deferrals: Vec<_> = transitions
+            .keys()
+            .zip(in_states.iter())
+            .map(|(name, in_state)| match machine.deferred_events.get(name) {
+                Some(deferred) => {
+                    let deferred = deferred.iter().map(|event| {
+                        match machine.event_data.data_types.get(&event.to_string()) {
+                            None => quote! { #events_type_name::#event },
+                            Some(_) => quote! { #events_type_name::#event(..) },
+                        }
+                    });
+
+                    quote! {
+                        #(#deferred)|* => {
+                            self.#state_field = Some(#states_type_name::#in_state);
+                            self.deferred_events
+                                .push(event)
+                                .map_err(|_| #error_type_name ::DeferredEventsFull)?;
+
+                            #transition_result
+                        }
+                    }
+                }
+                None => quote! {},
+            })
+            .collect();
+
+        quote! {
+            match self.#state_field.take().ok_or_else(|| #error_type_name ::Poisoned)? {
+                #(#states_type_name::#in_state_patterns => match event {
+                    #forwards
+                    #(#events_type_name::#events => {
+                        #code_blocks
+
+                        #transition_result
+                    }),*
+                    #deferrals
+                    #fallbacks
+                }),*
+                state => {
+                    self.#state_field = Some(state);
+                    #unhandled_result
+                }
+            }
+        }
+    };
+
+    let state_lifetimes = &sm.state_data.all_lifetimes;
+    let event_lifetimes = &sm.event_data.all_lifetimes;
+
+    // lifetimes that exists in #events_type_name but not in #states_type_name
+    let event_unique_lifetimes = event_lifetimes - state_lifetimes;
+
+    let guard_error = if sm.custom_guard_error {
+        quote! {
+            /// The error type returned by guard functions.
+            type GuardError: core::fmt::Debug;
+        }
+    } else {
+        quote! {}
+    };
+
+    let guard_error_type = if sm.custom_guard_error {
+        quote! { Self::GuardError }
+    } else {
+        quote! { () }
+    };
+
+    let (is_async, is_async_trait) = if sm.is_async() {
+        (quote! { async }, quote! { #[smlang::async_trait] })
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    let error_type = if sm.custom_guard_error {
+        quote! {
+            #error_type_name<<#context_param as #state_machine_context_type_name <#generic_args>>::GuardError>
+        }
+    } else {
+        quote! {#error_type_name}
+    };
+
+    let derive_states_list = &sm.derive_states;
+    let derive_events_list = &sm.derive_events;
+    let states_attributes = &sm.states_attributes;
+    let events_attributes = &sm.events_attributes;
+    let error_attributes = &sm.error_attributes;
+    let state_machine_attributes = &sm.state_machine_attributes;
+
+    // Create the functions taking the timed transitions of a state machine, or of one of its
+    // regions, and starting their timers, along with the expression of their next deadline
+    let clock_type = &sm.clock_type;
+    let clock_instant = quote! { <#clock_type as smlang::Clock>::Instant };
+    let generate_timed = |machine: &ParsedStateMachine, names: &MachineNames| {
+        let MachineNames {
+            states_type_name,
+            state_field,
+            process_timed,
+            start_timers,
+            ..
+        } = names;
+
+        let mut leaf_states: Vec<_> = machine.states.iter().collect();
+        leaf_states.sort_by_key(|(name, _)| name.to_string());
+
+        // The timed transitions of the active state are tried before the ones of its
+        // ancestors, each one once its duration has passed since its input state was entered
+        let mut in_states = Vec::new();
+        let mut checks = Vec::new();
+        let mut deadline_patterns = Vec::new();
+        let mut deadlines = Vec::new();
+        let mut timer_starts = Vec::new();
+        for (name, state) in leaf_states {
+            // Final states are never left, so their timed transitions are not taken
+            if machine.final_states.contains(state) {
+                continue;
+            }
+
+            let timed: Vec<_> = std::iter::once(state.clone())
+                .chain(machine.ancestors(state))
+                .filter_map(|timed_state| {
+                    let mappings = machine.timed_transitions.get(&timed_state.to_string())?;
+                    Some((timer_index(&timed_state).unwrap(), mappings))
+                })
+                .collect();
+            if timed.is_empty() {
+                continue;
+            }
+
+            let (in_state, deadline_pattern) = match machine.state_data.data_types.get(name) {
+                None => (quote! { #state }, quote! { #state }),
+                Some(_) => (quote! { #state(state_data) }, quote! { #state(..) }),
+            };
+
+            let state_checks = timed.iter().map(|(index, mappings)| {
+                // The branches of a choice share the duration of the transition to the choice
+                let mut groups: Vec<&[EventMapping]> = Vec::new();
+                let mut start = 0;
+                for end in 1..=mappings.len() {
+                    if end == mappings.len()
+                        || mappings[end].choice.is_none()
+                        || mappings[end].choice != mappings[start].choice
+                    {
+                        groups.push(&mappings[start..end]);
+                        start = end;
+                    }
+                }
+
+                let candidates = groups.into_iter().map(|mappings| {
+                    let after = &mappings[0].after;
+                    let candidate = generate_candidates(
+                        machine,
+                        names,
+                        state,
+                        &in_state,
+                        Trigger::Timeout,
+                        mappings,
+                    );
+                    quote! {
+                        if now >= entered + (#after) && checked.map_or(true, |checked| entered + (#after) > checked) {
+                            #candidate
+                        }
+                    }
+                });
+
+                // A timer whose guards failed is not tried again until its state is re-entered
+                quote! {
+                    let entered = *self.entered_at[#index].get_or_insert(now);
+                    let checked = self.checked_at[#index].replace(now);
+                    #(#candidates)*
+                }
+            });
+            checks.push(quote! { #({ #state_checks })* });
+
+            deadlines.push(
+                timed
+                    .iter()
+                    .flat_map(|(index, mappings)| {
+                        mappings.iter().map(move |mapping| {
+                            let after = &mapping.after;
+                            quote! {
+                                self.entered_at[#index]
+                                    .map(|entered| entered + (#after))
+                                    .filter(|deadline| self.checked_at[#index].map_or(true, |checked| *deadline > checked))
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            timer_starts.push(
+                timed
+                    .iter()
+                    .map(|(index, _)| quote! {
+                        self.entered_at[#index] = Some(now);
+                        self.checked_at[#index] = None;
+                    })
+                    .collect::<proc_macro2::TokenStream>(),
+            );
+            in_states.push(in_state);
+            deadline_patterns.push(deadline_pattern);
+        }
+
+        let deadline = quote! {
+            match self.#state_field.as_ref() {
+                #(Some(#states_type_name::#deadline_patterns) => {
+                    IntoIterator::into_iter([#(#deadlines),*]).flatten().min()
+                })*
+                _ => None,
+            }
+        };
+
+        let functions = quote! {
+            /// Starts the timers of the initial state, and of its composite states, when the
+            /// state machine is created.
+            fn #start_timers(&mut self) {
+                let now = smlang::Clock::now(self.context.clock());
+                match self.#state_field.as_ref() {
+                    #(Some(#states_type_name::#deadline_patterns) => {
+                        #timer_starts
+                    })*
+                    _ => {}
+                }
+            }
+
+            #is_async fn #process_timed(
+                &mut self,
+                #temporary_context
+                now: #clock_instant
+            ) -> Result<bool, #error_type> {
+                match self.#state_field.take().ok_or_else(|| #error_type_name ::Poisoned)? {
+                    #(#states_type_name::#in_states => {
+                        #checks
+                        self.#state_field = Some(#states_type_name::#in_states);
+                        Ok(false)
+                    })*
+                    state => {
+                        self.#state_field = Some(state);
+                        Ok(false)
+                    }
+                }
+            }
+        };
+
+        (deadline, functions)
+    };
+
+    // Create the function taking the completion transitions of a state machine, or of one of its
+    // regions, as long as the entered states have completion transitions whose guards pass
+    let generate_completion = |machine: &ParsedStateMachine, names: &MachineNames| {
+        if machine.completion_transitions.is_empty() {
+            return quote! {};
+        }
+
+        let MachineNames {
+            states_type_name,
+            state_field,
+            process_completion,
+            ..
+        } = names;
+
+        // Final states are never left, so their completion transitions are not taken
+        let mut completion_states: Vec<_> = machine
+            .completion_transitions
+            .iter()
+            .filter(|(name, _)| {
+                !machine
+                    .final_states
+                    .iter()
+                    .any(|state| state == name.as_str())
+            })
+            .collect();
+        completion_states.sort_by_key(|(name, _)| name.to_string());
+
+        // The states of submachines are only completed once the submachine has reached a final state
+        let (in_state_patterns, code_blocks): (Vec<_>, Vec<_>) = completion_states
+            .into_iter()
+            .map(|(name, mappings)| {
+                let state = machine.states.get(name).unwrap();
+                let in_state = match machine.state_data.data_types.get(name) {
+                    None => quote! { #state },
+                    Some(_) => quote! { #state(state_data) },
+                };
+                let in_state_pattern = if machine.submachines.contains_key(name) {
+                    quote! { #in_state if state_data.is_terminated() }
+                } else {
+                    in_state.clone()
+                };
+                let code_block = generate_candidates(
+                    machine,
+                    names,
+                    state,
+                    &in_state,
+                    Trigger::Completion,
+                    mappings,
+                );
+
+                (in_state_pattern, code_block)
+            })
+            .unzip();
+
+        let completion_limit = proc_macro2::Literal::usize_unsuffixed(sm.completion_limit);
+
+        quote! {
+            #is_async fn #process_completion(
+                &mut self,
+                #temporary_context
+            ) -> Result<(), #error_type> {
+                let mut remaining_completions: usize = #completion_limit;
+
+                loop {
+                    match self.#state_field.take().ok_or_else(|| #error_type_name ::Poisoned)? {
+                        #(#states_type_name::#in_state_patterns => {
+                            #code_blocks
+                        }),*
+                        state => {
+                            self.#state_field = Some(state);
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    // Create the enum listing the states of a state machine, or of one of its regions
+    let generate_states = |machine: &ParsedStateMachine, names: &MachineNames| {
+        let MachineNames {
+            states_type_name,
+            super_states_type_name,
+            state_id_type_name,
+            ..
+        } = names;
+
+        let state_lifetimes = &machine.state_data.all_lifetimes;
+        let state_generics = machine
+            .generics
+            .used_by(machine.state_data.data_types.values());
+        let state_generic_args = state_generics.args();
+
+        // Get only the unique states
+        let mut state_list: Vec<_> = machine.states.values().collect();
+        state_list.sort_by_key(|state| state.to_string());
+
+        // The ids of the states are ordered like the states
+        let state_ids_code = generate_ids(
+            visibility,
+            state_id_type_name,
+            &state_list,
+            &machine.state_docs,
+            "state",
+        );
+        let state_id_patterns = state_list.iter().map(|state| {
+            match machine.state_data.data_types.get(&state.to_string()) {
+                None => quote! { #states_type_name::#state },
+                Some(_) => quote! { #states_type_name::#state(..) },
+            }
+        });
+        let state_id_code = quote! {
+            impl<#state_lifetimes #state_generics> #states_type_name <#state_lifetimes #state_generic_args> {
+                /// Returns the id of the state, which has no data.
+                pub fn id(&self) -> #state_id_type_name {
+                    match self {
+                        #(#state_id_patterns => #state_id_type_name::#state_list),*
+                    }
+                }
+            }
+        };
+
+        // The states are documented with their doc comments, followed by the events they accept
+        let state_variants: Vec<_> = state_list
+            .iter()
+            .map(|value| {
+                let docs = machine.state_docs.get(&value.to_string());
+                let mut accepted_events = accepted_events_doc(machine, value);
+                if docs.is_some() && !accepted_events.is_empty() {
+                    accepted_events.insert(0, String::new());
+                }
+                let docs = docs.into_iter().flatten();
+
+                match machine.state_data.data_types.get(&value.to_string()) {
+                    None => {
+                        quote! {
+                            #(#docs)*
+                            #(#[doc = #accepted_events])*
+                            #value
+                        }
+                    }
+                    Some(t) => {
+                        quote! {
+                            #(#docs)*
+                            #(#[doc = #accepted_events])*
+                            #value(#t)
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        // Composite states are never active by themselves, instead the active state can be checked
+        // for being one of their substates
+        let super_states_code = if machine.composite_states.is_empty() {
+            quote! {}
+        } else {
+            let mut super_states: Vec<_> = machine.composite_states.values().collect();
+            super_states.sort_by_key(|state| state.to_string());
+
+            let substates: Vec<_> = super_states
+                .iter()
+                .map(|super_state| {
+                    let mut substates: Vec<_> = machine
+                        .states
+                        .values()
+                        .filter(|state| machine.ancestors(state).contains(super_state))
+                        .collect();
+                    substates.sort_by_key(|state| state.to_string());
+
+                    let substates = substates.iter().map(|state| {
+                        match machine.state_data.data_types.get(&state.to_string()) {
+                            None => quote! { #states_type_name::#state },
+                            Some(_) => quote! { #states_type_name::#state(..) },
+                        }
+                    });
+
+                    quote! { #(#substates)|* }
+                })
+                .collect();
+
+            let super_state_list = super_states.iter().map(|super_state| {
+                let docs = machine
+                    .state_docs
+                    .get(&super_state.to_string())
+                    .into_iter()
+                    .flatten();
+                quote! {
+                    #(#docs)*
+                    #super_state
+                }
+            });
+
+            quote! {
+                /// List of auto-generated composite states, which are active whenever one of their
+                /// substates is.
+                #[allow(missing_docs)]
+                #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+                #visibility enum #super_states_type_name { #(#super_state_list),* }
+
+                impl<#state_lifetimes #state_generics> #states_type_name <#state_lifetimes #state_generic_args> {
+                    /// Returns `true` if this state is a substate, directly or nested, of `super_state`.
+                    pub fn is_in(&self, super_state: #super_states_type_name) -> bool {
+                        match super_state {
+                            #(#super_states_type_name::#super_states => matches!(self, #substates)),*
+                        }
+                    }
+                }
+            }
+        };
+
+        quote! {
+            /// List of auto-generated states.
+            #[allow(missing_docs)]
+            #[derive(#(#derive_states_list),*)]
+            #(#states_attributes)*
+            #visibility enum #states_type_name <#state_lifetimes #state_generics> { #(#state_variants),* }
+
+            /// Manually define PartialEq for #states_type_name based on variant only to address issue-#21
+            impl<#state_lifetimes #state_generics> PartialEq for #states_type_name <#state_lifetimes #state_generic_args> {
+                fn eq(&self, other: &Self) -> bool {
+                    use core::mem::discriminant;
+                    discriminant(self) == discriminant(other)
+                }
+            }
+
+            #state_id_code
+            #state_ids_code
+            #super_states_code
+        }
+    };
+
+    // Create the field holding the recorded states of the composite states with history, and its
+    // initial value
+    let history_code = |machine: &ParsedStateMachine,
+                        names: &MachineNames,
+                        states_type: &proc_macro2::TokenStream| {
+        if machine.history_states.is_empty() {
+            return (quote! {}, quote! {});
+        }
+
+        let history_field = &names.history_field;
+        let history_len = proc_macro2::Literal::usize_unsuffixed(machine.history_states.len());
+        let no_history = machine.history_states.iter().map(|_| quote! { None });
+
+        (
+            quote! { #history_field: [Option<#states_type>; #history_len], },
+            quote! { #history_field: [#(#no_history),*], },
+        )
+    };
+
+    // A state machine with an event queue has a const generic parameter for its capacity
+    let (queue_param, queue_arg, event_queue_field, event_queue_init) = if sm.event_queue {
+        (
+            quote! { , const QUEUE_CAPACITY: usize },
+            quote! { , QUEUE_CAPACITY },
+            quote! {
+                event_queue: smlang::EventQueue<#events_type_name <#event_generic_args>, QUEUE_CAPACITY>,
+                event_queue_overflowed: bool,
+            },
+            quote! {
+                event_queue: smlang::EventQueue::new(),
+                event_queue_overflowed: false,
+            },
+        )
+    } else {
+        (quote! {}, quote! {}, quote! {}, quote! {})
+    };
+
+    // Create the code processing the events posted to the event queue after the first event, until
+    // the queue is empty
+    let generate_drain = |log_state: proc_macro2::TokenStream,
+                          replay: &proc_macro2::TokenStream| {
+        quote! {
+            let mut processed = 1;
+            while let Some(event) = self.event_queue.pop() {
+                self.context.log_process_event(#log_state, &event);
+                match self.dispatch_event(#temporary_context_call event) #process_await {
+                    Ok(())
+                    | Err(#error_type_name ::InvalidEvent)
+                    | Err(#guard_failed)
+                    #terminated => {}
+                    Err(e) => return Err(e),
+                }
+                #replay
+                processed += 1;
+            }
+
+            Ok(smlang::RunToCompletion {
+                processed,
+                overflowed: core::mem::replace(&mut self.event_queue_overflowed, false),
+            })
+        }
+    };
+
+    let (context_hooks_code, states_code, state_machine_code) = match &sm.starting_state {
+        // A state machine without regions has a single active state
+        Some(starting_state) => {
+            let mut names = MachineNames {
+                states_type_name: states_type_name.clone(),
+                super_states_type_name: format_ident!("{sm_name}SuperStates", span = sm_name_span),
+                state_id_type_name: sm.state_id_type_name(),
+                state_field: format_ident!("state"),
+                log_state_change: format_ident!("log_state_change"),
+                history_field: format_ident!("history"),
+                process_completion: format_ident!("process_completion_transitions"),
+                process_timed: format_ident!("process_timed_transitions"),
+                start_timers: format_ident!("start_timers"),
+                // Events are dispatched by a separate function when deferred or queued events are
+                // processed after them
+                transition_result: if sm.deferred_events.is_empty() && !sm.event_queue {
+                    quote! { self.state() }
+                } else {
+                    quote! { Ok(()) }
+                },
+                unhandled_result: quote! {},
+            };
+            // Unhandled events are handed to the context, which rejects them by default
+            let transition_result = &names.transition_result;
+            names.unhandled_result = quote! {
+                if let Some(state) = &self.state {
+                    self.context.on_unhandled(state, event)?;
+                }
+                #transition_result
+            };
+
+            let (mut extra_fields, mut extra_inits) = history_code(
+                sm,
+                &names,
+                &quote! { #states_type_name <#state_lifetimes #state_generic_args> },
+            );
+            extra_fields.extend(event_queue_field.clone());
+            extra_inits.extend(event_queue_init.clone());
+
+            // The type parameters are held by a marker, as they may only be used by the events
+            let type_params: Vec<_> = generics.type_params().collect();
+            if !type_params.is_empty() {
+                extra_fields.extend(quote! {
+                    generics: core::marker::PhantomData<fn() -> (#(#type_params,)*)>,
+                });
+                extra_inits.extend(quote! { generics: core::marker::PhantomData, });
+            }
+
+            // The deferred events are kept in a queue, so they can be replayed in order
+            if !sm.deferred_events.is_empty() {
+                let defer_capacity = proc_macro2::Literal::usize_unsuffixed(sm.defer_capacity);
+                extra_fields.extend(quote! {
+                    deferred_events: smlang::EventQueue<#events_type_name <#event_lifetimes #event_generic_args>, #defer_capacity>,
+                });
+                extra_inits.extend(quote! { deferred_events: smlang::EventQueue::new(), });
+            }
+
+            // The entry times of the states with timed transitions, and the last time their timers
+            // were checked
+            if !timed_states.is_empty() {
+                let timed_len = proc_macro2::Literal::usize_unsuffixed(timed_states.len());
+                let not_entered: Vec<_> = timed_states.iter().map(|_| quote! { None }).collect();
+                extra_fields.extend(quote! {
+                    entered_at: [Option<#clock_instant>; #timed_len],
+                    checked_at: [Option<#clock_instant>; #timed_len],
+                });
+                extra_inits.extend(quote! {
+                    entered_at: [#(#not_entered),*],
+                    checked_at: [#(#not_entered),*],
+                });
+            }
+
+            // create a token stream for creating a new machine.  If the starting state contains data, then
+            // add a second argument to pass this initial data
+            let starting_state_name = starting_state.to_string();
+            let (state_param, starting_state) =
+                match sm.state_data.data_types.get(&starting_state_name) {
+                    Some(st) => (
+                        quote! { , state_data: #st },
+                        quote! { #states_type_name::#starting_state (state_data) },
+                    ),
+                    None => (quote! {}, quote! { #states_type_name::#starting_state }),
+                };
+
+            // The timers of the initial state are started from the clock of the context, which
+            // can not be read in a const function
+            let init = |state: proc_macro2::TokenStream| {
+                let init = quote! {
+                    #state_machine_type_name {
+                        state: Some(#state),
+                        #extra_inits
+                        context
+                    }
+                };
+                if timed_states.is_empty() {
+                    init
+                } else {
+                    quote! {
+                        let mut state_machine = #init;
+                        state_machine.start_timers();
+                        state_machine
+                    }
+                }
+            };
+            let constness = if timed_states.is_empty() {
+                quote! { const }
+            } else {
+                quote! {}
+            };
+            let new_with_state_init = init(quote! { initial_state });
+
+            // The completion transitions of the starting state are taken when the state machine
+            // is created, stopping at the completion limit as no error can be returned
+            let (new_constness, new_init) =
+                if sm.completion_transitions.contains_key(&starting_state_name) {
+                    let init = init(starting_state);
+                    (
+                        quote! {},
+                        quote! {
+                            let mut state_machine = { #init };
+                            let _ = state_machine.process_completion_transitions();
+                            state_machine
+                        },
+                    )
+                } else {
+                    (constness.clone(), init(starting_state))
+                };
+            let new_sm_code = quote! {
+                pub #new_constness fn new(context: #context_param #state_param) -> Self {
+                    #new_init
+                }
+            };
+
+            let process_code = generate_process(sm, &names);
+            let completion_code = generate_completion(sm, &names);
+
+            // A state machine which entered a final state rejects all events
+            let (process_code, terminated_code) = if sm.final_states.is_empty() {
+                (process_code, quote! {})
+            } else {
+                let final_states = sm.final_states.iter().map(|state| {
+                    match sm.state_data.data_types.get(&state.to_string()) {
+                        None => quote! { #states_type_name::#state },
+                        Some(_) => quote! { #states_type_name::#state(..) },
+                    }
+                });
+
+                (
+                    quote! {
+                        if self.is_terminated() {
+                            return Err(#error_type_name ::Terminated);
+                        }
+                        #process_code
+                    },
+                    quote! {
+                        /// Returns `true` if a final state was entered, after which all events are
+                        /// rejected.
+                        #[inline(always)]
+                        pub fn is_terminated(&self) -> bool {
+                            matches!(self.state, Some(#(#final_states)|*))
+                        }
+                    },
+                )
+            };
+
+            // Events are dispatched by a separate function when deferred or queued events are
+            // processed after them
+            let (process_code, dispatch_code) = if sm.deferred_events.is_empty() && !sm.event_queue
+            {
+                (process_code, quote! {})
+            } else {
+                let replay = if sm.deferred_events.is_empty() {
+                    quote! {}
+                } else {
+                    quote! { self.replay_deferred_events(#temporary_context_call) #process_await?; }
+                };
+                let result = if sm.event_queue {
+                    generate_drain(quote! { self.state()? }, &replay)
+                } else {
+                    quote! { self.state() }
+                };
+
+                (
+                    quote! {
+                        self.dispatch_event(#temporary_context_call event) #process_await?;
+                        #replay
+                        #result
+                    },
+                    quote! {
+                        #is_async fn dispatch_event(
+                            &mut self,
+                            #temporary_context
+                            mut event: #events_type_name <#event_lifetimes #event_generic_args>
+                        ) -> Result<(), #error_type> {
+                            #process_code
+                        }
+                    },
+                )
+            };
+
+            let deferral_code = if sm.deferred_events.is_empty() {
+                quote! {}
+            } else {
+                let mut deferrals: Vec<_> = sm.deferred_events.iter().collect();
+                deferrals.sort_by_key(|(name, _)| name.to_string());
+
+                let events_type_name = &events_type_name;
+                let deferral_patterns = deferrals.into_iter().flat_map(|(name, deferred)| {
+                    let state = &sm.states[name];
+                    let state = match sm.state_data.data_types.get(name) {
+                        None => quote! { #states_type_name::#state },
+                        Some(_) => quote! { #states_type_name::#state(..) },
+                    };
+
+                    deferred.iter().map(move |event| {
+                        match sm.event_data.data_types.get(&event.to_string()) {
+                            None => quote! { (#state, #events_type_name::#event) },
+                            Some(_) => quote! { (#state, #events_type_name::#event(..)) },
                         }
                     })
-                    .collect()
-            },
-        )
-        .collect();
+                });
+
+                quote! {
+                        /// Processes the deferred events which are no longer deferred by the
+                        /// active state, in the order they were deferred.
+                        #is_async fn replay_deferred_events(
+                            &mut self,
+                            #temporary_context
+                        ) -> Result<(), #error_type> {
+                            let mut index = 0;
+                            while let Some(event) = self.deferred_events.get(index) {
+                                if Self::defers(self.state()?, event) {
+                                    index += 1;
+                                    continue;
+                                }
 
-    let starting_state = &sm.starting_state;
+                                if let Some(event) = self.deferred_events.remove(index) {
+                                    self.context.log_process_event(self.state()?, &event);
+                                    match self.dispatch_event(#temporary_context_call event) #process_await {
+                                        Ok(())
+                                        | Err(#error_type_name ::InvalidEvent)
+                                        | Err(#guard_failed)
+                                        #terminated => {}
+                                        Err(e) => return Err(e),
+                                    }
+                                }
+
+                                // The active state may have changed, which can end the deferral
+                                // of the events before this one
+                                index = 0;
+                            }
 
-    // create a token stream for creating a new machine.  If the starting state contains data, then
-    // add a second argument to pass this initial data
-    let starting_state_name = starting_state.to_string();
-    let new_sm_code = match sm.state_data.data_types.get(&starting_state_name) {
-        Some(st) => quote! {
-            pub const fn new(context: T, state_data: #st ) -> Self {
-                #state_machine_type_name {
-                    state: Some(#states_type_name::#starting_state (state_data)),
-                    context
+                            Ok(())
+                        }
+
+                        /// Returns `true` if the event is deferred by the state.
+                        fn defers(
+                            state: &#states_type_name <#state_lifetimes #state_generic_args>,
+                            event: &#events_type_name <#event_lifetimes #event_generic_args>
+                        ) -> bool {
+                            matches!((state, event), #(#deferral_patterns)|*)
+                        }
                 }
-            }
-        },
-        None => quote! {
-            pub const fn new(context: T ) -> Self {
-                #state_machine_type_name {
-                    state: Some(#states_type_name::#starting_state),
-                    context
+            };
+
+            let transition_table_code =
+                generate_transition_table(sm, &names.state_id_type_name, &event_id_type_name, "");
+
+            let timed_code = if timed_states.is_empty() {
+                quote! {}
+            } else {
+                let (deadline, timed_functions) = generate_timed(sm, &names);
+
+                let replay = if sm.deferred_events.is_empty() {
+                    quote! {}
+                } else {
+                    quote! { self.replay_deferred_events(#temporary_context_call) #process_await?; }
+                };
+                let (tick_result, result) = if sm.event_queue {
+                    let drain = generate_drain(quote! { self.state()? }, &replay);
+                    (
+                        quote! { smlang::RunToCompletion },
+                        quote! {
+                            #replay
+                            let run_to_completion: Result<smlang::RunToCompletion, #error_type> = { #drain };
+                            run_to_completion.map(Some)
+                        },
+                    )
+                } else {
+                    (
+                        quote! { &#states_type_name <#state_lifetimes #state_generic_args> },
+                        quote! {
+                            #replay
+                            self.state().map(Some)
+                        },
+                    )
+                };
+
+                quote! {
+                    /// Takes a timed transition of the active state, or of one of its composite
+                    /// states, whose duration has passed at `now`.
+                    ///
+                    /// It will return `Ok(Some(..))` if a timed transition was taken, `Ok(None)` if
+                    /// none was due, or `Err(#error_type_name)` if there was an error in the
+                    /// transition.
+                    pub #is_async fn tick(
+                        &mut self,
+                        #temporary_context
+                        now: #clock_instant
+                    ) -> Result<Option<#tick_result>, #error_type> {
+                        if !self.process_timed_transitions(#temporary_context_call now) #process_await? {
+                            return Ok(None);
+                        }
+
+                        #result
+                    }
+
+                    /// Returns the earliest time at which a timed transition of the active state,
+                    /// or of one of its composite states, is due, without checking its guard. A
+                    /// timed transition whose guard failed in `tick` is left out until its input
+                    /// state is entered again.
+                    pub fn next_deadline(&self) -> Option<#clock_instant> {
+                        #deadline
+                    }
+
+                    #timed_functions
+                }
+            };
+
+            let (process_event_doc, process_event_result) = if sm.event_queue {
+                (
+                    quote! {
+                        /// Process an event, followed by the events posted to the event queue by
+                        /// the actions.
+                        ///
+                        /// It will return `Ok(RunToCompletion)` if the transition for the event was
+                        /// successful, or `Err(#error_type_name)` if there was an error in the
+                        /// transition. Posted events which are rejected are dropped.
+                    },
+                    quote! { smlang::RunToCompletion },
+                )
+            } else {
+                (
+                    quote! {
+                        /// Process an event.
+                        ///
+                        /// It will return `Ok(&NextState)` if the transition was successful, or `Err(#error_type_name)`
+                        /// if there was an error in the transition.
+                    },
+                    quote! { &#states_type_name <#state_lifetimes #state_generic_args> },
+                )
+            };
+
+            let context_hooks_code = quote! {
+                /// Called when transitioning to a new state as a result of an event passed to
+                /// `process_event()`. No-op by default but can be overridden in implementations
+                /// of a state machine's `StateMachineContext` trait.
+                fn log_state_change(&self, new_state: & #states_type_name <#state_generic_args>) {}
+
+                /// Called with an event which is not handled in the current state. Returning
+                /// `Ok(())` accepts the event without a transition, while the default
+                /// implementation rejects it with `Error::InvalidEvent`.
+                fn on_unhandled(&mut self, state: & #states_type_name <#state_generic_args>, event: #events_type_name <#event_generic_args>) -> Result<(), #error_type_name <#guard_error_type>> {
+                    Err(#error_type_name::InvalidEvent)
+                }
+            };
+
+            let state_machine_code = quote! {
+                /// State machine structure definition.
+                #(#state_machine_attributes)*
+                #visibility struct #state_machine_type_name<#state_lifetimes #generics #context_param: #state_machine_context_type_name <#generic_args> #queue_param> {
+                    state: Option<#states_type_name <#state_lifetimes #state_generic_args>>,
+                    #extra_fields
+                    context: #context_param
+                }
+
+                impl<#state_lifetimes #generics #context_param: #state_machine_context_type_name <#generic_args> #queue_param> #state_machine_type_name<#state_lifetimes #generic_args #context_param #queue_arg> {
+                    /// Creates a new state machine with the specified starting state.
+                    #[inline(always)]
+                    #new_sm_code
+
+                    /// Creates a new state machine with an initial state.
+                    #[inline(always)]
+                    pub #constness fn new_with_state(context: #context_param, initial_state: #states_type_name <#state_lifetimes #state_generic_args>) -> Self {
+                        #new_with_state_init
+                    }
+
+                    /// Returns the current state.
+                    #[inline(always)]
+                    pub fn state(&self) -> Result<&#states_type_name <#state_lifetimes #state_generic_args>, #error_type> {
+                        self.state.as_ref().ok_or_else(|| #error_type_name ::Poisoned)
+                    }
+
+                    /// Returns the current context.
+                    #[inline(always)]
+                    pub fn context(&self) -> &#context_param {
+                        &self.context
+                    }
+
+                    /// Returns the current context as a mutable reference.
+                    #[inline(always)]
+                    pub fn context_mut(&mut self) -> &mut #context_param {
+                        &mut self.context
+                    }
+
+                    #process_event_doc
+                    pub #is_async fn process_event <#event_unique_lifetimes> (
+                        &mut self,
+                        #temporary_context
+                        mut event: #events_type_name <#event_lifetimes #event_generic_args>
+                    ) -> Result<#process_event_result, #error_type> {
+                        self.context.log_process_event(self.state()?, &event);
+                        #process_code
+                    }
+
+                    #terminated_code
+                    #completion_code
+                    #dispatch_code
+                    #deferral_code
+                    #timed_code
+                    #transition_table_code
+                }
+            };
+
+            (
+                context_hooks_code,
+                generate_states(sm, &names),
+                state_machine_code,
+            )
+        }
+        // Every region of a state machine with regions has its own active state
+        None => {
+            let regions: Vec<_> = sm
+                .regions
+                .iter()
+                .map(|(region_name, region)| {
+                    let state_field = format_ident!("{}", snake_case(region_name));
+                    let names = MachineNames {
+                        states_type_name: format_ident!(
+                            "{sm_name}{region_name}States",
+                            span = region_name.span()
+                        ),
+                        super_states_type_name: format_ident!(
+                            "{sm_name}{region_name}SuperStates",
+                            span = region_name.span()
+                        ),
+                        state_id_type_name: format_ident!(
+                            "{sm_name}{region_name}StateId",
+                            span = region_name.span()
+                        ),
+                        log_state_change: format_ident!("log_{state_field}_state_change"),
+                        history_field: format_ident!("{state_field}_history"),
+                        process_completion: format_ident!(
+                            "process_{state_field}_completion_transitions"
+                        ),
+                        process_timed: format_ident!("process_{state_field}_timed_transitions"),
+                        start_timers: format_ident!("start_{state_field}_timers"),
+                        state_field,
+                        transition_result: quote! { Ok(()) },
+                        // The event is handed back, so it can be passed to the context once no
+                        // region handled it
+                        unhandled_result: quote! {
+                            *unhandled = Some(event);
+                            Err(#error_type_name ::InvalidEvent)
+                        },
+                    };
+                    (region_name, region, names)
+                })
+                .collect();
+
+            let mut context_hooks_code = proc_macro2::TokenStream::new();
+            let mut states_code = proc_macro2::TokenStream::new();
+            let mut fields = Vec::new();
+            let mut region_types = Vec::new();
+            let mut new_params = Vec::new();
+            let mut starting_states = Vec::new();
+            let mut process_functions = proc_macro2::TokenStream::new();
+            let mut extra_fields = proc_macro2::TokenStream::new();
+            let mut extra_inits = proc_macro2::TokenStream::new();
+            let mut deadlines = Vec::new();
+            let mut processes_timed = Vec::new();
+            let mut timer_starts = Vec::new();
+            let mut starting_completions = Vec::new();
+
+            for (region_name, region, names) in regions.iter() {
+                let MachineNames {
+                    states_type_name: region_states_type_name,
+                    state_field,
+                    log_state_change,
+                    ..
+                } = names;
+                let region_lifetimes = &region.state_data.all_lifetimes;
+                let region_generic_args = region
+                    .generics
+                    .used_by(region.state_data.data_types.values())
+                    .args();
+
+                let doc = format!(
+                    "Called when the `{}` region transitions to a new state as a result of an event passed to `process_event()`. No-op by default but can be overridden in implementations of a state machine's `StateMachineContext` trait.",
+                    region_name
+                );
+                context_hooks_code.extend(quote! {
+                    #[doc = #doc]
+                    fn #log_state_change(&self, new_state: & #region_states_type_name <#region_generic_args>) {}
+                });
+
+                states_code.extend(generate_states(region, names));
+
+                // The starting state of every region which has data needs an argument to pass
+                // this initial data
+                let starting_state = region.starting_state.as_ref().unwrap();
+                match region
+                    .state_data
+                    .data_types
+                    .get(&starting_state.to_string())
+                {
+                    Some(st) => {
+                        new_params.push(quote! { #state_field: #st });
+                        starting_states.push(
+                            quote! { #region_states_type_name::#starting_state(#state_field) },
+                        );
+                    }
+                    None => {
+                        starting_states.push(quote! { #region_states_type_name::#starting_state });
+                    }
+                }
+                if region
+                    .completion_transitions
+                    .contains_key(&starting_state.to_string())
+                {
+                    starting_completions.push(names.process_completion.clone());
+                }
+
+                // A region which entered a final state no longer reacts to events, like a
+                // terminated state machine
+                let final_check = if region.final_states.is_empty() {
+                    quote! {}
+                } else {
+                    let final_states = region.final_states.iter().map(|state| {
+                        match region.state_data.data_types.get(&state.to_string()) {
+                            None => quote! { #region_states_type_name::#state },
+                            Some(_) => quote! { #region_states_type_name::#state(..) },
+                        }
+                    });
+                    quote! {
+                        if matches!(self.#state_field, Some(#(#final_states)|*)) {
+                            *unhandled = Some(event);
+                            return Err(#error_type_name ::InvalidEvent);
+                        }
+                    }
+                };
+
+                let process_function = format_ident!("process_{state_field}_event");
+                let process_code = generate_process(region, names);
+                process_functions.extend(quote! {
+                    #is_async fn #process_function <#event_unique_lifetimes> (
+                        &mut self,
+                        #temporary_context
+                        mut event: #events_type_name <#event_lifetimes #event_generic_args>,
+                        unhandled: &mut Option<#events_type_name <#event_lifetimes #event_generic_args>>
+                    ) -> Result<(), #error_type> {
+                        #final_check
+                        #process_code
+                    }
+                });
+                process_functions.extend(generate_completion(region, names));
+                process_functions.extend(generate_transition_table(
+                    region,
+                    &names.state_id_type_name,
+                    &event_id_type_name,
+                    &format!("{}_", state_field),
+                ));
+
+                if !region.timed_transitions.is_empty() {
+                    let (deadline, timed_functions) = generate_timed(region, names);
+                    process_functions.extend(timed_functions);
+                    deadlines.push(deadline);
+                    processes_timed.push(names.process_timed.clone());
+                    timer_starts.push(names.start_timers.clone());
                 }
+
+                let region_type =
+                    quote! { #region_states_type_name <#region_lifetimes #region_generic_args> };
+                let (history_field, history_init) = history_code(region, names, &region_type);
+                extra_fields.extend(history_field);
+                extra_inits.extend(history_init);
+
+                fields.push(state_field);
+                region_types.push(region_type);
             }
-        },
-    };
 
-    let state_lifetimes = &sm.state_data.all_lifetimes;
-    let event_lifetimes = &sm.event_data.all_lifetimes;
+            // The type parameters are held by a marker, as they may only be used by the events
+            let type_params: Vec<_> = generics.type_params().collect();
+            if !type_params.is_empty() {
+                extra_fields.extend(quote! {
+                    generics: core::marker::PhantomData<fn() -> (#(#type_params,)*)>,
+                });
+                extra_inits.extend(quote! { generics: core::marker::PhantomData, });
+            }
 
-    // lifetimes that exists in #events_type_name but not in #states_type_name
-    let event_unique_lifetimes = event_lifetimes - state_lifetimes;
+            // Each event is handed to every region which has a transition for it, the event only
+            // fails when none of them takes a transition
+            let mut event_names: Vec<_> = sm.events.keys().collect();
+            event_names.sort();
+
+            let dispatch_code: Vec<_> = event_names
+                .into_iter()
+                .map(|event_name| {
+                    let event = &sm.events[event_name];
+                    let event_pattern = match sm.event_data.data_types.get(event_name) {
+                        None => quote! { #events_type_name::#event },
+                        Some(_) => quote! { #events_type_name::#event(..) },
+                    };
 
-    let guard_error = if sm.custom_guard_error {
+                    let handling_regions: Vec<_> = regions
+                        .iter()
+                        .filter(|(_, region, _)| region.handles_event(event_name))
+                        .collect();
+                    let last = handling_regions.len() - 1;
+
+                    let region_calls = handling_regions.iter().enumerate().map(
+                        |(index, (_, _, names))| {
+                            let process_function =
+                                format_ident!("process_{}_event", names.state_field);
+                            let event = if index == last {
+                                quote! { event }
+                            } else {
+                                quote! { event.clone() }
+                            };
+
+
+                            quote! {
+                                match self.#process_function(#temporary_context_call #event, &mut unhandled) #process_await {
+                                    Ok(()) => result = Ok(()),
+                                    Err(#error_type_name::InvalidEvent) => {}
+                                    Err(e @ (#guard_failed)) => {
+                                        if result.is_err() {
+                                            result = Err(e);
+                                        }
+                                    }
+                                    Err(e) => return Err(e),
+                                }
+                            }
+                        },
+                    );
+
+                    quote! {
+                        #event_pattern => {
+                            #(#region_calls)*
+                        }
+                    }
+                })
+                .collect();
+
+            let debug =
+                if sm.derive_states.iter().any(
+                    |derive| matches!(derive.segments.last(), Some(last) if last.ident == "Debug"),
+                ) {
+                    quote! { , Debug }
+                } else {
+                    quote! {}
+                };
+
+            // Events which no region handles are handed to the context, which rejects them by
+            // default
+            let dispatch = quote! {
+                let mut result: Result<(), #error_type> = Err(#error_type_name ::InvalidEvent);
+                let mut unhandled = None;
+                match &event {
+                    #(#dispatch_code)*
+                }
+                if matches!(result, Err(#error_type_name ::InvalidEvent)) {
+                    if let Some(event) = unhandled {
+                        let state = #states_type_name {
+                            #(#fields: self.#fields.as_ref().ok_or_else(|| #error_type_name ::Poisoned)?),*
+                        };
+                        result = self.context.on_unhandled(&state, event);
+                    }
+                }
+            };
+            context_hooks_code.extend(quote! {
+                /// Called with an event which is not handled in the active state of any region.
+                /// Returning `Ok(())` accepts the event without a transition, while the default
+                /// implementation rejects it with `Error::InvalidEvent`.
+                fn on_unhandled(&mut self, state: & #states_type_name <#state_generic_args>, event: #events_type_name <#event_generic_args>) -> Result<(), #error_type_name <#guard_error_type>> {
+                    Err(#error_type_name::InvalidEvent)
+                }
+            });
+
+            let state_generics = generics.used_by(sm.state_data.data_types.values());
+            states_code.extend(quote! {
+                /// The active states of all regions.
+                #[allow(missing_docs)]
+                #[derive(Clone, Copy, PartialEq #debug)]
+                #visibility struct #states_type_name <'s, #state_lifetimes #state_generics> {
+                    #(pub #fields: &'s #region_types),*
+                }
+            });
+
+            // A state machine is terminated once every region has entered one of its final states,
+            // while a region which entered a final state on its own no longer reacts to events
+            let (terminated_check, terminated_code) = if sm.final_states.is_empty() {
+                (quote! {}, quote! {})
+            } else {
+                let region_terminated = regions.iter().map(|(_, region, names)| {
+                    let MachineNames {
+                        states_type_name: region_states_type_name,
+                        state_field,
+                        ..
+                    } = names;
+                    let final_states = region.final_states.iter().map(|state| {
+                        match region.state_data.data_types.get(&state.to_string()) {
+                            None => quote! { #region_states_type_name::#state },
+                            Some(_) => quote! { #region_states_type_name::#state(..) },
+                        }
+                    });
+
+                    if region.final_states.is_empty() {
+                        quote! { false }
+                    } else {
+                        quote! { matches!(self.#state_field, Some(#(#final_states)|*)) }
+                    }
+                });
+
+                (
+                    quote! {
+                        if self.is_terminated() {
+                            return Err(#error_type_name ::Terminated);
+                        }
+                    },
+                    quote! {
+                        /// Returns `true` if every region entered a final state, after which all
+                        /// events are rejected.
+                        #[inline(always)]
+                        pub fn is_terminated(&self) -> bool {
+                            #(#region_terminated)&&*
+                        }
+                    },
+                )
+            };
+
+            // With an event queue, the events are dispatched to the regions by a separate function
+            // so the posted events can be processed after them
+            let process_event_code = if sm.event_queue {
+                let drain = generate_drain(quote! { &self.state()? }, &quote! {});
+
+                quote! {
+                    /// Process an event in every region which has a transition for it, followed by
+                    /// the events posted to the event queue by the actions.
+                    ///
+                    /// It will return `Ok(RunToCompletion)` if any region took a transition for the
+                    /// event, or `Err(#error_type_name)` if none of them did. Posted events which
+                    /// are rejected are dropped.
+                    pub #is_async fn process_event <#event_unique_lifetimes> (
+                        &mut self,
+                        #temporary_context
+                        event: #events_type_name <#event_lifetimes #event_generic_args>
+                    ) -> Result<smlang::RunToCompletion, #error_type> {
+                        self.context.log_process_event(&self.state()?, &event);
+                        self.dispatch_event(#temporary_context_call event) #process_await?;
+                        #drain
+                    }
+
+                    #is_async fn dispatch_event <#event_unique_lifetimes> (
+                        &mut self,
+                        #temporary_context
+                        event: #events_type_name <#event_lifetimes #event_generic_args>
+                    ) -> Result<(), #error_type> {
+                        #terminated_check
+                        #dispatch
+                        result
+                    }
+                }
+            } else {
+                quote! {
+                    /// Process an event in every region which has a transition for it.
+                    ///
+                    /// It will return `Ok(NextStates)` if any region took a transition, or `Err(#error_type_name)`
+                    /// if none of them did.
+                    pub #is_async fn process_event <#event_unique_lifetimes> (
+                        &mut self,
+                        #temporary_context
+                        event: #events_type_name <#event_lifetimes #event_generic_args>
+                    ) -> Result<#states_type_name <'_, #state_lifetimes #state_generic_args>, #error_type> {
+                        self.context.log_process_event(&self.state()?, &event);
+                        #terminated_check
+
+                        #dispatch
+                        result?;
+
+                        self.state()
+                    }
+                }
+            };
+
+            // The entry times of the states with timed transitions, and the last time their timers
+            // were checked, shared by all regions as their states are unique
+            let timed_field = if timed_states.is_empty() {
+                quote! {}
+            } else {
+                let timed_len = proc_macro2::Literal::usize_unsuffixed(timed_states.len());
+                quote! {
+                    entered_at: [Option<#clock_instant>; #timed_len],
+                    checked_at: [Option<#clock_instant>; #timed_len],
+                }
+            };
+            let not_entered: Vec<_> = timed_states.iter().map(|_| quote! { None }).collect();
+            let timed_init = if timed_states.is_empty() {
+                quote! {}
+            } else {
+                quote! {
+                    entered_at: [#(#not_entered),*],
+                    checked_at: [#(#not_entered),*],
+                }
+            };
+
+            // The timers of the initial states are started from the clock of the context, which
+            // can not be read in a const function
+            let init = |states: proc_macro2::TokenStream| {
+                let init = quote! {
+                    #state_machine_type_name {
+                        #states
+                        #extra_inits
+                        #timed_init
+                        #event_queue_init
+                        context
+                    }
+                };
+                if timed_states.is_empty() {
+                    init
+                } else {
+                    quote! {
+                        let mut state_machine = #init;
+                        #(state_machine.#timer_starts();)*
+                        state_machine
+                    }
+                }
+            };
+            let constness = if timed_states.is_empty() {
+                quote! { const }
+            } else {
+                quote! {}
+            };
+            let new_with_state_init = init(quote! { #(#fields: Some(#fields),)* });
+
+            // The completion transitions of the starting states are taken when the state machine
+            // is created, stopping at the completion limit as no error can be returned
+            let (new_constness, new_init) = if starting_completions.is_empty() {
+                (
+                    constness.clone(),
+                    init(quote! { #(#fields: Some(#starting_states),)* }),
+                )
+            } else {
+                let init = init(quote! { #(#fields: Some(#starting_states),)* });
+                (
+                    quote! {},
+                    quote! {
+                        let mut state_machine = { #init };
+                        #(let _ = state_machine.#starting_completions();)*
+                        state_machine
+                    },
+                )
+            };
+
+            let timed_code = if timed_states.is_empty() {
+                quote! {}
+            } else {
+                let (tick_result, result) = if sm.event_queue {
+                    let drain = generate_drain(quote! { &self.state()? }, &quote! {});
+                    (
+                        quote! { smlang::RunToCompletion },
+                        quote! {
+                            let run_to_completion: Result<smlang::RunToCompletion, #error_type> = { #drain };
+                            run_to_completion.map(Some)
+                        },
+                    )
+                } else {
+                    (
+                        quote! { #states_type_name <'_, #state_lifetimes #state_generic_args> },
+                        quote! { self.state().map(Some) },
+                    )
+                };
+
+                quote! {
+                    /// Takes a timed transition in every region whose active state, or one of its
+                    /// composite states, has a timed transition whose duration has passed at `now`.
+                    ///
+                    /// It will return `Ok(Some(..))` if any region took a timed transition,
+                    /// `Ok(None)` if none was due, or `Err(#error_type_name)` if there was an error
+                    /// in the transition.
+                    pub #is_async fn tick(
+                        &mut self,
+                        #temporary_context
+                        now: #clock_instant
+                    ) -> Result<Option<#tick_result>, #error_type> {
+                        let mut taken = false;
+                        #(taken |= self.#processes_timed(#temporary_context_call now) #process_await?;)*
+                        if !taken {
+                            return Ok(None);
+                        }
+
+                        #result
+                    }
+
+                    /// Returns the earliest time at which a timed transition of the active state
+                    /// of a region, or of one of its composite states, is due, without checking its
+                    /// guard. A timed transition whose guard failed in `tick` is left out until its
+                    /// input state is entered again.
+                    pub fn next_deadline(&self) -> Option<#clock_instant> {
+                        IntoIterator::into_iter([#(#deadlines),*]).flatten().min()
+                    }
+                }
+            };
+
+            let state_machine_code = quote! {
+                /// State machine structure definition.
+                #(#state_machine_attributes)*
+                #visibility struct #state_machine_type_name<#state_lifetimes #generics #context_param: #state_machine_context_type_name <#generic_args> #queue_param> {
+                    #(#fields: Option<#region_types>,)*
+                    #extra_fields
+                    #timed_field
+                    #event_queue_field
+                    context: #context_param
+                }
+
+                impl<#state_lifetimes #generics #context_param: #state_machine_context_type_name <#generic_args> #queue_param> #state_machine_type_name<#state_lifetimes #generic_args #context_param #queue_arg> {
+                    /// Creates a new state machine with the specified starting state of each region.
+                    #[inline(always)]
+                    pub #new_constness fn new(context: #context_param, #(#new_params),*) -> Self {
+                        #new_init
+                    }
+
+                    /// Creates a new state machine with an initial state for each region.
+                    #[inline(always)]
+                    pub #constness fn new_with_state(context: #context_param, #(#fields: #region_types),*) -> Self {
+                        #new_with_state_init
+                    }
+
+                    /// Returns the current state of each region.
+                    #[inline(always)]
+                    pub fn state(&self) -> Result<#states_type_name <'_, #state_lifetimes #state_generic_args>, #error_type> {
+                        Ok(#states_type_name {
+                            #(#fields: self.#fields.as_ref().ok_or_else(|| #error_type_name ::Poisoned)?),*
+                        })
+                    }
+
+                    /// Returns the current context.
+                    #[inline(always)]
+                    pub fn context(&self) -> &#context_param {
+                        &self.context
+                    }
+
+                    /// Returns the current context as a mutable reference.
+                    #[inline(always)]
+                    pub fn context_mut(&mut self) -> &mut #context_param {
+                        &mut self.context
+                    }
+
+                    #process_event_code
+
+                    #terminated_code
+                    #timed_code
+
+                    #process_functions
+                }
+            };
+
+            (context_hooks_code, states_code, state_machine_code)
+        }
+    };
+
+    let completion_limit_error = if sm.completion_transitions.is_empty() {
+        quote! {}
+    } else {
         quote! {
-            /// The error type returned by guard functions.
-            type GuardError: core::fmt::Debug;
+            /// When more completion transitions are taken in a row than allowed by the completion
+            /// limit, which usually means that the completion transitions form a cycle.
+            CompletionLimitReached,
+        }
+    };
+
+    let guard_expression_failed_error = if has_guard_expressions {
+        quote! {
+            /// When an event is processed whose guard expression did not pass. `guard` names the
+            /// guard which decided the result, and `error` holds its error, or `None` if it passed
+            /// while negated with `!`. If several guarded transitions exist for the event, this is
+            /// about the last one tried.
+            GuardExpressionFailed {
+                /// The name of the guard which decided the result.
+                guard: &'static str,
+                /// The error of the guard, or `None` if it passed while negated.
+                error: Option<T>,
+            },
         }
     } else {
         quote! {}
     };
 
-    let guard_error_type = if sm.custom_guard_error {
-        quote! { Self::GuardError }
+    let terminated_error = if sm.final_states.is_empty() {
+        quote! {}
     } else {
-        quote! { () }
+        quote! {
+            /// When an event is processed after a final state was entered.
+            Terminated,
+        }
     };
 
-    let (is_async, is_async_trait) = if sm_is_async {
-        (quote! { async }, quote! { #[smlang::async_trait] })
+    let submachine_rejected_error = if sm.submachines.is_empty() {
+        quote! {}
     } else {
-        (quote! {}, quote! {})
+        quote! {
+            /// When an event forwarded to a submachine is rejected by it.
+            SubmachineRejected,
+        }
     };
 
-    let error_type = if sm.custom_guard_error {
+    let deferred_events_error = if sm.deferred_events.is_empty() {
+        quote! {}
+    } else {
         quote! {
-            #error_type_name<<T as #state_machine_context_type_name>::GuardError>
+            /// When an event is deferred while the queue of deferred events is full.
+            DeferredEventsFull,
         }
-    } else {
-        quote! {#error_type_name}
     };
 
-    let derive_states_list = &sm.derive_states;
-    let derive_events_list = &sm.derive_events;
+    // The context of a state machine with timed transitions provides the clock the entry times of
+    // the states are read from
+    let clock_code = match (&sm.clock_type, sm.timed_transitions.is_empty()) {
+        (Some(clock_type), false) => quote! {
+            /// Returns the clock which the entry times of the states are read from.
+            fn clock(&self) -> &#clock_type;
+        },
+        _ => quote! {},
+    };
+
     // Build the states and events output
     quote! {
         /// This trait outlines the guards and actions that need to be implemented for the state
         /// machine.
         #is_async_trait
-        pub trait #state_machine_context_type_name {
+        #visibility trait #state_machine_context_type_name <#generics> {
             #guard_error
+            #clock_code
             #guard_list
             #action_list
+            #hook_list
 
             /// Called at the beginning of a state machine's `process_event()`. No-op by
             /// default but can be overridden in implementations of a state machine's
             /// `StateMachineContext` trait.
-            fn log_process_event(&self, current_state: & #states_type_name, event: & #events_type_name) {}
+            fn log_process_event(&self, current_state: & #states_type_name <#state_generic_args>, event: & #events_type_name <#event_generic_args>) {}
 
             /// Called after executing a guard during `process_event()`. No-op by
             /// default but can be overridden in implementations of a state machine's
@@ -502,118 +3124,59 @@ pub fn generate_code(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
             /// `StateMachineContext` trait.
             fn log_action(&self, action: &'static str) {}
 
-            /// Called when transitioning to a new state as a result of an event passed to
-            /// `process_event()`. No-op by default but can be overridden in implementations
-            /// of a state machine's `StateMachineContext` trait.
-            fn log_state_change(&self, new_state: & #states_type_name) {}
+            #context_hooks_code
         }
 
-        /// List of auto-generated states.
-        #[allow(missing_docs)]
-        #[derive(#(#derive_states_list),*)]
-        pub enum #states_type_name <#state_lifetimes> { #(#state_list),* }
-
-        /// Manually define PartialEq for #states_type_name based on variant only to address issue-#21
-        impl<#state_lifetimes> PartialEq for #states_type_name <#state_lifetimes> {
-            fn eq(&self, other: &Self) -> bool {
-                use core::mem::discriminant;
-                discriminant(self) == discriminant(other)
-            }
-        }
+        #states_code
 
         /// List of auto-generated events.
         #[allow(missing_docs)]
         #[derive(#(#derive_events_list),*)]
-        pub enum #events_type_name <#event_lifetimes> { #(#event_list),* }
+        #(#events_attributes)*
+        #visibility enum #events_type_name <#event_lifetimes #event_generics> { #(#event_variants),* }
+
+        #target_list
 
         /// Manually define PartialEq for #events_type_name based on variant only to address issue-#21
-        impl<#event_lifetimes> PartialEq for #events_type_name <#event_lifetimes> {
+        impl<#event_lifetimes #event_generics> PartialEq for #events_type_name <#event_lifetimes #event_generic_args> {
             fn eq(&self, other: &Self) -> bool {
                 use core::mem::discriminant;
                 discriminant(self) == discriminant(other)
             }
         }
 
+        impl<#event_lifetimes #event_generics> #events_type_name <#event_lifetimes #event_generic_args> {
+            /// Returns the id of the event, which has no data.
+            pub fn id(&self) -> #event_id_type_name {
+                match self {
+                    #(#event_id_patterns => #event_id_type_name::#event_list),*
+                }
+            }
+        }
+
+        #event_ids_code
+
         /// List of possible errors
         #[derive(Debug)]
-        pub enum #error_type_name <T=()> {
+        #(#error_attributes)*
+        #visibility enum #error_type_name <T=()> {
             /// When an event is processed which should not come in the current state.
             InvalidEvent,
-            /// When an event is processed whose guard did not return `true`.
+            /// When an event is processed whose guard did not return `true`. If several guarded
+            /// transitions exist for the event, this holds the error of the last one tried.
             GuardFailed(T),
+            #guard_expression_failed_error
             /// When the state has an unexpected value.
             ///
             /// This can happen if there is a bug in the code generated by smlang,
             /// or if a guard or action gets panicked.
             Poisoned,
+            #completion_limit_error
+            #deferred_events_error
+            #terminated_error
+            #submachine_rejected_error
         }
 
-        /// State machine structure definition.
-        pub struct #state_machine_type_name<#state_lifetimes T: #state_machine_context_type_name> {
-            state: Option<#states_type_name <#state_lifetimes>>,
-            context: T
-        }
-
-        impl<#state_lifetimes T: #state_machine_context_type_name> #state_machine_type_name<#state_lifetimes T> {
-            /// Creates a new state machine with the specified starting state.
-            #[inline(always)]
-            #new_sm_code
-
-            /// Creates a new state machine with an initial state.
-            #[inline(always)]
-            pub const fn new_with_state(context: T, initial_state: #states_type_name <#state_lifetimes>) -> Self {
-                #state_machine_type_name {
-                    state: Some(initial_state),
-                    context
-                }
-            }
-
-            /// Returns the current state.
-            #[inline(always)]
-            pub fn state(&self) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
-                self.state.as_ref().ok_or_else(|| #error_type_name ::Poisoned)
-            }
-
-            /// Returns the current context.
-            #[inline(always)]
-            pub fn context(&self) -> &T {
-                &self.context
-            }
-
-            /// Returns the current context as a mutable reference.
-            #[inline(always)]
-            pub fn context_mut(&mut self) -> &mut T {
-                &mut self.context
-            }
-
-            /// Process an event.
-            ///
-            /// It will return `Ok(&NextState)` if the transition was successful, or `Err(#error_type_name)`
-            /// if there was an error in the transition.
-            pub #is_async fn process_event <#event_unique_lifetimes> (
-                &mut self,
-                #temporary_context
-                mut event: #events_type_name <#event_lifetimes>
-            ) -> Result<&#states_type_name <#state_lifetimes>, #error_type> {
-                self.context.log_process_event(self.state()?, &event);
-                match self.state.take().ok_or_else(|| #error_type_name ::Poisoned)? {
-                    #(#states_type_name::#in_states => match event {
-                        #(#events_type_name::#events => {
-                            #code_blocks
-
-                            self.state()
-                        }),*
-                        _ => {
-                            self.state = Some(#states_type_name::#in_states);
-                            Err(#error_type_name ::InvalidEvent)
-                        }
-                    }),*
-                    state => {
-                        self.state = Some(state);
-                        Err(#error_type_name ::InvalidEvent)
-                    }
-                }
-            }
-        }
+        #state_machine_code
     }
 }
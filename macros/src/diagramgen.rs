@@ -1,52 +1,189 @@
-use crate::parser::*;
+use crate::parser::{event::EventMapping, output_state::History, *};
+use quote::ToTokens;
+use syn::Ident;
 
 /// Generates a string containing 'dot' syntax to generate a statemachine diagram with graphviz.
 pub fn generate_diagram(sm: &ParsedStateMachine) -> String {
     let transitions = &sm.states_events_mapping;
 
-    let mut diagram_states = sm.states.iter().map(|s| s.0).collect::<Vec<&String>>();
-    diagram_states.sort();
-    let diagram_states = diagram_states.into_iter();
     let mut diagram_events = vec![];
     let mut diagram_transitions = vec![];
-    for (state, event) in transitions {
-        for (_event, eventmapping) in event {
+    for (state, events) in transitions {
+        for (event, eventmapping) in events
+            .iter()
+            .flat_map(|(event, mappings)| mappings.iter().map(move |mapping| (event, mapping)))
+        {
             diagram_events.push((
-                eventmapping.event.to_string(),
+                event.clone(),
                 eventmapping
                     .guard
                     .as_ref()
-                    .map(|i| i.ident.to_string())
-                    .unwrap_or_else(|| "_".to_string()),
-                eventmapping
-                    .action
-                    .as_ref()
-                    .map(|i| i.ident.to_string())
+                    .map(|guard| guard.to_string())
                     .unwrap_or_else(|| "_".to_string()),
+                actions(eventmapping).unwrap_or_else(|| "_".to_string()),
             ));
-            diagram_transitions.push((
-                state,
-                eventmapping.out_state.to_string(),
-                eventmapping.event.to_string(),
-            ));
+
+            // Transitions of composite states are drawn once, from the border of the cluster
+            // holding their substates, instead of once for every substate
+            let in_state = eventmapping.in_state.to_string();
+            let ltail = if sm.composite_states.contains_key(&in_state) {
+                format!(" ltail=cluster_{}", in_state)
+            } else if &in_state != state {
+                continue;
+            } else {
+                String::new()
+            };
+            // Internal transitions are drawn as dashed loops, as they do not leave the state
+            if eventmapping.internal {
+                diagram_transitions.push((
+                    sm.initial_leaf(&eventmapping.in_state).to_string(),
+                    sm.initial_leaf(&eventmapping.in_state).to_string(),
+                    event.clone(),
+                    " style=dashed".to_string(),
+                ));
+                continue;
+            }
+
+            let in_state = sm.initial_leaf(&eventmapping.in_state).to_string();
+
+            for (out_state, lhead) in targets(sm, eventmapping) {
+                // The transitions to a choice end at its diamond, from which its branches are
+                // drawn
+                match eventmapping.choice {
+                    Some(choice) => {
+                        diagram_transitions.push((
+                            in_state.clone(),
+                            choice_node(choice),
+                            event.clone(),
+                            ltail.clone(),
+                        ));
+                        diagram_transitions.push((
+                            choice_node(choice),
+                            out_state,
+                            format!("\"{}\"", guard_action_label(eventmapping)),
+                            lhead,
+                        ));
+                    }
+                    None => diagram_transitions.push((
+                        in_state.clone(),
+                        out_state,
+                        event.clone(),
+                        format!("{}{}", ltail, lhead),
+                    )),
+                }
+            }
         }
     }
+
+    // Completion transitions have no event, so they are labeled with their guard and action, and
+    // timed transitions with their duration as well
+    for eventmapping in sm
+        .completion_transitions
+        .values()
+        .chain(sm.timed_transitions.values())
+        .flatten()
+    {
+        let after = eventmapping
+            .after
+            .as_ref()
+            .map(|after| format!("after({})", after.to_token_stream()).replace('"', "\\\""))
+            .unwrap_or_default();
+
+        let in_state = eventmapping.in_state.to_string();
+        let ltail = if sm.composite_states.contains_key(&in_state) {
+            format!(" ltail=cluster_{}", in_state)
+        } else {
+            String::new()
+        };
+        let in_state = sm.initial_leaf(&eventmapping.in_state).to_string();
+
+        for (out_state, lhead) in targets(sm, eventmapping) {
+            match eventmapping.choice {
+                Some(choice) => {
+                    let label: Vec<_> = vec![
+                        after.clone(),
+                        action_list(&eventmapping.actions[..eventmapping.choice_actions])
+                            .map(|actions| format!("/ {}", actions))
+                            .unwrap_or_default(),
+                    ]
+                    .into_iter()
+                    .filter(|part| !part.is_empty())
+                    .collect();
+
+                    diagram_transitions.push((
+                        in_state.clone(),
+                        choice_node(choice),
+                        format!("\"{}\"", label.join(" ")),
+                        ltail.clone(),
+                    ));
+                    diagram_transitions.push((
+                        choice_node(choice),
+                        out_state,
+                        format!("\"{}\"", guard_action_label(eventmapping)),
+                        lhead,
+                    ));
+                }
+                None => {
+                    let label: Vec<_> = vec![after.clone(), guard_action_label(eventmapping)]
+                        .into_iter()
+                        .filter(|part| !part.is_empty())
+                        .collect();
+
+                    diagram_transitions.push((
+                        in_state.clone(),
+                        out_state,
+                        format!("\"{}\"", label.join(" ")),
+                        format!("{}{}", ltail, lhead),
+                    ));
+                }
+            }
+        }
+    }
+
+    // Choice pseudo-states are drawn as diamonds
+    let mut choices: Vec<_> = sm
+        .all_transitions()
+        .filter_map(|eventmapping| eventmapping.choice)
+        .collect();
+    choices.sort_unstable();
+    choices.dedup();
+    let choice_string = choices
+        .into_iter()
+        .map(|choice| format!("\t{} [shape=diamond label=\"\"]", choice_node(choice)))
+        .collect::<Vec<String>>();
+
     // Sorting is needed to ensure stable (ie not changing between runs of
     // the same sm code) dot file contents. This is needed to ensure stable
     // hash sum, which is used to name unnamed diagrams. If done without sorting,
     // the output is polluted with lots of similar svg files with different names.
     // This ensures that new files will only occur upon changing the structure of the code.
     diagram_events.sort();
+    diagram_events.dedup();
     diagram_transitions.sort();
+    diagram_transitions.dedup();
 
-    let state_string = diagram_states
-        .map(|s| {
-            format!(
-                "\t{} [shape=box color=\"red\" fillcolor=\"#ffbb33\" style=filled]",
-                s
-            )
-        })
-        .collect::<Vec<String>>();
+    // The regions of a state machine are drawn as clusters holding their states, each with its
+    // own starting point
+    let state_string = match &sm.starting_state {
+        Some(starting_state) => format!(
+            "    s [shape=circle size=2 color=\"black\" style=filled]\n\n    s -> {}\n{}",
+            starting_state,
+            generate_states(sm, 1)
+        ),
+        None => sm
+            .regions
+            .iter()
+            .map(|(name, region)| {
+                format!(
+                    "\tsubgraph cluster_region_{0} {{\n\t\tlabel=\"{0}\";\n\t\tstyle=dashed;\n\t\ts_{0} [shape=circle size=2 color=\"black\" style=filled]\n\t\ts_{0} -> {1}\n{2}\n\t}}",
+                    name,
+                    region.starting_state.as_ref().unwrap(),
+                    generate_states(region, 2)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    };
     let event_string = diagram_events
         .iter()
         .map(|s| {
@@ -58,26 +195,212 @@ pub fn generate_diagram(sm: &ParsedStateMachine) -> String {
         .collect::<Vec<String>>();
     let transition_string = diagram_transitions
         .iter()
-        .map(|t| format!("\t{0} -> {1} [color=blue label={2}];", t.0, t.1, t.2))
+        .map(|t| {
+            format!(
+                "\t{0} -> {1} [color=blue label={2}{3}];",
+                t.0, t.1, t.2, t.3
+            )
+        })
         .collect::<Vec<String>>();
 
     format!(
         "digraph G {{
     rankdir=\"LR\";
+    compound=true;
     node [fontname=Arial];
     edge [fontname=Arial];
-    s [shape=circle size=2 color=\"black\" style=filled]
-    
-    s -> {}
+{}
 {}
 
 {}
 
 {}
 }}",
-        sm.starting_state.to_string(),
-        state_string.join("\n"),
+        state_string,
+        choice_string.join("\n"),
         event_string.join("\n"),
         transition_string.join("\n")
     )
 }
+
+// helper function for drawing the states which are not nested in a composite state, composite
+// states are drawn as clusters holding their substates
+fn generate_states(sm: &ParsedStateMachine, depth: usize) -> String {
+    let mut top_level_states = sm
+        .states
+        .values()
+        .chain(sm.composite_states.values())
+        .filter(|s| !sm.parents.contains_key(&s.to_string()))
+        .collect::<Vec<&Ident>>();
+    top_level_states.sort_by_key(|s| s.to_string());
+
+    top_level_states
+        .into_iter()
+        .map(|s| generate_state(sm, s, depth))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// helper function for drawing a state, or a cluster with the substates of a composite state
+fn generate_state(sm: &ParsedStateMachine, state: &Ident, depth: usize) -> String {
+    let indent = "\t".repeat(depth);
+
+    if !sm.composite_states.contains_key(&state.to_string()) {
+        // The events deferred by a state, and the submachine it holds, are listed below its name
+        let mut details = Vec::new();
+        if let Some(deferred) = sm.deferred_events.get(&state.to_string()) {
+            details.push(format!(
+                "defer: {}",
+                deferred
+                    .iter()
+                    .map(|event| event.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        if let Some(submachine) = sm.submachines.get(&state.to_string()) {
+            details.push(format!("submachine: {}", submachine.ident));
+        }
+        let label = if details.is_empty() {
+            String::new()
+        } else {
+            format!(" label=\"{}\\n{}\"", state, details.join("\\n"))
+        };
+
+        // Final states are drawn with a double border
+        let peripheries = if sm.final_states.contains(state) {
+            " peripheries=2"
+        } else {
+            ""
+        };
+
+        return format!(
+            "{}{} [shape=box color=\"red\" fillcolor=\"#ffbb33\" style=filled{}{}]",
+            indent, state, peripheries, label
+        );
+    }
+
+    let mut substates = sm
+        .states
+        .values()
+        .chain(sm.composite_states.values())
+        .filter(|s| sm.parents.get(&s.to_string()) == Some(state))
+        .collect::<Vec<&Ident>>();
+    substates.sort_by_key(|s| s.to_string());
+
+    let mut substate_string = substates
+        .into_iter()
+        .map(|s| generate_state(sm, s, depth + 1))
+        .collect::<Vec<String>>();
+
+    // History pseudo-states are drawn inside the composite state they belong to
+    let mut histories = sm
+        .all_transitions()
+        .filter(|eventmapping| &eventmapping.out_state == state)
+        .filter_map(|eventmapping| eventmapping.history)
+        .collect::<Vec<History>>();
+    histories.sort_by_key(|history| *history == History::Deep);
+    histories.dedup();
+
+    substate_string.extend(histories.into_iter().map(|history| {
+        format!(
+            "{}\t{} [shape=circle label=\"{}\"]",
+            indent,
+            history_node(state, history),
+            match history {
+                History::Shallow => "H",
+                History::Deep => "H*",
+            }
+        )
+    }));
+
+    format!(
+        "{0}subgraph cluster_{1} {{\n{0}\tlabel=\"{1}\";\n{2}\n{0}}}",
+        indent,
+        state,
+        substate_string.join("\n")
+    )
+}
+
+// helper function for labeling the actions of a transition, with chained actions as `(a, b, c)`
+fn actions(eventmapping: &EventMapping) -> Option<String> {
+    action_list(&eventmapping.actions)
+}
+
+// helper function for labeling a list of actions, with chained actions as `(a, b, c)`
+fn action_list(actions: &[AsyncIdent]) -> Option<String> {
+    let actions: Vec<_> = actions
+        .iter()
+        .map(|action| action.ident.to_string())
+        .collect();
+    match actions.len() {
+        0 => None,
+        1 => Some(actions[0].clone()),
+        _ => Some(format!("({})", actions.join(", "))),
+    }
+}
+
+// helper function for labeling a transition with its guard and actions, as `[guard] / action`.
+// The branches of a choice are labeled with their own actions only, as the actions run before the
+// guards label the transition to the choice.
+fn guard_action_label(eventmapping: &EventMapping) -> String {
+    let guard = match &eventmapping.guard {
+        Some(guard) => format!("[{}]", guard),
+        None if eventmapping.choice.is_some() => "[else]".to_string(),
+        None => String::new(),
+    };
+    let action = action_list(&eventmapping.actions[eventmapping.choice_actions..])
+        .map(|actions| format!("/ {}", actions))
+        .unwrap_or_default();
+
+    vec![guard, action]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+// helper function for naming the node of a choice pseudo-state
+fn choice_node(choice: usize) -> String {
+    format!("choice_{}", choice)
+}
+
+// helper function for getting the nodes a transition points at, and the clusters its edges end at
+// when entering a composite state. A transition whose action chooses the target has an edge per
+// target.
+fn targets(sm: &ParsedStateMachine, eventmapping: &EventMapping) -> Vec<(String, String)> {
+    if let Some(history) = eventmapping.history {
+        return vec![(
+            history_node(&eventmapping.out_state, history),
+            String::new(),
+        )];
+    }
+
+    let out_states = if eventmapping.targets.is_empty() {
+        std::slice::from_ref(&eventmapping.out_state)
+    } else {
+        &eventmapping.targets[..]
+    };
+
+    out_states
+        .iter()
+        .map(|out_state| {
+            if sm.composite_states.contains_key(&out_state.to_string()) {
+                (
+                    sm.initial_leaf(out_state).to_string(),
+                    format!(" lhead=cluster_{}", out_state),
+                )
+            } else {
+                (out_state.to_string(), String::new())
+            }
+        })
+        .collect()
+}
+
+// helper function for naming the node of a history pseudo-state
+fn history_node(state: &Ident, history: History) -> String {
+    match history {
+        History::Shallow => format!("{}_H", state),
+        History::Deep => format!("{}_H_deep", state),
+    }
+}
@@ -38,7 +38,7 @@ pub fn statemachine(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
 
                 // Start the 'dot' process.
                 let mut process = std::process::Command::new("dot")
-                    .args(&["-Tsvg", "-o", &format!("statemachine_{diagram_name}.svg")])
+                    .args(["-Tsvg", "-o", &format!("statemachine_{diagram_name}.svg")])
                     .stdin(std::process::Stdio::piped())
                     .spawn()
                     .expect("Failed to execute 'dot'. Are you sure graphviz is installed?");
@@ -65,7 +65,9 @@ pub fn statemachine(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 return e.to_compile_error().into();
             }
 
-            codegen::generate_code(&sm).into()
+            let mut output = codegen::generate_code(&sm);
+            output.extend(validation::warnings(&sm));
+            output.into()
         }
         Err(error) => error.to_compile_error().into(),
     }
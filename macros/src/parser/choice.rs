@@ -0,0 +1,141 @@
+use super::guard::GuardExpression;
+use super::output_state::OutputState;
+use super::transition::parse_actions;
+use super::AsyncIdent;
+use syn::{braced, bracketed, parse, token, Token};
+
+/// A branch of a choice pseudo-state, taken if its guard passes, or if it is the `else` branch.
+#[derive(Debug, Clone)]
+pub struct ChoiceBranch {
+    pub guard: Option<GuardExpression>,
+    pub actions: Vec<AsyncIdent>,
+    pub out_state: OutputState,
+}
+
+/// A choice pseudo-state, given as `?{ [guard] => Target, else => Other }`, whose branches are
+/// tried in order.
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub branches: Vec<ChoiceBranch>,
+}
+
+impl parse::Parse for ChoiceBranch {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let guard = if input.peek(token::Bracket) {
+            let content;
+            bracketed!(content in input);
+            Some(content.parse::<GuardExpression>()?)
+        } else if input.parse::<Token![else]>().is_ok() {
+            None
+        } else {
+            return Err(parse::Error::new(
+                input.span(),
+                "The branches of a choice need a guard, or `else` for the last branch.",
+            ));
+        };
+
+        // Possible action, run once the branch is chosen
+        let actions = if input.parse::<Token![/]>().is_ok() {
+            parse_actions(input)?
+        } else {
+            Vec::new()
+        };
+
+        input.parse::<Token![=>]>()?;
+        let out_state = OutputState::parse_target(input)?;
+
+        Ok(Self {
+            guard,
+            actions,
+            out_state,
+        })
+    }
+}
+
+impl parse::Parse for Choice {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![?]>()?;
+        let content;
+        let braces = braced!(content in input);
+
+        let mut branches: Vec<ChoiceBranch> = Vec::new();
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            // Nothing after the `else` branch could ever be reached
+            if let Some(branch) = branches.last() {
+                if branch.guard.is_none() {
+                    return Err(parse::Error::new(
+                        branch.out_state.ident.span(),
+                        "The `else` branch must be the last branch of a choice.",
+                    ));
+                }
+            }
+
+            branches.push(content.parse()?);
+
+            // No comma at end of line, no more branches
+            if content.is_empty() {
+                break;
+            }
+
+            content.parse::<Token![,]>()?;
+        }
+
+        if branches.is_empty() {
+            return Err(parse::Error::new(
+                braces.span,
+                "A choice needs at least one branch.",
+            ));
+        }
+
+        Ok(Self { branches })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn branches() {
+        let choice: Choice = parse_quote! {
+            ?{
+                [has_rfid] => Authorizing,
+                [free_mode && !locked] / start_session => Charging(Session),
+                else / (log, reject) => Rejected!,
+            }
+        };
+
+        assert_eq!(choice.branches.len(), 3);
+        assert_eq!(choice.branches[0].out_state.ident, "Authorizing");
+        assert_eq!(
+            choice.branches[1].guard.as_ref().unwrap().to_string(),
+            "free_mode && !locked"
+        );
+        assert!(choice.branches[1].out_state.data_type.is_some());
+        assert!(choice.branches[2].guard.is_none());
+        assert_eq!(choice.branches[2].actions.len(), 2);
+        assert!(choice.branches[2].out_state.is_final);
+    }
+
+    #[test]
+    #[should_panic(expected = "The `else` branch must be the last branch of a choice.")]
+    fn else_before_guarded_branch() {
+        let _: Choice = parse_quote! {
+            ?{ else => Rejected, [has_rfid] => Authorizing }
+        };
+    }
+
+    #[test]
+    #[should_panic(expected = "The branches of a choice need a guard")]
+    fn branch_without_guard() {
+        let _: Choice = parse_quote! {
+            ?{ [has_rfid] => Authorizing, Charging }
+        };
+    }
+}
@@ -1,26 +1,66 @@
-use crate::parser::AsyncIdent;
-use syn::{parenthesized, parse, spanned::Spanned, token, Ident, Token, Type};
+use crate::parser::{guard::GuardExpression, output_state::History, parse_docs, AsyncIdent};
+use syn::{parenthesized, parse, spanned::Spanned, token, Attribute, Expr, Ident, Token, Type};
 
 #[derive(Debug, Clone)]
 pub struct Event {
+    pub wildcard: bool,
     pub ident: Ident,
     pub data_type: Option<Type>,
 }
 
+/// The doc comments of an event, given in the `events` section of the DSL.
 #[derive(Debug)]
+pub struct EventDefinition {
+    pub docs: Vec<Attribute>,
+    pub ident: Ident,
+}
+
+#[derive(Debug, Clone)]
 pub struct EventMapping {
+    /// The doc comments of the transition
+    pub docs: Vec<Attribute>,
     pub in_state: Ident,
-    pub event: Ident,
-    pub guard: Option<AsyncIdent>,
-    pub action: Option<AsyncIdent>,
+    pub event: Option<Ident>,
+    pub after: Option<Expr>,
+    pub guard: Option<GuardExpression>,
+    pub actions: Vec<AsyncIdent>,
     pub out_state: Ident,
+    pub history: Option<History>,
+    pub internal: bool,
+    /// The choice pseudo-state the transition is a branch of
+    pub choice: Option<usize>,
+    /// The number of actions at the start of `actions` which are given before the choice, and run
+    /// once before the guards of its branches
+    pub choice_actions: usize,
+    /// The states the action chooses from, the first one is the output state
+    pub targets: Vec<Ident>,
+    /// Whether the transition was added for a wildcard input state
+    pub wildcard: bool,
+}
+
+impl parse::Parse for EventDefinition {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        Ok(Self {
+            docs: parse_docs(input)?,
+            ident: input.parse()?,
+        })
+    }
 }
 
 impl parse::Parse for Event {
     fn parse(input: parse::ParseStream) -> syn::Result<Self> {
         // Event
         input.parse::<Token![+]>()?;
-        let ident: Ident = input.parse()?;
+
+        // check to see if this is a wildcard event, which is denoted with "underscore"
+        let underscore = input.parse::<Token![_]>();
+        let wildcard = underscore.is_ok();
+
+        let ident: Ident = if let Ok(underscore) = underscore {
+            underscore.into()
+        } else {
+            input.parse()?
+        };
 
         // Possible type on the event
         let data_type = if input.peek(token::Paren) {
@@ -28,6 +68,14 @@ impl parse::Parse for Event {
             parenthesized!(content in input);
             let input: Type = content.parse()?;
 
+            // Wildcards match any event, so their action gets the whole event instead
+            if wildcard {
+                return Err(parse::Error::new(
+                    input.span(),
+                    "Wildcard events cannot have data associated with it.",
+                ));
+            }
+
             // Check so the type is supported
             match &input {
                 Type::Array(_)
@@ -49,6 +97,56 @@ impl parse::Parse for Event {
             None
         };
 
-        Ok(Self { ident, data_type })
+        Ok(Self {
+            wildcard,
+            ident,
+            data_type,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn event_definition() {
+        let definition: EventDefinition = parse_quote! {
+            /// The cable was plugged in
+            Plug
+        };
+
+        assert_eq!(definition.ident, "Plug");
+        assert_eq!(definition.docs.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only doc comments can be given here.")]
+    fn event_definition_with_attribute() {
+        let _: EventDefinition = parse_quote! {
+            #[cfg(test)]
+            Plug
+        };
+    }
+
+    #[test]
+    fn wildcard() {
+        let event: Event = parse_quote! {
+            + _
+        };
+
+        assert!(event.wildcard);
+        assert_eq!(event.ident, "_");
+        assert!(event.data_type.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Wildcard events cannot have data associated with it.")]
+    fn wildcard_with_data() {
+        let _: Event = parse_quote! {
+            + _(u8)
+        };
     }
 }
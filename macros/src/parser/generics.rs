@@ -0,0 +1,225 @@
+use proc_macro2::{Span, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{parse, spanned::Spanned, GenericParam, Generics, Ident, Type};
+
+/// The type and const parameters declared with `generics`
+#[derive(Default, Debug, Clone)]
+pub struct GenericParameters {
+    params: Vec<GenericParam>,
+}
+
+impl GenericParameters {
+    pub fn new() -> GenericParameters {
+        GenericParameters { params: Vec::new() }
+    }
+
+    pub fn from_generics(generics: Generics) -> Result<GenericParameters, parse::Error> {
+        let mut params: Vec<GenericParam> = Vec::new();
+
+        for param in generics.params {
+            match &param {
+                GenericParam::Lifetime(lifetime) => {
+                    return Err(parse::Error::new(
+                        lifetime.span(),
+                        "Lifetimes are taken from the data of the states and events, only type and const parameters can be declared.",
+                    ))
+                }
+                GenericParam::Type(syn::TypeParam {
+                    eq_token: Some(eq_token),
+                    ..
+                })
+                | GenericParam::Const(syn::ConstParam {
+                    eq_token: Some(eq_token),
+                    ..
+                }) => {
+                    return Err(parse::Error::new(
+                        eq_token.span(),
+                        "Generic parameters of a state machine can not have defaults.",
+                    ))
+                }
+                _ => (),
+            }
+
+            if params.iter().any(|p| ident(p) == ident(&param)) {
+                return Err(parse::Error::new(
+                    ident(&param).span(),
+                    "Generic parameter declared multiple times, remove duplicates.",
+                ));
+            }
+            params.push(param);
+        }
+
+        Ok(GenericParameters { params })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.params.iter().any(|param| ident(param) == name)
+    }
+
+    /// Returns the parameters used by any of the types, along with the parameters used in the
+    /// bounds of those, as a type may only take the parameters it uses
+    pub fn used_by<'a>(&self, types: impl IntoIterator<Item = &'a Type>) -> GenericParameters {
+        let mut tokens = TokenStream::new();
+        for data_type in types {
+            data_type.to_tokens(&mut tokens);
+        }
+
+        let mut used: Vec<&GenericParam> = Vec::new();
+        loop {
+            let unused: Vec<_> = self
+                .params
+                .iter()
+                .filter(|param| !used.contains(param))
+                .filter(|param| mentions(tokens.clone(), ident(param)))
+                .collect();
+            if unused.is_empty() {
+                break;
+            }
+
+            for param in unused {
+                bounds(param).to_tokens(&mut tokens);
+                used.push(param);
+            }
+        }
+
+        GenericParameters {
+            params: self
+                .params
+                .iter()
+                .filter(|param| used.contains(param))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// The parameters as arguments of the type declaring them, such as `T, N,`
+    pub fn args(&self) -> TokenStream {
+        if self.is_empty() {
+            return quote! {};
+        }
+
+        let idents = self.params.iter().map(ident);
+        quote! { #(#idents),* ,}
+    }
+
+    /// The type parameters, which have to be used by the fields of a struct
+    pub fn type_params(&self) -> impl Iterator<Item = &Ident> {
+        self.params.iter().filter_map(|param| match param {
+            GenericParam::Type(param) => Some(&param.ident),
+            _ => None,
+        })
+    }
+
+    /// Returns an identifier named `name` which does not clash with the parameters, to name the
+    /// parameters added by the generated code
+    pub fn unused_ident(&self, name: &str) -> Ident {
+        let mut unused = name.to_string();
+        let mut index = 0;
+        while self.contains(&unused) {
+            index += 1;
+            unused = format!("{}{}", name, index);
+        }
+
+        Ident::new(&unused, Span::call_site())
+    }
+}
+
+impl ToTokens for GenericParameters {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        if self.is_empty() {
+            return;
+        }
+
+        let params = &self.params;
+        tokens.extend(quote! { #(#params),* ,});
+    }
+}
+
+// helper function for getting the name of a parameter
+fn ident(param: &GenericParam) -> &Ident {
+    match param {
+        GenericParam::Type(param) => &param.ident,
+        GenericParam::Const(param) => &param.ident,
+        GenericParam::Lifetime(param) => &param.lifetime.ident,
+    }
+}
+
+// helper function for getting the bounds of a type parameter, and the type of a const parameter
+fn bounds(param: &GenericParam) -> TokenStream {
+    match param {
+        GenericParam::Type(param) => param.bounds.to_token_stream(),
+        GenericParam::Const(param) => param.ty.to_token_stream(),
+        GenericParam::Lifetime(_) => TokenStream::new(),
+    }
+}
+
+// helper function for checking if an identifier is used anywhere in a token stream
+fn mentions(tokens: TokenStream, name: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => &ident == name,
+        TokenTree::Group(group) => mentions(group.stream(), name),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn used_by() {
+        let generics = GenericParameters::from_generics(parse_quote! {
+            <T: Transport, U, const N: usize>
+        })
+        .unwrap();
+
+        let types: Vec<Type> = vec![parse_quote! { Buffer<N> }, parse_quote! { T::Handle }];
+        let used = generics.used_by(&types);
+
+        assert_eq!(used.args().to_string(), "T , N ,");
+        assert_eq!(used.type_params().collect::<Vec<_>>(), ["T"]);
+    }
+
+    #[test]
+    fn used_by_bounds() {
+        let generics = GenericParameters::from_generics(parse_quote! {
+            <U, T: Transport<U>>
+        })
+        .unwrap();
+
+        let types: Vec<Type> = vec![parse_quote! { T }];
+        assert_eq!(generics.used_by(&types).args().to_string(), "U , T ,");
+    }
+
+    #[test]
+    fn unused_ident() {
+        let generics = GenericParameters::from_generics(parse_quote! { <T, T1> }).unwrap();
+
+        assert_eq!(generics.unused_ident("T"), "T2");
+        assert_eq!(generics.unused_ident("C"), "C");
+    }
+
+    #[test]
+    #[should_panic(expected = "Lifetimes are taken from the data of the states and events")]
+    fn lifetime() {
+        GenericParameters::from_generics(parse_quote! { <'a, T> }).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Generic parameters of a state machine can not have defaults.")]
+    fn default() {
+        GenericParameters::from_generics(parse_quote! { <const N: usize = 4> }).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Generic parameter declared multiple times, remove duplicates.")]
+    fn duplicate() {
+        GenericParameters::from_generics(parse_quote! { <T, T: Clone> }).unwrap();
+    }
+}
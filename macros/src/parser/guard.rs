@@ -0,0 +1,136 @@
+use super::AsyncIdent;
+use std::fmt;
+use syn::{parenthesized, parse, token, Ident, Token};
+
+/// A guard of a transition, combining named guards with `&&`, `||` and `!`.
+#[derive(Debug, Clone)]
+pub enum GuardExpression {
+    Guard(AsyncIdent),
+    Not(Box<GuardExpression>),
+    And(Box<GuardExpression>, Box<GuardExpression>),
+    Or(Box<GuardExpression>, Box<GuardExpression>),
+}
+
+impl GuardExpression {
+    /// Lists the named guards of the expression, in the order they are evaluated.
+    pub fn guards(&self) -> Vec<&AsyncIdent> {
+        match self {
+            GuardExpression::Guard(guard) => vec![guard],
+            GuardExpression::Not(expression) => expression.guards(),
+            GuardExpression::And(left, right) | GuardExpression::Or(left, right) => {
+                let mut guards = left.guards();
+                guards.extend(right.guards());
+                guards
+            }
+        }
+    }
+
+    // helper function for parsing the operands of `||`, which bind tighter
+    fn parse_and(input: parse::ParseStream) -> parse::Result<Self> {
+        let mut expression = Self::parse_unary(input)?;
+        while input.peek(Token![&&]) {
+            input.parse::<Token![&&]>()?;
+            let right = Self::parse_unary(input)?;
+            expression = GuardExpression::And(Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+
+    // helper function for parsing a negated guard, a parenthesized expression, or a named guard
+    fn parse_unary(input: parse::ParseStream) -> parse::Result<Self> {
+        if input.peek(Token![!]) {
+            input.parse::<Token![!]>()?;
+            Ok(GuardExpression::Not(Box::new(Self::parse_unary(input)?)))
+        } else if input.peek(token::Paren) {
+            let content;
+            parenthesized!(content in input);
+            content.parse()
+        } else {
+            let is_async = input.parse::<token::Async>().is_ok();
+            let ident: Ident = input.parse()?;
+            Ok(GuardExpression::Guard(AsyncIdent { ident, is_async }))
+        }
+    }
+}
+
+impl parse::Parse for GuardExpression {
+    fn parse(input: parse::ParseStream) -> parse::Result<Self> {
+        let mut expression = Self::parse_and(input)?;
+        while input.peek(Token![||]) {
+            input.parse::<Token![||]>()?;
+            let right = Self::parse_and(input)?;
+            expression = GuardExpression::Or(Box::new(expression), Box::new(right));
+        }
+
+        Ok(expression)
+    }
+}
+
+impl fmt::Display for GuardExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GuardExpression::Guard(guard) => write!(f, "{}", guard.ident),
+            GuardExpression::Not(expression) => match **expression {
+                GuardExpression::Guard(_) | GuardExpression::Not(_) => write!(f, "!{}", expression),
+                _ => write!(f, "!({})", expression),
+            },
+            GuardExpression::And(left, right) => {
+                for (index, operand) in [left, right].iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " && ")?;
+                    }
+                    match ***operand {
+                        GuardExpression::Or(..) => write!(f, "({})", operand)?,
+                        _ => write!(f, "{}", operand)?,
+                    }
+                }
+                Ok(())
+            }
+            GuardExpression::Or(left, right) => write!(f, "{} || {}", left, right),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn single_guard() {
+        let expression: GuardExpression = parse_quote! { async cable_ok };
+
+        match expression {
+            GuardExpression::Guard(guard) => {
+                assert_eq!(guard.ident, "cable_ok");
+                assert!(guard.is_async);
+            }
+            _ => panic!("expected a single guard"),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let expression: GuardExpression = parse_quote! { a || b && !c };
+
+        assert!(matches!(expression, GuardExpression::Or(..)));
+        assert_eq!(expression.to_string(), "a || b && !c");
+
+        let guards: Vec<_> = expression
+            .guards()
+            .iter()
+            .map(|g| g.ident.to_string())
+            .collect();
+        assert_eq!(guards, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn parentheses() {
+        let expression: GuardExpression = parse_quote! { (a || b) && !(c && d) };
+
+        assert!(matches!(expression, GuardExpression::And(..)));
+        assert_eq!(expression.to_string(), "(a || b) && !(c && d)");
+    }
+}
@@ -1,9 +1,13 @@
-use syn::{parenthesized, parse, spanned::Spanned, token, Ident, Token, Type};
+use syn::{
+    parenthesized, parse, punctuated::Punctuated, spanned::Spanned, token, Ident, Token, Type,
+};
 
 #[derive(Debug, Clone)]
 pub struct InputState {
     pub start: bool,
     pub wildcard: bool,
+    /// The states a wildcard does not apply to, given as `_ - State` or `_ - (State1 | State2)`
+    pub exclusions: Vec<Ident>,
     pub ident: Ident,
     pub data_type: Option<Type>,
 }
@@ -32,6 +36,38 @@ impl parse::Parse for InputState {
             input.parse()?
         };
 
+        // Possible states excluded from the wildcard
+        let mut exclusions: Vec<Ident> = Vec::new();
+        if input.peek(Token![-]) {
+            let minus = input.parse::<Token![-]>()?;
+            if !wildcard {
+                return Err(parse::Error::new(
+                    minus.span(),
+                    "Only wildcards can exclude states.",
+                ));
+            }
+
+            let excluded: Vec<Ident> = if input.peek(token::Paren) {
+                let content;
+                parenthesized!(content in input);
+                Punctuated::<Ident, Token![|]>::parse_separated_nonempty(&content)?
+                    .into_iter()
+                    .collect()
+            } else {
+                vec![input.parse()?]
+            };
+
+            for state in excluded {
+                if exclusions.contains(&state) {
+                    return Err(parse::Error::new(
+                        state.span(),
+                        "State excluded multiple times, remove duplicates.",
+                    ));
+                }
+                exclusions.push(state);
+            }
+        }
+
         // Possible type on the input state
         let data_type = if input.peek(token::Paren) {
             let content;
@@ -70,6 +106,7 @@ impl parse::Parse for InputState {
         Ok(Self {
             start,
             wildcard,
+            exclusions,
             ident,
             data_type,
         })
@@ -128,6 +165,38 @@ mod tests {
         assert!(wildcard.data_type.is_none());
     }
 
+    #[test]
+    fn wildcard_with_exclusions() {
+        let wildcard: InputState = parse_quote! {
+            _ - (Updating | Flashing)
+        };
+
+        assert!(wildcard.wildcard);
+        assert_eq!(wildcard.exclusions, ["Updating", "Flashing"]);
+
+        let wildcard: InputState = parse_quote! {
+            _ - Updating
+        };
+
+        assert_eq!(wildcard.exclusions, ["Updating"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only wildcards can exclude states.")]
+    fn exclusion_without_wildcard() {
+        let _: InputState = parse_quote! {
+            State1 - State2
+        };
+    }
+
+    #[test]
+    #[should_panic(expected = "State excluded multiple times, remove duplicates.")]
+    fn duplicate_exclusion() {
+        let _: InputState = parse_quote! {
+            _ - (Updating | Updating)
+        };
+    }
+
     #[test]
     fn start() {
         let start: InputState = parse_quote! {
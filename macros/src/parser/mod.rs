@@ -1,23 +1,57 @@
+pub mod choice;
 pub mod data;
 pub mod event;
+pub mod generics;
+pub mod guard;
 pub mod input_state;
 pub mod lifetimes;
 pub mod output_state;
+pub mod state_definition;
 pub mod state_machine;
 pub mod transition;
 
 use data::DataDefinitions;
-use event::EventMapping;
-use state_machine::StateMachine;
+use event::{EventDefinition, EventMapping};
+use generics::GenericParameters;
+use state_definition::StateDefinition;
+use state_machine::{StateMachine, TypeNames};
 
 use input_state::InputState;
 use proc_macro2::Span;
+use quote::format_ident;
 
-use std::collections::{hash_map, HashMap};
-use syn::{parse, Ident, Type};
+use std::collections::HashMap;
+use syn::{parse, parse_quote, spanned::Spanned, Attribute, Ident, Path, Type, Visibility};
 use transition::StateTransition;
 
-pub type TransitionMap = HashMap<String, HashMap<String, EventMapping>>;
+/// Maps input states to events, and each event to the ordered list of transitions that may be
+/// taken for it. Transitions for the same state and event are tried in declaration order.
+pub type TransitionMap = HashMap<String, HashMap<String, Vec<EventMapping>>>;
+
+/// The number of completion transitions which may be taken in a row before processing an event
+/// fails, unless set with `completion_limit`.
+const DEFAULT_COMPLETION_LIMIT: usize = 16;
+
+/// The number of deferred events which can be stored, unless set with `defer_capacity`.
+const DEFAULT_DEFER_CAPACITY: usize = 8;
+
+/// Parses the doc comments in front of a transition, state or event, which are added to the
+/// generated code.
+pub fn parse_docs(input: parse::ParseStream) -> parse::Result<Vec<Attribute>> {
+    let attributes = input.call(Attribute::parse_outer)?;
+
+    if let Some(attribute) = attributes
+        .iter()
+        .find(|attribute| !attribute.path.is_ident("doc"))
+    {
+        return Err(parse::Error::new(
+            attribute.span(),
+            "Only doc comments can be given here.",
+        ));
+    }
+
+    Ok(attributes)
+}
 
 #[derive(Debug, Clone)]
 pub struct AsyncIdent {
@@ -28,56 +62,196 @@ pub struct AsyncIdent {
 #[derive(Debug)]
 pub struct ParsedStateMachine {
     pub name: Option<Ident>,
-    pub derive_states: Vec<Ident>,
-    pub derive_events: Vec<Ident>,
+    /// The visibility of the generated items, `pub` unless given with `visibility`
+    pub visibility: Visibility,
+    pub type_names: TypeNames,
+    pub derive_states: Vec<Path>,
+    pub derive_events: Vec<Path>,
+    /// The doc comments of the states and events, given in the `states` and `events` sections
+    pub state_docs: HashMap<String, Vec<Attribute>>,
+    pub event_docs: HashMap<String, Vec<Attribute>>,
+    pub states_attributes: Vec<Attribute>,
+    pub events_attributes: Vec<Attribute>,
+    pub error_attributes: Vec<Attribute>,
+    pub state_machine_attributes: Vec<Attribute>,
+    /// The type and const parameters declared with `generics`
+    pub generics: GenericParameters,
     pub temporary_context_type: Option<Type>,
     pub custom_guard_error: bool,
+    pub event_queue: bool,
+    /// The type of the clock of the timed transitions
+    pub clock_type: Option<Type>,
     pub states: HashMap<String, Ident>,
-    pub starting_state: Ident,
+    pub starting_state: Option<Ident>,
     pub state_data: DataDefinitions,
     pub events: HashMap<String, Ident>,
     pub event_data: DataDefinitions,
-    pub states_events_mapping: HashMap<String, HashMap<String, EventMapping>>,
+    pub states_events_mapping: TransitionMap,
+    pub entry_actions: HashMap<String, AsyncIdent>,
+    pub exit_actions: HashMap<String, AsyncIdent>,
+    pub composite_states: HashMap<String, Ident>,
+    pub parents: HashMap<String, Ident>,
+    pub initial_substates: HashMap<String, Ident>,
+    pub history_states: Vec<Ident>,
+    /// The states marked with `!`, which no longer accept events once entered
+    pub final_states: Vec<Ident>,
+    /// Transitions without an event, taken as soon as their input state is entered
+    pub completion_transitions: HashMap<String, Vec<EventMapping>>,
+    pub completion_limit: usize,
+    /// Transitions taken once their input state has been active for a duration, given with
+    /// `after(<duration>)`
+    pub timed_transitions: HashMap<String, Vec<EventMapping>>,
+    /// The events deferred by each state, to be processed once a state is entered which does not
+    /// defer them
+    pub deferred_events: HashMap<String, Vec<Ident>>,
+    pub defer_capacity: usize,
+    /// The states whose data is another state machine, which the events named after it are
+    /// forwarded to
+    pub submachines: HashMap<String, AsyncIdent>,
+    pub regions: Vec<(Ident, ParsedStateMachine)>,
+}
+
+// helper function for flattening nested state definitions, pairing each one with its parent
+fn flatten_state_definitions<'a>(
+    definitions: &'a [StateDefinition],
+    parent: Option<&'a Ident>,
+    flattened: &mut Vec<(&'a StateDefinition, Option<&'a Ident>)>,
+) {
+    for definition in definitions {
+        flattened.push((definition, parent));
+        flatten_state_definitions(&definition.substates, Some(&definition.ident), flattened);
+    }
+}
+
+// helper function for collecting the doc comments of the events, which have to be used in a
+// transition
+fn event_docs(
+    definitions: &[EventDefinition],
+    events: &HashMap<String, Ident>,
+) -> parse::Result<HashMap<String, Vec<Attribute>>> {
+    let mut event_docs = HashMap::new();
+
+    for definition in definitions {
+        let event_name = definition.ident.to_string();
+
+        if !events.contains_key(&event_name) {
+            return Err(parse::Error::new(
+                definition.ident.span(),
+                "Unknown event, only events which are used in a transition can be documented.",
+            ));
+        }
+
+        if event_docs
+            .insert(event_name, definition.docs.clone())
+            .is_some()
+        {
+            return Err(parse::Error::new(
+                definition.ident.span(),
+                "Event specified multiple times, remove duplicates.",
+            ));
+        }
+    }
+
+    Ok(event_docs)
 }
 
-// helper function for adding a transition to a transition event map
+// helper function for listing the ancestors of a state, starting with its parent
+fn ancestors(parents: &HashMap<String, Ident>, state: &str) -> Vec<Ident> {
+    let mut ancestors = Vec::new();
+    let mut state = state.to_string();
+
+    while let Some(parent) = parents.get(&state) {
+        ancestors.push(parent.clone());
+        state = parent.to_string();
+    }
+
+    ancestors
+}
+
+// helper function for finding the state which is entered when a (possibly composite) state is
+// the target of a transition
+fn initial_leaf(initial_substates: &HashMap<String, Ident>, state: &Ident) -> Ident {
+    let mut state = state.clone();
+
+    while let Some(substate) = initial_substates.get(&state.to_string()) {
+        state = substate.clone();
+    }
+
+    state
+}
+
+// helper function for adding a transition to the transitions of its state and event
 fn add_transition(
     transition: &StateTransition,
-    transition_map: &mut TransitionMap,
+    mappings: &mut Vec<EventMapping>,
     state_data: &DataDefinitions,
+    initial_substates: &HashMap<String, Ident>,
 ) -> Result<(), parse::Error> {
-    let p = transition_map
-        .get_mut(&transition.in_state.ident.to_string())
-        .unwrap();
-
-    if let hash_map::Entry::Vacant(entry) = p.entry(transition.event.ident.to_string()) {
-        let mapping = EventMapping {
-            in_state: transition.in_state.ident.clone(),
-            event: transition.event.ident.clone(),
-            guard: transition.guard.clone(),
-            action: transition.action.clone(),
-            out_state: transition.out_state.ident.clone(),
-        };
-
-        entry.insert(mapping);
-    } else {
+    // Several transitions may share the same state and event as long as they are guarded, in
+    // which case they are tried in order. Anything after an unguarded transition could never be
+    // reached.
+    if mappings.iter().any(|mapping| mapping.guard.is_none()) {
         return Err(parse::Error::new(
             transition.in_state.ident.span(),
             "State and event combination specified multiple times, remove duplicates.",
         ));
     }
 
+    // Internal transitions stay in their input state, without leaving it
+    let out_state = match &transition.out_state {
+        Some(out_state) => out_state,
+        None => {
+            mappings.push(EventMapping {
+                docs: transition.docs.clone(),
+                in_state: transition.in_state.ident.clone(),
+                event: transition.event.as_ref().map(|event| event.ident.clone()),
+                after: transition.after.clone(),
+                guard: transition.guard.clone(),
+                actions: transition.actions.clone(),
+                out_state: transition.in_state.ident.clone(),
+                history: None,
+                internal: true,
+                choice: None,
+                choice_actions: 0,
+                targets: Vec::new(),
+                wildcard: transition.in_state.wildcard,
+            });
+            return Ok(());
+        }
+    };
+
+    mappings.push(EventMapping {
+        docs: transition.docs.clone(),
+        in_state: transition.in_state.ident.clone(),
+        event: transition.event.as_ref().map(|event| event.ident.clone()),
+        after: transition.after.clone(),
+        guard: transition.guard.clone(),
+        actions: transition.actions.clone(),
+        out_state: out_state.ident.clone(),
+        history: out_state.history,
+        internal: false,
+        choice: transition.choice,
+        choice_actions: transition.choice_actions,
+        targets: transition
+            .targets
+            .iter()
+            .map(|target| target.ident.clone())
+            .collect(),
+        wildcard: transition.in_state.wildcard,
+    });
+
     // Check for actions when states have data a
     if state_data
         .data_types
-        .contains_key(&transition.out_state.ident.to_string())
+        .contains_key(&initial_leaf(initial_substates, &out_state.ident).to_string())
     {
         // This transition goes to a state that has data associated, check so it has an
-        // action
+        // action. The actions given before a choice run before a branch is chosen, so they can
+        // not provide the data of its target.
 
-        if transition.action.is_none() {
+        if transition.actions.len() == transition.choice_actions {
             return Err(parse::Error::new(
-                transition.out_state.ident.span(),
+                out_state.ident.span(),
                 "This state has data associated, but not action is define here to provide it.",
             ));
         }
@@ -85,8 +259,36 @@ fn add_transition(
     Ok(())
 }
 
+// helper function for naming a generated type after the state machine, such as `States`, unless
+// it was named explicitly
+fn type_name(sm_name: Option<&Ident>, name: Option<&Ident>, suffix: &str) -> Ident {
+    match (name, sm_name) {
+        (Some(name), _) => name.clone(),
+        (None, Some(sm_name)) => format_ident!("{}{}", sm_name, suffix, span = sm_name.span()),
+        (None, None) => format_ident!("{}", suffix),
+    }
+}
+
+// helper function for getting the number of completion transitions which may be taken in a row
+fn completion_limit(sm: &StateMachine) -> parse::Result<usize> {
+    match &sm.completion_limit {
+        Some(limit) => limit.base10_parse(),
+        None => Ok(DEFAULT_COMPLETION_LIMIT),
+    }
+}
+
 impl ParsedStateMachine {
     pub fn new(sm: StateMachine) -> parse::Result<Self> {
+        if !sm.regions.is_empty() {
+            return Self::new_with_regions(sm);
+        }
+
+        let completion_limit = completion_limit(&sm)?;
+        let defer_capacity = match &sm.defer_capacity {
+            Some(capacity) => capacity.base10_parse()?,
+            None => DEFAULT_DEFER_CAPACITY,
+        };
+
         // Check the initial state definition
         let mut starting_transitions_iter = sm.transitions.iter().filter(|sm| sm.in_state.start);
 
@@ -104,80 +306,395 @@ impl ParsedStateMachine {
             ));
         }
 
-        // Extract the starting state
-        let starting_state = starting_transition.in_state.ident.clone();
+        // Collect the state hierarchy and the entry and exit actions of the states
+        let mut state_definitions = Vec::new();
+        flatten_state_definitions(&sm.state_definitions, None, &mut state_definitions);
+
+        let mut composite_states = HashMap::new();
+        let mut parents = HashMap::new();
+        let mut initial_substates = HashMap::new();
+        let mut entry_actions = HashMap::new();
+        let mut exit_actions = HashMap::new();
+        let mut submachines = HashMap::new();
+        let mut state_docs = HashMap::new();
+        let mut defined_states = Vec::new();
+
+        for (definition, parent) in state_definitions.iter() {
+            let state_name = definition.ident.to_string();
+
+            if defined_states.contains(&state_name) {
+                return Err(parse::Error::new(
+                    definition.ident.span(),
+                    "State specified multiple times, remove duplicates.",
+                ));
+            }
+            defined_states.push(state_name.clone());
+
+            if !definition.docs.is_empty() {
+                state_docs.insert(state_name.clone(), definition.docs.clone());
+            }
+
+            match parent {
+                Some(parent) => {
+                    parents.insert(state_name.clone(), (*parent).clone());
+                    if definition.initial {
+                        initial_substates.insert(parent.to_string(), definition.ident.clone());
+                    }
+                }
+                None if definition.initial => {
+                    return Err(parse::Error::new(
+                        definition.ident.span(),
+                        "Only substates can be marked as initial, the starting state is indicated in the transitions.",
+                    ));
+                }
+                None => {}
+            }
+
+            if !definition.substates.is_empty() {
+                composite_states.insert(state_name.clone(), definition.ident.clone());
+            }
+
+            if let Some(entry) = &definition.entry {
+                entry_actions.insert(state_name.clone(), entry.clone());
+            }
+
+            if let Some(exit) = &definition.exit {
+                exit_actions.insert(state_name.clone(), exit.clone());
+            }
+
+            if let Some(submachine) = &definition.submachine {
+                if !definition.substates.is_empty() {
+                    return Err(parse::Error::new(
+                        submachine.ident.span(),
+                        "Submachines can not be used with composite states.",
+                    ));
+                }
+
+                submachines.insert(state_name, submachine.clone());
+            }
+        }
+
+        // Extract the starting state, which may be entered through its initial substates
+        let starting_state = initial_leaf(&initial_substates, &starting_transition.in_state.ident);
 
         let mut states = HashMap::new();
         let mut state_data = DataDefinitions::new();
         let mut events = HashMap::new();
         let mut event_data = DataDefinitions::new();
         let mut states_events_mapping = TransitionMap::new();
+        let mut final_states: Vec<Ident> = Vec::new();
+
+        // The targets chosen by an action must be final states or states which are used elsewhere,
+        // so a misspelled target does not add a state
+        let known_states: Vec<&Ident> = sm
+            .transitions
+            .iter()
+            .flat_map(|transition| {
+                let out_state = match transition.targets.is_empty() {
+                    true => transition
+                        .out_state
+                        .as_ref()
+                        .map(|out_state| &out_state.ident),
+                    false => None,
+                };
+                std::iter::once(&transition.in_state.ident).chain(out_state)
+            })
+            .chain(
+                state_definitions
+                    .iter()
+                    .map(|(definition, _)| &definition.ident),
+            )
+            .collect();
+
+        for target in sm
+            .transitions
+            .iter()
+            .flat_map(|transition| &transition.targets)
+        {
+            if !target.is_final && !known_states.contains(&&target.ident) {
+                return Err(parse::Error::new(
+                    target.ident.span(),
+                    "Unknown state, the targets chosen by an action must be final, used in another transition or defined in `states`.",
+                ));
+            }
+        }
 
         for transition in sm.transitions.iter() {
-            // Collect states
-            let in_state_name = transition.in_state.ident.to_string();
-            let out_state_name = transition.out_state.ident.to_string();
+            // Completion transitions are taken when their input state is entered
+            if transition.event.is_none() && transition.after.is_none() {
+                let message = if transition.in_state.wildcard {
+                    Some("Completion transitions can not be used with wildcards.")
+                } else if transition.out_state.is_none() {
+                    Some("Completion transitions need an output state.")
+                } else if composite_states.contains_key(&transition.in_state.ident.to_string()) {
+                    Some("Completion transitions can only be used with states which are not composite.")
+                } else {
+                    None
+                };
+
+                if let Some(message) = message {
+                    return Err(parse::Error::new(transition.in_state.ident.span(), message));
+                }
+            }
+
+            // Timed transitions are taken once their input state has been active for a while, an
+            // internal transition would not restart the timer and be taken over and over again
+            if transition.after.is_some() {
+                let message = if transition.in_state.wildcard {
+                    Some("Timed transitions can not be used with wildcards.")
+                } else if transition.out_state.is_none() {
+                    Some("Timed transitions need an output state.")
+                } else {
+                    None
+                };
+
+                if let Some(message) = message {
+                    return Err(parse::Error::new(transition.in_state.ident.span(), message));
+                }
+            }
+
+            for out_state in transition.out_states() {
+                if out_state.history.is_some()
+                    && !composite_states.contains_key(&out_state.ident.to_string())
+                {
+                    return Err(parse::Error::new(
+                        out_state.ident.span(),
+                        "History can only be used with composite states.",
+                    ));
+                }
+
+                // A state is final as soon as one of the transitions entering it marks it
+                if out_state.is_final {
+                    if composite_states.contains_key(&out_state.ident.to_string()) {
+                        return Err(parse::Error::new(
+                            out_state.ident.span(),
+                            "Final states can not be composite states.",
+                        ));
+                    }
+
+                    if !final_states.contains(&out_state.ident) {
+                        final_states.push(out_state.ident.clone());
+                    }
+                }
+            }
+
+            // Collect states, composite states are never active by themselves
+            let mut transition_states = Vec::new();
             if !transition.in_state.wildcard {
-                states.insert(in_state_name.clone(), transition.in_state.ident.clone());
-                state_data.collect(in_state_name.clone(), transition.in_state.data_type.clone())?;
+                transition_states
+                    .push((&transition.in_state.ident, &transition.in_state.data_type));
             }
-            states.insert(out_state_name.clone(), transition.out_state.ident.clone());
-            state_data.collect(
-                out_state_name.clone(),
-                transition.out_state.data_type.clone(),
-            )?;
+            for out_state in transition.out_states() {
+                transition_states.push((&out_state.ident, &out_state.data_type));
+            }
+
+            for (state, data_type) in transition_states {
+                let state_name = state.to_string();
 
-            // Collect events
-            let event_name = transition.event.ident.to_string();
-            events.insert(event_name.clone(), transition.event.ident.clone());
-            event_data.collect(event_name.clone(), transition.event.data_type.clone())?;
+                if !composite_states.contains_key(&state_name) {
+                    states.insert(state_name.clone(), state.clone());
+                    state_data.collect(state_name, data_type.clone())?;
+                } else if let Some(data_type) = data_type {
+                    return Err(parse::Error::new(
+                        data_type.span(),
+                        "Composite states can not have data associated with them.",
+                    ));
+                }
+            }
+
+            // Collect events, wildcards match all of them
+            if let Some(event) = transition.event.as_ref().filter(|event| !event.wildcard) {
+                let event_name = event.ident.to_string();
+                events.insert(event_name.clone(), event.ident.clone());
+                event_data.collect(event_name.clone(), event.data_type.clone())?;
+            }
 
             // add input and output states to the mapping HashMap
             if !transition.in_state.wildcard {
                 states_events_mapping.insert(transition.in_state.ident.to_string(), HashMap::new());
             }
-            states_events_mapping.insert(transition.out_state.ident.to_string(), HashMap::new());
+            for out_state in transition.out_states() {
+                states_events_mapping.insert(out_state.ident.to_string(), HashMap::new());
+            }
+        }
+
+        // The events of a submachine are given to the enclosing state machine as an event named
+        // after the submachine, and forwarded to it
+        let mut submachine_states: Vec<_> = submachines.iter().collect();
+        submachine_states.sort_by_key(|(state_name, _)| state_name.to_string());
+        for (state_name, submachine) in submachine_states {
+            if !state_data.data_types.contains_key(state_name) {
+                return Err(parse::Error::new(
+                    submachine.ident.span(),
+                    format!(
+                        "The state of a submachine needs the submachine as its data, such as `{}({}StateMachine<Context>)`.",
+                        state_name, submachine.ident
+                    ),
+                ));
+            }
+
+            let events_type_name = format_ident!("{}Events", submachine.ident);
+            events.insert(submachine.ident.to_string(), submachine.ident.clone());
+            event_data.collect(
+                submachine.ident.to_string(),
+                Some(parse_quote! { #events_type_name }),
+            )?;
         }
 
+        // The guards and actions of wildcard events get the whole event, as it may be any of them
+        if let Some(event) = sm
+            .transitions
+            .iter()
+            .filter_map(|transition| transition.event.as_ref())
+            .find(|event| event.wildcard)
+        {
+            let events_type_name =
+                type_name(sm.name.as_ref(), sm.type_names.events.as_ref(), "Events");
+            let lifetimes = &event_data.all_lifetimes;
+            let generics = sm.generics.used_by(event_data.data_types.values()).args();
+            event_data.collect(
+                event.ident.to_string(),
+                Some(parse_quote! { #events_type_name <#lifetimes #generics> }),
+            )?;
+        }
+
+        // Substates do not have to be used in any transition, as they can be entered as initial
+        // substates and left through the transitions of their parents
+        for (definition, parent) in state_definitions.iter() {
+            let state_name = definition.ident.to_string();
+
+            if parent.is_none() && !states_events_mapping.contains_key(&state_name) {
+                return Err(parse::Error::new(
+                    definition.ident.span(),
+                    "This state is not used in any transition.",
+                ));
+            }
+
+            if !composite_states.contains_key(&state_name) {
+                states
+                    .entry(state_name.clone())
+                    .or_insert_with(|| definition.ident.clone());
+            }
+            states_events_mapping.entry(state_name).or_default();
+        }
+
+        // Only known states can be excluded from a wildcard, so a misspelled exclusion does not
+        // go unnoticed
+        for excluded in sm
+            .transitions
+            .iter()
+            .flat_map(|transition| &transition.in_state.exclusions)
+        {
+            let state_name = excluded.to_string();
+            if !states.contains_key(&state_name) && !composite_states.contains_key(&state_name) {
+                return Err(parse::Error::new(
+                    excluded.span(),
+                    "Unknown state, only states which are used in a transition or defined in `states` can be excluded.",
+                ));
+            }
+        }
+
+        let mut completion_transitions: HashMap<String, Vec<EventMapping>> = HashMap::new();
+        let mut timed_transitions: HashMap<String, Vec<EventMapping>> = HashMap::new();
+
         for transition in sm.transitions.iter() {
+            let event = match &transition.event {
+                Some(event) => event,
+                // Several timed transitions of a state may be unguarded, as they have different
+                // durations
+                None if transition.after.is_some() => {
+                    let mut mappings = Vec::new();
+                    add_transition(transition, &mut mappings, &state_data, &initial_substates)?;
+                    timed_transitions
+                        .entry(transition.in_state.ident.to_string())
+                        .or_default()
+                        .extend(mappings);
+                    continue;
+                }
+                None => {
+                    add_transition(
+                        transition,
+                        completion_transitions
+                            .entry(transition.in_state.ident.to_string())
+                            .or_default(),
+                        &state_data,
+                        &initial_substates,
+                    )?;
+                    continue;
+                }
+            };
+
             // if input state is a wildcard, we need to add this transition for all states
             if transition.in_state.wildcard {
                 let mut transition_added = false;
 
                 for (name, in_state) in &states {
-                    // skip already set input state
-                    let p = states_events_mapping
-                        .get_mut(&in_state.to_string())
-                        .unwrap();
+                    // skip final states, which do not accept any event
+                    if final_states.contains(in_state) {
+                        continue;
+                    }
 
-                    if p.contains_key(&transition.event.ident.to_string()) {
+                    // skip excluded states, including the substates of excluded composite states
+                    let excluded = std::iter::once(in_state.clone())
+                        .chain(ancestors(&parents, name))
+                        .any(|state| transition.in_state.exclusions.contains(&state));
+                    if excluded {
                         continue;
                     }
 
-                    // create a new input state from wildcard
+                    // skip already set input state, including the ones that inherit the
+                    // transition from a parent state, but not the earlier branches of the same
+                    // choice
+                    let event_name = event.ident.to_string();
+                    let already_set = std::iter::once(in_state.clone())
+                        .chain(ancestors(&parents, name))
+                        .any(|state| {
+                            matches!(
+                                states_events_mapping[&state.to_string()].get(&event_name),
+                                Some(mappings) if transition.choice.is_none()
+                                    || mappings.iter().any(|m| m.choice != transition.choice)
+                            )
+                        });
+
+                    if already_set {
+                        continue;
+                    }
+
+                    // create a new input state from wildcard, spanned at the wildcard so the
+                    // errors caused by it show up at that line, and still marked as a wildcard so
+                    // the transition is known to come from it
                     let in_state = InputState {
                         start: false,
-                        wildcard: false,
-                        ident: in_state.clone(),
+                        wildcard: true,
+                        exclusions: Vec::new(),
+                        ident: Ident::new(name, transition.in_state.ident.span()),
                         data_type: state_data.data_types.get(name).cloned(),
                     };
 
                     // create the transition
                     let wildcard_transition = StateTransition {
+                        docs: transition.docs.clone(),
                         in_state,
                         event: transition.event.clone(),
+                        after: transition.after.clone(),
                         guard: transition.guard.clone(),
-                        action: transition.action.clone(),
+                        actions: transition.actions.clone(),
                         out_state: transition.out_state.clone(),
+                        choice: transition.choice,
+                        choice_actions: transition.choice_actions,
+                        targets: transition.targets.clone(),
                     };
 
                     // add the wildcard transition to the transition map
-                    // TODO:  Need to work on the span of this error, as it is being caused by the wildcard
-                    // but won't show up at that line
                     add_transition(
                         &wildcard_transition,
-                        &mut states_events_mapping,
+                        states_events_mapping
+                            .get_mut(name)
+                            .unwrap()
+                            .entry(event_name)
+                            .or_default(),
                         &state_data,
+                        &initial_substates,
                     )?;
 
                     transition_added = true;
@@ -192,22 +709,548 @@ impl ParsedStateMachine {
                     ));
                 }
             } else {
-                add_transition(transition, &mut states_events_mapping, &state_data)?;
+                add_transition(
+                    transition,
+                    states_events_mapping
+                        .get_mut(&transition.in_state.ident.to_string())
+                        .unwrap()
+                        .entry(event.ident.to_string())
+                        .or_default(),
+                    &state_data,
+                    &initial_substates,
+                )?;
+            }
+        }
+
+        // The events of a submachine are always forwarded to it
+        for (state_name, submachine) in submachines.iter() {
+            if states_events_mapping[state_name].contains_key(&submachine.ident.to_string()) {
+                return Err(parse::Error::new(
+                    submachine.ident.span(),
+                    "The events of a submachine are forwarded to it, so its state can not have transitions for them.",
+                ));
+            }
+        }
+
+        // The entry times of the states are read from the clock
+        let timed_transition = sm
+            .transitions
+            .iter()
+            .find(|transition| transition.after.is_some());
+        if let (Some(transition), None) = (timed_transition, &sm.clock_type) {
+            return Err(parse::Error::new(
+                transition.in_state.ident.span(),
+                "Timed transitions need a clock, given with `clock: <type>`.",
+            ));
+        }
+
+        // Events which are not handled by a state bubble up to its ancestors, so the transitions of
+        // composite states are tried after the state's own transitions
+        for state_name in states.keys() {
+            for ancestor in ancestors(&parents, state_name) {
+                let inherited = states_events_mapping[&ancestor.to_string()].clone();
+                let mapping = states_events_mapping.get_mut(state_name).unwrap();
+
+                for (event_name, event_mappings) in inherited {
+                    let mappings = mapping.entry(event_name).or_default();
+                    if mappings.iter().all(|mapping| mapping.guard.is_some()) {
+                        mappings.extend(event_mappings);
+                    }
+                }
             }
         }
 
+        // The composite states whose history is kept, the states they are left from are recorded
+        // without their data so they can only contain states without data
+        let mut history_states: Vec<Ident> = Vec::new();
+        for transition in sm.transitions.iter() {
+            let history_state = match &transition.out_state {
+                Some(out_state) if out_state.history.is_some() => &out_state.ident,
+                _ => continue,
+            };
+            if history_states.contains(history_state) {
+                continue;
+            }
+
+            let has_data = states.keys().any(|state_name| {
+                state_data.data_types.contains_key(state_name)
+                    && ancestors(&parents, state_name).contains(history_state)
+            });
+            if has_data {
+                return Err(parse::Error::new(
+                    history_state.span(),
+                    "History can only be used with composite states whose substates have no data.",
+                ));
+            }
+
+            history_states.push(history_state.clone());
+        }
+        history_states.sort_by_key(|state| state.to_string());
+
+        // Events deferred by a composite state are deferred by its substates as well, unless the
+        // active state has a transition for them
+        let mut deferred_events: HashMap<String, Vec<Ident>> = HashMap::new();
+        for (definition, _) in state_definitions.iter() {
+            for event in definition.defer.iter() {
+                if !events.contains_key(&event.to_string()) {
+                    return Err(parse::Error::new(
+                        event.span(),
+                        "Unknown event, only events which are used in a transition can be deferred.",
+                    ));
+                }
+
+                if !event_data.all_lifetimes.is_empty() {
+                    return Err(parse::Error::new(
+                        event.span(),
+                        "Events can not be deferred when the data of an event has a lifetime.",
+                    ));
+                }
+            }
+        }
+
+        for (state_name, state) in states.iter() {
+            let handled_events = &states_events_mapping[state_name];
+            let mut deferred: Vec<Ident> = std::iter::once(state.clone())
+                .chain(ancestors(&parents, state_name))
+                .filter_map(|state| {
+                    state_definitions
+                        .iter()
+                        .find(|(definition, _)| definition.ident == state)
+                })
+                .flat_map(|(definition, _)| definition.defer.iter().cloned())
+                .filter(|event| !handled_events.contains_key(&event.to_string()))
+                .collect();
+            deferred.sort_by_key(|event| event.to_string());
+            deferred.dedup();
+
+            if !deferred.is_empty() {
+                deferred_events.insert(state_name.clone(), deferred);
+            }
+        }
+
+        // Only states which can be active are left in the mapping
+        for composite_state in composite_states.keys() {
+            states_events_mapping.remove(composite_state);
+        }
+
+        let event_docs = event_docs(&sm.event_definitions, &events)?;
+
         Ok(ParsedStateMachine {
             name: sm.name,
+            visibility: sm.visibility.unwrap_or_else(|| parse_quote! { pub }),
+            type_names: sm.type_names,
             derive_states: sm.derive_states,
             derive_events: sm.derive_events,
+            state_docs,
+            event_docs,
+            states_attributes: sm.states_attributes,
+            events_attributes: sm.events_attributes,
+            error_attributes: sm.error_attributes,
+            state_machine_attributes: sm.state_machine_attributes,
+            generics: sm.generics,
             temporary_context_type: sm.temporary_context_type,
             custom_guard_error: sm.custom_guard_error,
+            event_queue: sm.event_queue,
+            clock_type: sm.clock_type,
             states,
-            starting_state,
+            starting_state: Some(starting_state),
             state_data,
             events,
             event_data,
             states_events_mapping,
+            entry_actions,
+            exit_actions,
+            composite_states,
+            parents,
+            initial_substates,
+            history_states,
+            final_states,
+            completion_transitions,
+            completion_limit,
+            timed_transitions,
+            deferred_events,
+            defer_capacity,
+            submachines,
+            regions: Vec::new(),
         })
     }
+
+    // A state machine with regions holds the states and transitions of all its regions, while
+    // each region keeps track of its own states
+    fn new_with_regions(sm: StateMachine) -> parse::Result<Self> {
+        if let Some(transition) = sm.transitions.first() {
+            return Err(parse::Error::new(
+                transition.in_state.ident.span(),
+                "Transitions must be defined inside a region when regions are used.",
+            ));
+        }
+
+        if let Some(definition) = sm.state_definitions.first() {
+            return Err(parse::Error::new(
+                definition.ident.span(),
+                "States must be defined inside a region when regions are used.",
+            ));
+        }
+
+        // Each region has its own state ids, named after the region
+        if let Some(state_id) = &sm.type_names.state_id {
+            return Err(parse::Error::new(
+                state_id.span(),
+                "`state_id_type` can not be used with regions, the state ids of each region are named after the region.",
+            ));
+        }
+
+        let completion_limit = completion_limit(&sm)?;
+        let defer_capacity = match &sm.defer_capacity {
+            Some(capacity) => capacity.base10_parse()?,
+            None => DEFAULT_DEFER_CAPACITY,
+        };
+
+        let mut machine = ParsedStateMachine {
+            name: sm.name,
+            visibility: sm.visibility.unwrap_or_else(|| parse_quote! { pub }),
+            type_names: sm.type_names,
+            derive_states: sm.derive_states,
+            derive_events: sm.derive_events,
+            state_docs: HashMap::new(),
+            event_docs: HashMap::new(),
+            states_attributes: sm.states_attributes,
+            events_attributes: sm.events_attributes,
+            error_attributes: sm.error_attributes,
+            state_machine_attributes: sm.state_machine_attributes,
+            generics: sm.generics,
+            temporary_context_type: sm.temporary_context_type,
+            custom_guard_error: sm.custom_guard_error,
+            event_queue: sm.event_queue,
+            clock_type: sm.clock_type,
+            states: HashMap::new(),
+            starting_state: None,
+            state_data: DataDefinitions::new(),
+            events: HashMap::new(),
+            event_data: DataDefinitions::new(),
+            states_events_mapping: TransitionMap::new(),
+            entry_actions: HashMap::new(),
+            exit_actions: HashMap::new(),
+            composite_states: HashMap::new(),
+            parents: HashMap::new(),
+            initial_substates: HashMap::new(),
+            history_states: Vec::new(),
+            final_states: Vec::new(),
+            completion_transitions: HashMap::new(),
+            completion_limit,
+            timed_transitions: HashMap::new(),
+            deferred_events: HashMap::new(),
+            defer_capacity,
+            submachines: HashMap::new(),
+            regions: Vec::new(),
+        };
+
+        for (region_name, mut region) in sm.regions {
+            if region.name.is_some()
+                || region.visibility.is_some()
+                || !region.type_names.is_empty()
+                || region.temporary_context_type.is_some()
+                || region.custom_guard_error
+                || region.event_queue
+                || region.clock_type.is_some()
+                || !region.derive_states.is_empty()
+                || !region.derive_events.is_empty()
+                || !region.event_definitions.is_empty()
+                || !region.states_attributes.is_empty()
+                || !region.events_attributes.is_empty()
+                || !region.error_attributes.is_empty()
+                || !region.state_machine_attributes.is_empty()
+                || !region.generics.is_empty()
+                || !region.regions.is_empty()
+                || region.completion_limit.is_some()
+                || region.defer_capacity.is_some()
+            {
+                return Err(parse::Error::new(
+                    region_name.span(),
+                    "Regions can only contain transitions and states, other options apply to the whole state machine.",
+                ));
+            }
+
+            if machine.regions.iter().any(|(name, _)| name == &region_name) {
+                return Err(parse::Error::new(
+                    region_name.span(),
+                    "Region specified multiple times, remove duplicates.",
+                ));
+            }
+
+            // The timed transitions of all regions are driven by the clock of the state machine,
+            // and their data may use its generic parameters
+            region.clock_type = machine.clock_type.clone();
+            region.generics = machine.generics.clone();
+            let region = ParsedStateMachine::new(region)?;
+
+            // An event deferred by one region may be handled by another one at the same time,
+            // where replaying it later would process it twice in the other region, while dropping
+            // it would lose the deferral
+            if let Some(event) = region.deferred_events.values().flatten().next() {
+                return Err(parse::Error::new(
+                    event.span(),
+                    "Deferred events can not be used with regions, as another region may handle the deferred event.",
+                ));
+            }
+
+            // States are shared by name, so a state can only be active in a single region
+            let mut region_states: Vec<_> = region
+                .states
+                .values()
+                .chain(region.composite_states.values())
+                .collect();
+            region_states.sort_by_key(|state| state.to_string());
+
+            for state in region_states {
+                let state_name = state.to_string();
+
+                if machine.states.contains_key(&state_name)
+                    || machine.composite_states.contains_key(&state_name)
+                {
+                    return Err(parse::Error::new(
+                        state.span(),
+                        "State is used in multiple regions, the states of each region must be unique.",
+                    ));
+                }
+            }
+
+            // Regions reacting to the same event must agree on the data it carries
+            let mut region_events: Vec<_> = region.events.iter().collect();
+            region_events.sort_by_key(|(event_name, _)| event_name.to_string());
+
+            for (event_name, event) in region_events {
+                let data_type = region.event_data.data_types.get(event_name);
+
+                if machine.events.contains_key(event_name)
+                    && machine.event_data.data_types.get(event_name) != data_type
+                {
+                    return Err(parse::Error::new(
+                        event.span(),
+                        "This event's type does not match its definition in another region.",
+                    ));
+                }
+
+                machine.events.insert(event_name.clone(), event.clone());
+                if !machine.event_data.data_types.contains_key(event_name) {
+                    machine
+                        .event_data
+                        .collect(event_name.clone(), data_type.cloned())?;
+                }
+            }
+
+            for (state_name, state) in &region.states {
+                machine.states.insert(state_name.clone(), state.clone());
+                machine.state_data.collect(
+                    state_name.clone(),
+                    region.state_data.data_types.get(state_name).cloned(),
+                )?;
+            }
+
+            machine
+                .states_events_mapping
+                .extend(region.states_events_mapping.clone());
+            machine.entry_actions.extend(region.entry_actions.clone());
+            machine.exit_actions.extend(region.exit_actions.clone());
+            machine
+                .composite_states
+                .extend(region.composite_states.clone());
+            machine.parents.extend(region.parents.clone());
+            machine
+                .initial_substates
+                .extend(region.initial_substates.clone());
+
+            machine
+                .history_states
+                .extend(region.history_states.iter().cloned());
+            machine
+                .final_states
+                .extend(region.final_states.iter().cloned());
+            machine
+                .completion_transitions
+                .extend(region.completion_transitions.clone());
+            machine
+                .timed_transitions
+                .extend(region.timed_transitions.clone());
+            machine.submachines.extend(region.submachines.clone());
+
+            machine.regions.push((region_name, region));
+        }
+
+        // The guards and actions of wildcard events get the whole event, which holds the events of
+        // all regions
+        if machine
+            .regions
+            .iter()
+            .any(|(_, region)| region.has_event_wildcard())
+        {
+            let events_type_name = machine.events_type_name();
+            let lifetimes = &machine.event_data.all_lifetimes;
+            let generics = machine
+                .generics
+                .used_by(machine.event_data.data_types.values())
+                .args();
+            let data_type: Type = parse_quote! { #events_type_name <#lifetimes #generics> };
+
+            for (_, region) in machine.regions.iter_mut() {
+                if region.has_event_wildcard() {
+                    region.event_data.data_types.remove("_");
+                    region.event_data.lifetimes.remove("_");
+                    region
+                        .event_data
+                        .collect("_".to_string(), Some(data_type.clone()))?;
+                }
+            }
+            machine
+                .event_data
+                .collect("_".to_string(), Some(data_type))?;
+        }
+        machine
+            .history_states
+            .sort_by_key(|state| state.to_string());
+        machine.event_docs = event_docs(&sm.event_definitions, &machine.events)?;
+
+        Ok(machine)
+    }
+
+    /// Lists the ancestors of a state, starting with its parent.
+    pub fn ancestors(&self, state: &Ident) -> Vec<Ident> {
+        ancestors(&self.parents, &state.to_string())
+    }
+
+    /// Returns the state which becomes active when `state` is the target of a transition,
+    /// following the initial substates of composite states.
+    pub fn initial_leaf(&self, state: &Ident) -> Ident {
+        initial_leaf(&self.initial_substates, state)
+    }
+
+    /// Iterates over all transitions, including completion and timed transitions.
+    pub fn all_transitions(&self) -> impl Iterator<Item = &EventMapping> {
+        self.states_events_mapping
+            .values()
+            .flat_map(|events| events.values().flatten())
+            .chain(self.completion_transitions.values().flatten())
+            .chain(self.timed_transitions.values().flatten())
+    }
+
+    /// Returns `true` if any guard, action, entry or exit action, or submachine is async.
+    pub fn is_async(&self) -> bool {
+        self.all_transitions()
+            .flat_map(|mapping| {
+                mapping
+                    .guard
+                    .iter()
+                    .flat_map(|guard| guard.guards())
+                    .chain(&mapping.actions)
+            })
+            .chain(self.entry_actions.values())
+            .chain(self.exit_actions.values())
+            .chain(self.submachines.values())
+            .any(|function| function.is_async)
+    }
+
+    /// Returns `true` if a state has transitions for the wildcard event, which match any event.
+    pub fn has_event_wildcard(&self) -> bool {
+        self.states_events_mapping
+            .values()
+            .any(|events| events.contains_key("_"))
+    }
+
+    /// Returns `true` if the state machine, or a region, may handle an event, either with a
+    /// transition for it or with a wildcard event.
+    pub fn handles_event(&self, event_name: &str) -> bool {
+        self.events.contains_key(event_name) || self.has_event_wildcard()
+    }
+
+    /// Returns the name of the enum of the states, such as `States` or `ChargerStates` for a state
+    /// machine named `Charger`, unless it was given with `states_type`.
+    pub fn states_type_name(&self) -> Ident {
+        type_name(
+            self.name.as_ref(),
+            self.type_names.states.as_ref(),
+            "States",
+        )
+    }
+
+    /// Returns the name of the enum of the events, unless it was given with `events_type`.
+    pub fn events_type_name(&self) -> Ident {
+        type_name(
+            self.name.as_ref(),
+            self.type_names.events.as_ref(),
+            "Events",
+        )
+    }
+
+    /// Returns the name of the enum of the errors, unless it was given with `error_type`.
+    pub fn error_type_name(&self) -> Ident {
+        type_name(self.name.as_ref(), self.type_names.error.as_ref(), "Error")
+    }
+
+    /// Returns the name of the state machine structure, unless it was given with
+    /// `state_machine_type`.
+    pub fn state_machine_type_name(&self) -> Ident {
+        type_name(
+            self.name.as_ref(),
+            self.type_names.state_machine.as_ref(),
+            "StateMachine",
+        )
+    }
+
+    /// Returns the name of the trait of the context, unless it was given with `context_type`.
+    pub fn state_machine_context_type_name(&self) -> Ident {
+        type_name(
+            self.name.as_ref(),
+            self.type_names.context.as_ref(),
+            "StateMachineContext",
+        )
+    }
+
+    /// Returns the name of the enum of the state ids, unless it was given with `state_id_type`.
+    pub fn state_id_type_name(&self) -> Ident {
+        type_name(
+            self.name.as_ref(),
+            self.type_names.state_id.as_ref(),
+            "StateId",
+        )
+    }
+
+    /// Returns the name of the enum of the event ids, unless it was given with `event_id_type`.
+    pub fn event_id_type_name(&self) -> Ident {
+        type_name(
+            self.name.as_ref(),
+            self.type_names.event_id.as_ref(),
+            "EventId",
+        )
+    }
+
+    /// Returns the name of the enum returned by an action which chooses the target of its
+    /// transitions, such as `ParseResponseTarget` for `parse_response`.
+    pub fn target_type_name(&self, action: &Ident) -> Ident {
+        let sm_name = self
+            .name
+            .as_ref()
+            .map(|name| name.to_string())
+            .unwrap_or_default();
+        let action_name: String = action
+            .to_string()
+            .split('_')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect();
+
+        format_ident!("{}{}Target", sm_name, action_name, span = action.span())
+    }
+
+    /// Returns the type of the data of the event of a transition, if any.
+    pub fn event_data_type(&self, event_mapping: &EventMapping) -> Option<&Type> {
+        event_mapping
+            .event
+            .as_ref()
+            .and_then(|event| self.event_data.data_types.get(&event.to_string()))
+    }
 }
@@ -1,16 +1,49 @@
 use syn::{parenthesized, parse, spanned::Spanned, token, Ident, Token, Type};
 
+/// A history pseudo-state, re-entering the substate of a composite state which was active when it
+/// was last left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum History {
+    /// Re-enters the last active direct substate, which is entered through its initial substates.
+    Shallow,
+    /// Re-enters the last active substate at any depth.
+    Deep,
+}
+
 #[derive(Debug, Clone)]
 pub struct OutputState {
     pub ident: Ident,
     pub data_type: Option<Type>,
+    pub history: Option<History>,
+    /// Marked with `!`, the state machine terminates once the state is entered
+    pub is_final: bool,
 }
 
-impl parse::Parse for OutputState {
-    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
-        input.parse::<Token![=]>()?;
+impl OutputState {
+    /// Parses the target state of a transition, which follows its `=` or the `=>` of a branch of
+    /// a choice.
+    pub fn parse_target(input: parse::ParseStream) -> syn::Result<Self> {
         let ident: Ident = input.parse()?;
 
+        // Possible history pseudo-state of a composite state
+        let history = if input.parse::<Token![.]>().is_ok() {
+            let pseudo_state: Ident = input.parse()?;
+            if pseudo_state != "H" {
+                return Err(parse::Error::new(
+                    pseudo_state.span(),
+                    "Unknown pseudo-state, use `H` for shallow history or `H*` for deep history.",
+                ));
+            }
+
+            if input.parse::<Token![*]>().is_ok() {
+                Some(History::Deep)
+            } else {
+                Some(History::Shallow)
+            }
+        } else {
+            None
+        };
+
         // Possible type on the output state
         let data_type = if input.peek(token::Paren) {
             let content;
@@ -38,6 +71,77 @@ impl parse::Parse for OutputState {
             None
         };
 
-        Ok(Self { ident, data_type })
+        // Possible final state marker
+        let is_final = input.parse::<Token![!]>().is_ok();
+
+        Ok(Self {
+            ident,
+            data_type,
+            history,
+            is_final,
+        })
+    }
+}
+
+impl parse::Parse for OutputState {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![=]>()?;
+        Self::parse_target(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn output_state() {
+        let state: OutputState = parse_quote! {
+            = Charging
+        };
+
+        assert_eq!(state.ident, "Charging");
+        assert!(state.history.is_none());
+    }
+
+    #[test]
+    fn shallow_history() {
+        let state: OutputState = parse_quote! {
+            = Session.H
+        };
+
+        assert_eq!(state.ident, "Session");
+        assert_eq!(state.history, Some(History::Shallow));
+    }
+
+    #[test]
+    fn deep_history() {
+        let state: OutputState = parse_quote! {
+            = Session.H*
+        };
+
+        assert_eq!(state.ident, "Session");
+        assert_eq!(state.history, Some(History::Deep));
+    }
+
+    #[test]
+    fn final_state() {
+        let state: OutputState = parse_quote! {
+            = Done(Report)!
+        };
+
+        assert_eq!(state.ident, "Done");
+        assert!(state.data_type.is_some());
+        assert!(state.is_final);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown pseudo-state")]
+    fn unknown_pseudo_state() {
+        let _: OutputState = parse_quote! {
+            = Session.Deep
+        };
     }
 }
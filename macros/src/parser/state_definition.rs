@@ -0,0 +1,251 @@
+use super::{parse_docs, AsyncIdent};
+use syn::{braced, bracketed, parse, token, Attribute, Ident, Token};
+
+/// Options for a single state, given in the `states` section of the DSL.
+#[derive(Debug)]
+pub struct StateDefinition {
+    /// The doc comments of the state, added to its variant of the states
+    pub docs: Vec<Attribute>,
+    pub initial: bool,
+    pub ident: Ident,
+    pub entry: Option<AsyncIdent>,
+    pub exit: Option<AsyncIdent>,
+    pub defer: Vec<Ident>,
+    /// The name of the state machine used as the data of the state, given with `submachine`
+    pub submachine: Option<AsyncIdent>,
+    pub substates: Vec<StateDefinition>,
+}
+
+impl parse::Parse for StateDefinition {
+    fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let docs = parse_docs(input)?;
+
+        // Check for initial substate definition
+        let initial = input.parse::<Token![*]>().is_ok();
+        let ident: Ident = input.parse()?;
+
+        let mut definition = Self {
+            docs,
+            initial,
+            ident,
+            entry: None,
+            exit: None,
+            defer: Vec::new(),
+            submachine: None,
+            substates: Vec::new(),
+        };
+
+        // The options are optional, a state may be listed by name only
+        if input.parse::<Token![:]>().is_err() {
+            return Ok(definition);
+        }
+
+        let content;
+        braced!(content in input);
+        loop {
+            if content.is_empty() {
+                break;
+            }
+
+            let option: Ident = content.parse()?;
+            content.parse::<Token![:]>()?;
+
+            match option.to_string().as_str() {
+                "entry" => definition.entry = Some(parse_hook(&content)?),
+                "exit" => definition.exit = Some(parse_hook(&content)?),
+                "submachine" => definition.submachine = Some(parse_hook(&content)?),
+                "defer" => {
+                    let events;
+                    bracketed!(events in content);
+                    loop {
+                        if events.is_empty() {
+                            break;
+                        }
+
+                        definition.defer.push(events.parse()?);
+
+                        if events.parse::<Token![,]>().is_err() {
+                            break;
+                        };
+                    }
+                }
+                "substates" => {
+                    let substates;
+                    braced!(substates in content);
+                    loop {
+                        if substates.is_empty() {
+                            break;
+                        }
+
+                        definition.substates.push(substates.parse()?);
+
+                        if substates.parse::<Token![,]>().is_err() {
+                            break;
+                        };
+                    }
+
+                    let mut initial_substates = definition.substates.iter().filter(|s| s.initial);
+
+                    if initial_substates.next().is_none() {
+                        return Err(parse::Error::new(
+                            definition.ident.span(),
+                            "No initial substate defined, indicate the initial substate with a *.",
+                        ));
+                    }
+
+                    if let Some(substate) = initial_substates.next() {
+                        return Err(parse::Error::new(
+                            substate.ident.span(),
+                            "More than one initial substate defined (indicated with *), remove duplicates.",
+                        ));
+                    }
+                }
+                option => {
+                    return Err(parse::Error::new(
+                        content.span(),
+                        format!(
+                            "Unknown state option {}. Supported options: [\"entry\", \"exit\", \"defer\", \"submachine\", \"substates\"]",
+                            option
+                        ),
+                    ))
+                }
+            }
+
+            // No comma at end of line, no more options
+            if content.is_empty() {
+                break;
+            }
+
+            if content.parse::<Token![,]>().is_err() {
+                break;
+            };
+        }
+
+        Ok(definition)
+    }
+}
+
+// helper function for parsing an optionally async function name
+fn parse_hook(input: parse::ParseStream) -> syn::Result<AsyncIdent> {
+    let is_async = input.parse::<token::Async>().is_ok();
+    let ident: Ident = input.parse()?;
+    Ok(AsyncIdent { ident, is_async })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn entry_and_exit() {
+        let definition: StateDefinition = parse_quote! {
+            Charging: { entry: start_meter, exit: async stop_meter }
+        };
+
+        assert_eq!(definition.ident, "Charging");
+
+        let entry = definition.entry.unwrap();
+        assert_eq!(entry.ident, "start_meter");
+        assert!(!entry.is_async);
+
+        let exit = definition.exit.unwrap();
+        assert_eq!(exit.ident, "stop_meter");
+        assert!(exit.is_async);
+    }
+
+    #[test]
+    fn substates() {
+        let definition: StateDefinition = parse_quote! {
+            Session: {
+                entry: start_session,
+                substates: {
+                    *Idle,
+                    Charging: { substates: { *Bulk, Float } },
+                },
+            }
+        };
+
+        assert!(!definition.initial);
+        assert_eq!(definition.substates.len(), 2);
+        assert!(definition.substates[0].initial);
+        assert_eq!(definition.substates[1].ident, "Charging");
+        assert_eq!(definition.substates[1].substates.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "No initial substate defined")]
+    fn substates_without_initial() {
+        let _: StateDefinition = parse_quote! {
+            Session: { substates: { Idle, Charging } }
+        };
+    }
+
+    #[test]
+    #[should_panic(expected = "More than one initial substate defined")]
+    fn substates_with_multiple_initial() {
+        let _: StateDefinition = parse_quote! {
+            Session: { substates: { *Idle, *Charging } }
+        };
+    }
+
+    #[test]
+    fn defer() {
+        let definition: StateDefinition = parse_quote! {
+            Authorizing: { entry: authorize, defer: [MeterValue, StatusNotification] }
+        };
+
+        assert_eq!(definition.defer.len(), 2);
+        assert_eq!(definition.defer[0], "MeterValue");
+        assert_eq!(definition.defer[1], "StatusNotification");
+    }
+
+    #[test]
+    fn submachine() {
+        let definition: StateDefinition = parse_quote! {
+            Authorizing: { submachine: async Auth }
+        };
+
+        let submachine = definition.submachine.unwrap();
+        assert_eq!(submachine.ident, "Auth");
+        assert!(submachine.is_async);
+    }
+
+    #[test]
+    fn docs() {
+        let definition: StateDefinition = parse_quote! {
+            /// Charging the car
+            Charging: {
+                substates: {
+                    /// Charging with a constant current
+                    *Bulk,
+                    Float,
+                }
+            }
+        };
+
+        assert_eq!(definition.docs.len(), 1);
+        assert_eq!(definition.substates[0].docs.len(), 1);
+        assert!(definition.substates[1].docs.is_empty());
+    }
+
+    #[test]
+    fn name_only() {
+        let definition: StateDefinition = parse_quote! {
+            Charging
+        };
+
+        assert!(definition.entry.is_none());
+        assert!(definition.exit.is_none());
+        assert!(definition.defer.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown state option")]
+    fn unknown_option() {
+        let _: StateDefinition = parse_quote! {
+            Charging: { enter: start_meter }
+        };
+    }
+}
@@ -1,14 +1,67 @@
+use super::event::EventDefinition;
+use super::generics::GenericParameters;
+use super::state_definition::StateDefinition;
 use super::transition::{StateTransition, StateTransitions};
-use syn::{braced, bracketed, parse, spanned::Spanned, token, Ident, Token, Type};
+use syn::{
+    braced, bracketed, parse, spanned::Spanned, token, Attribute, Ident, Path, Token, Type,
+    Visibility,
+};
 
 #[derive(Debug)]
 pub struct StateMachine {
     pub temporary_context_type: Option<Type>,
     pub custom_guard_error: bool,
+    pub event_queue: bool,
+    pub clock_type: Option<Type>,
     pub transitions: Vec<StateTransition>,
+    pub state_definitions: Vec<StateDefinition>,
+    /// The events documented in the `events` section
+    pub event_definitions: Vec<EventDefinition>,
     pub name: Option<Ident>,
-    pub derive_states: Vec<Ident>,
-    pub derive_events: Vec<Ident>,
+    /// The visibility of the generated items, given with `visibility`
+    pub visibility: Option<Visibility>,
+    /// The names of the generated types, given with `states_type`, `events_type`, `error_type`,
+    /// `state_machine_type` and `context_type`, instead of naming them after `name`
+    pub type_names: TypeNames,
+    pub derive_states: Vec<Path>,
+    pub derive_events: Vec<Path>,
+    /// The attributes of the generated states, events, error and state machine, given with
+    /// `states_attributes`, `events_attributes`, `error_attributes` and `state_machine_attributes`
+    pub states_attributes: Vec<Attribute>,
+    pub events_attributes: Vec<Attribute>,
+    pub error_attributes: Vec<Attribute>,
+    pub state_machine_attributes: Vec<Attribute>,
+    /// The type and const parameters of the generated types, given with `generics`
+    pub generics: GenericParameters,
+    pub regions: Vec<(Ident, StateMachine)>,
+    pub completion_limit: Option<syn::LitInt>,
+    pub defer_capacity: Option<syn::LitInt>,
+    /// The number of choice pseudo-states, including the ones of the regions
+    pub choices: usize,
+}
+
+/// The names given to the generated types, which are otherwise named after the state machine
+#[derive(Debug, Default, Clone)]
+pub struct TypeNames {
+    pub states: Option<Ident>,
+    pub events: Option<Ident>,
+    pub error: Option<Ident>,
+    pub state_machine: Option<Ident>,
+    pub context: Option<Ident>,
+    pub state_id: Option<Ident>,
+    pub event_id: Option<Ident>,
+}
+
+impl TypeNames {
+    pub fn is_empty(&self) -> bool {
+        self.states.is_none()
+            && self.events.is_none()
+            && self.error.is_none()
+            && self.state_machine.is_none()
+            && self.context.is_none()
+            && self.state_id.is_none()
+            && self.event_id.is_none()
+    }
 }
 
 impl StateMachine {
@@ -16,27 +69,99 @@ impl StateMachine {
         StateMachine {
             temporary_context_type: None,
             custom_guard_error: false,
+            event_queue: false,
+            clock_type: None,
             transitions: Vec::new(),
+            state_definitions: Vec::new(),
+            event_definitions: Vec::new(),
             name: None,
+            visibility: None,
+            type_names: TypeNames::default(),
             derive_states: Vec::new(),
             derive_events: Vec::new(),
+            states_attributes: Vec::new(),
+            events_attributes: Vec::new(),
+            error_attributes: Vec::new(),
+            state_machine_attributes: Vec::new(),
+            generics: GenericParameters::new(),
+            regions: Vec::new(),
+            completion_limit: None,
+            defer_capacity: None,
+            choices: 0,
         }
     }
 
     pub fn add_transitions(&mut self, transitions: StateTransitions) {
-        for in_state in transitions.in_states {
-            let transition = StateTransition {
-                in_state,
-                event: transitions.event.clone(),
-                guard: transitions.guard.clone(),
-                action: transitions.action.clone(),
-                out_state: transitions.out_state.clone(),
-            };
-            self.transitions.push(transition);
+        // The branches of a choice are tried in order, like several guarded transitions of the
+        // same state and event. The actions of the transition to the choice are kept in front of
+        // the actions of every branch, and run once before the guards of the branches.
+        let branches = match transitions.choice {
+            Some(choice) => {
+                let id = self.choices;
+                self.choices += 1;
+                let transition_actions = &transitions.actions;
+                choice
+                    .branches
+                    .into_iter()
+                    .map(|branch| {
+                        let mut actions = transition_actions.clone();
+                        actions.extend(branch.actions);
+                        (
+                            branch.guard,
+                            actions,
+                            Some(branch.out_state),
+                            Some(id),
+                            transition_actions.len(),
+                        )
+                    })
+                    .collect()
+            }
+            None => vec![(
+                transitions.guard,
+                transitions.actions,
+                transitions.out_state,
+                None,
+                0,
+            )],
+        };
+
+        for mut in_state in transitions.in_states {
+            for (guard, actions, out_state, choice, choice_actions) in branches.iter() {
+                let transition = StateTransition {
+                    docs: transitions.docs.clone(),
+                    in_state: in_state.clone(),
+                    event: transitions.event.clone(),
+                    after: transitions.after.clone(),
+                    guard: guard.clone(),
+                    actions: actions.clone(),
+                    out_state: out_state.clone(),
+                    choice: *choice,
+                    choice_actions: *choice_actions,
+                    targets: transitions.targets.clone(),
+                };
+                self.transitions.push(transition);
+
+                // The starting state is only indicated once
+                in_state.start = false;
+            }
         }
     }
 }
 
+// helper function for parsing a list of attributes, such as `: [#[repr(u8)] #[cfg_attr(..)]]`
+fn parse_attributes(input: parse::ParseStream) -> parse::Result<Vec<Attribute>> {
+    input.parse::<Token![:]>()?;
+    let content;
+    bracketed!(content in input);
+    let attributes = content.call(Attribute::parse_outer)?;
+
+    if !content.is_empty() {
+        return Err(content.error("Expected an attribute, such as `#[repr(u8)]`."));
+    }
+
+    Ok(attributes)
+}
+
 impl parse::Parse for StateMachine {
     fn parse(input: parse::ParseStream) -> parse::Result<Self> {
         let mut statemachine = StateMachine::new();
@@ -72,6 +197,110 @@ impl parse::Parse for StateMachine {
                         }
                     }
                 }
+                "states" => {
+                    input.parse::<Token![:]>()?;
+                    let content;
+                    braced!(content in input);
+                    loop {
+                        if content.is_empty() {
+                            break;
+                        }
+
+                        let state_definition: StateDefinition = content.parse()?;
+                        statemachine.state_definitions.push(state_definition);
+
+                        // No comma at end of line, no more states
+                        if content.is_empty() {
+                            break;
+                        }
+
+                        if content.parse::<Token![,]>().is_err() {
+                            break;
+                        };
+                    }
+                }
+                "events" => {
+                    input.parse::<Token![:]>()?;
+                    let content;
+                    braced!(content in input);
+                    loop {
+                        if content.is_empty() {
+                            break;
+                        }
+
+                        let event_definition: EventDefinition = content.parse()?;
+                        statemachine.event_definitions.push(event_definition);
+
+                        // No comma at end of line, no more events
+                        if content.is_empty() {
+                            break;
+                        }
+
+                        if content.parse::<Token![,]>().is_err() {
+                            break;
+                        };
+                    }
+                }
+                "regions" => {
+                    input.parse::<Token![:]>()?;
+                    let content;
+                    braced!(content in input);
+                    loop {
+                        if content.is_empty() {
+                            break;
+                        }
+
+                        // Each region is described with the same syntax as a state machine
+                        let region_name: Ident = content.parse()?;
+                        content.parse::<Token![:]>()?;
+                        let region;
+                        braced!(region in content);
+                        let mut region: StateMachine = region.parse()?;
+
+                        // The choices are numbered across all regions, so they can be told apart
+                        for transition in region.transitions.iter_mut() {
+                            if let Some(choice) = &mut transition.choice {
+                                *choice += statemachine.choices;
+                            }
+                        }
+                        statemachine.choices += region.choices;
+                        statemachine.regions.push((region_name, region));
+
+                        // No comma at end of line, no more regions
+                        if content.is_empty() {
+                            break;
+                        }
+
+                        if content.parse::<Token![,]>().is_err() {
+                            break;
+                        };
+                    }
+                }
+                "completion_limit" => {
+                    input.parse::<Token![:]>()?;
+                    let completion_limit: syn::LitInt = input.parse()?;
+                    completion_limit.base10_parse::<usize>()?;
+                    statemachine.completion_limit = Some(completion_limit);
+                }
+                "defer_capacity" => {
+                    input.parse::<Token![:]>()?;
+                    let defer_capacity: syn::LitInt = input.parse()?;
+                    defer_capacity.base10_parse::<usize>()?;
+                    statemachine.defer_capacity = Some(defer_capacity);
+                }
+                "event_queue" => {
+                    input.parse::<Token![:]>()?;
+                    let event_queue: syn::LitBool = input.parse()?;
+                    statemachine.event_queue = event_queue.value;
+                }
+                "generics" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.generics = GenericParameters::from_generics(input.parse()?)?;
+                }
+                "clock" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.clock_type = Some(input.parse::<Type>()?);
+                }
                 "custom_guard_error" => {
                     input.parse::<Token![:]>()?;
                     let custom_guard_error: syn::LitBool = input.parse()?;
@@ -108,6 +337,38 @@ impl parse::Parse for StateMachine {
                     input.parse::<Token![:]>()?;
                     statemachine.name = Some(input.parse::<Ident>()?);
                 },
+                "visibility" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.visibility = Some(input.parse::<Visibility>()?);
+                }
+                "states_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.states = Some(input.parse::<Ident>()?);
+                }
+                "events_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.events = Some(input.parse::<Ident>()?);
+                }
+                "error_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.error = Some(input.parse::<Ident>()?);
+                }
+                "state_machine_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.state_machine = Some(input.parse::<Ident>()?);
+                }
+                "context_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.context = Some(input.parse::<Ident>()?);
+                }
+                "state_id_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.state_id = Some(input.parse::<Ident>()?);
+                }
+                "event_id_type" => {
+                    input.parse::<Token![:]>()?;
+                    statemachine.type_names.event_id = Some(input.parse::<Ident>()?);
+                }
                 "derive_states" => {
                     input.parse::<Token![:]>()?;
                     if input.peek(token::Bracket) {
@@ -117,7 +378,7 @@ impl parse::Parse for StateMachine {
                             if content.is_empty() {
                                 break;
                             };
-                            let trait_ =  content.parse::<Ident>()?;
+                            let trait_ =  content.parse::<Path>()?;
                             statemachine.derive_states.push(trait_);
                             if content.parse::<Token![,]>().is_err() {
                                 break;
@@ -133,17 +394,29 @@ impl parse::Parse for StateMachine {
                         if content.is_empty() {
                             break;
                         };
-                        let trait_ =  content.parse::<Ident>()?;
+                        let trait_ =  content.parse::<Path>()?;
                         statemachine.derive_events.push(trait_);
                         if content.parse::<Token![,]>().is_err() {
                             break;
                         };
                     }
                 },
+                "states_attributes" => {
+                    statemachine.states_attributes = parse_attributes(input)?;
+                }
+                "events_attributes" => {
+                    statemachine.events_attributes = parse_attributes(input)?;
+                }
+                "error_attributes" => {
+                    statemachine.error_attributes = parse_attributes(input)?;
+                }
+                "state_machine_attributes" => {
+                    statemachine.state_machine_attributes = parse_attributes(input)?;
+                }
                 keyword => {
                     return Err(parse::Error::new(
                         input.span(),
-                        format!("Unknown keyword {}. Support keywords: [\"name\", \"transitions\", \"temporary_context\", \"custom_guard_error\", \"derive_states\", \"derive_events\"]", keyword)
+                        format!("Unknown keyword {}. Support keywords: [\"name\", \"visibility\", \"states_type\", \"events_type\", \"error_type\", \"state_machine_type\", \"context_type\", \"state_id_type\", \"event_id_type\", \"transitions\", \"states\", \"events\", \"regions\", \"completion_limit\", \"defer_capacity\", \"event_queue\", \"clock\", \"generics\", \"temporary_context\", \"custom_guard_error\", \"derive_states\", \"derive_events\", \"states_attributes\", \"events_attributes\", \"error_attributes\", \"state_machine_attributes\"]", keyword)
                     ))
                 }
             }
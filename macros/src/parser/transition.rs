@@ -1,29 +1,58 @@
+use super::choice::Choice;
 use super::event::Event;
+use super::guard::GuardExpression;
 use super::input_state::InputState;
 use super::output_state::OutputState;
-use super::AsyncIdent;
-use syn::{bracketed, parse, token, Ident, Token};
+use super::{parse_docs, AsyncIdent};
+use syn::{braced, bracketed, parenthesized, parse, token, Attribute, Expr, Ident, Token};
 
 #[derive(Debug)]
 pub struct StateTransition {
+    /// The doc comments of the transition, added to its guards and actions
+    pub docs: Vec<Attribute>,
     pub in_state: InputState,
-    pub event: Event,
-    pub guard: Option<AsyncIdent>,
-    pub action: Option<AsyncIdent>,
-    pub out_state: OutputState,
+    pub event: Option<Event>,
+    pub after: Option<Expr>,
+    pub guard: Option<GuardExpression>,
+    pub actions: Vec<AsyncIdent>,
+    pub out_state: Option<OutputState>,
+    /// The choice pseudo-state the transition is a branch of, numbered in order of appearance
+    pub choice: Option<usize>,
+    /// The number of actions at the start of `actions` which are given before the choice, and run
+    /// once before the guards of its branches
+    pub choice_actions: usize,
+    /// The states the action chooses from, given as `{A | B}`, the first one is the output state
+    pub targets: Vec<OutputState>,
+}
+
+impl StateTransition {
+    /// Lists the states the transition may enter.
+    pub fn out_states(&self) -> Vec<&OutputState> {
+        if self.targets.is_empty() {
+            self.out_state.iter().collect()
+        } else {
+            self.targets.iter().collect()
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct StateTransitions {
+    pub docs: Vec<Attribute>,
     pub in_states: Vec<InputState>,
-    pub event: Event,
-    pub guard: Option<AsyncIdent>,
-    pub action: Option<AsyncIdent>,
-    pub out_state: OutputState,
+    pub event: Option<Event>,
+    pub after: Option<Expr>,
+    pub guard: Option<GuardExpression>,
+    pub actions: Vec<AsyncIdent>,
+    pub out_state: Option<OutputState>,
+    pub choice: Option<Choice>,
+    pub targets: Vec<OutputState>,
 }
 
 impl parse::Parse for StateTransitions {
     fn parse(input: parse::ParseStream) -> syn::Result<Self> {
+        let docs = parse_docs(input)?;
+
         // parse the input pattern
         let mut in_states = Vec::new();
         loop {
@@ -47,42 +76,262 @@ impl parse::Parse for StateTransitions {
         }
 
         // Event
-        let event: Event = input.parse()?;
+        // The event is left out for completion transitions, which are taken as soon as the input
+        // state is entered, and replaced by `after(<duration>)` for timed transitions, which are
+        // taken once the input state has been active for the duration. Timed transitions have no
+        // `+`, so they can not be mistaken for an event named `after`.
+        let (event, after) = if is_timed(input) {
+            input.parse::<Ident>()?;
+            let content;
+            parenthesized!(content in input);
+            (None, Some(content.parse::<Expr>()?))
+        } else if input.peek(Token![+]) {
+            (Some(input.parse::<Event>()?), None)
+        } else {
+            (None, None)
+        };
 
         // Possible guard
-        let guard = if input.peek(token::Bracket) {
+        let (guard, guard_span) = if input.peek(token::Bracket) {
             let content;
-            bracketed!(content in input);
-            let is_async = content.parse::<token::Async>().is_ok();
-            let guard: Ident = content.parse()?;
-            Some(AsyncIdent {
-                ident: guard,
-                is_async,
-            })
+            let brackets = bracketed!(content in input);
+            (
+                Some(content.parse::<GuardExpression>()?),
+                Some(brackets.span),
+            )
         } else {
-            None
+            (None, None)
         };
 
-        // Possible action
-        let action = if input.parse::<Token![/]>().is_ok() {
-            let is_async = input.parse::<token::Async>().is_ok();
-            let action: Ident = input.parse()?;
-            Some(AsyncIdent {
-                ident: action,
-                is_async,
-            })
+        // Possible action, or actions run in sequence given as `/ (a, b, c)`
+        let actions = if input.parse::<Token![/]>().is_ok() {
+            parse_actions(input)?
+        } else {
+            Vec::new()
+        };
+
+        // The output state is left out for internal transitions, which stay in the input state,
+        // and replaced by a choice pseudo-state when it depends on guards, or by the states the
+        // action chooses from as `{A | B}`
+        let mut targets = Vec::new();
+        let (out_state, choice) = if input.peek(Token![=]) && input.peek2(Token![?]) {
+            input.parse::<Token![=]>()?;
+            (None, Some(input.parse::<Choice>()?))
+        } else if input.peek(Token![=]) && input.peek2(token::Brace) {
+            input.parse::<Token![=]>()?;
+            let content;
+            let braces = braced!(content in input);
+            loop {
+                let target = OutputState::parse_target(&content)?;
+                if target.history.is_some() {
+                    return Err(parse::Error::new(
+                        target.ident.span(),
+                        "History can not be used in the targets chosen by an action.",
+                    ));
+                }
+                if targets
+                    .iter()
+                    .any(|t: &OutputState| t.ident == target.ident)
+                {
+                    return Err(parse::Error::new(
+                        target.ident.span(),
+                        "Target specified multiple times, remove duplicates.",
+                    ));
+                }
+                targets.push(target);
+
+                if content.is_empty() {
+                    break;
+                }
+                content.parse::<Token![|]>()?;
+            }
+
+            if actions.is_empty() {
+                return Err(parse::Error::new(
+                    braces.span,
+                    "Transitions to several targets need an action, which chooses the target.",
+                ));
+            }
+
+            (targets.first().cloned(), None)
+        } else if input.peek(Token![=]) {
+            (Some(input.parse::<OutputState>()?), None)
         } else {
-            None
+            (None, None)
         };
 
-        let out_state: OutputState = input.parse()?;
+        if let (Some(span), Some(_)) = (guard_span, &choice) {
+            return Err(parse::Error::new(
+                span,
+                "Transitions to a choice can not have a guard, the guards are given in its branches.",
+            ));
+        }
+
+        // The actions of the transition to a choice run before the guards of its branches, so one
+        // branch must always be taken once they have run
+        if let (Some(action), Some(choice)) = (actions.first(), &choice) {
+            if matches!(choice.branches.last(), Some(branch) if branch.guard.is_some()) {
+                return Err(parse::Error::new(
+                    action.ident.span(),
+                    "The actions of a transition to a choice run before its guards, so the choice needs an `else` branch.",
+                ));
+            }
+        }
 
         Ok(Self {
+            docs,
             in_states,
             event,
+            after,
             guard,
-            action,
+            actions,
             out_state,
+            choice,
+            targets,
         })
     }
 }
+
+/// Parses the actions following a `/`, a single action or several given as `(a, b, c)`.
+pub fn parse_actions(input: parse::ParseStream) -> syn::Result<Vec<AsyncIdent>> {
+    if !input.peek(token::Paren) {
+        return Ok(vec![parse_action(input)?]);
+    }
+
+    let content;
+    let parens = parenthesized!(content in input);
+    let actions: Vec<_> = content
+        .parse_terminated::<_, Token![,]>(parse_action)?
+        .into_iter()
+        .collect();
+    if actions.is_empty() {
+        return Err(parse::Error::new(
+            parens.span,
+            "At least one action must be given after `/`.",
+        ));
+    }
+
+    Ok(actions)
+}
+
+// helper function for parsing an optionally async action
+fn parse_action(input: parse::ParseStream) -> syn::Result<AsyncIdent> {
+    let is_async = input.parse::<token::Async>().is_ok();
+    let ident: Ident = input.parse()?;
+    Ok(AsyncIdent { ident, is_async })
+}
+
+// helper function for checking if the trigger of a transition is `after(<duration>)`
+fn is_timed(input: parse::ParseStream) -> bool {
+    let fork = input.fork();
+    matches!(fork.parse::<Ident>(), Ok(ident) if ident == "after") && fork.peek(token::Paren)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use syn::parse_quote;
+
+    #[test]
+    fn timed_transition() {
+        let transitions: StateTransitions = parse_quote! {
+            WaitingForCar after(Duration::from_secs(30)) [has_reservation] = Idle
+        };
+
+        assert!(transitions.event.is_none());
+        assert!(transitions.after.is_some());
+        assert_eq!(transitions.guard.unwrap().to_string(), "has_reservation");
+    }
+
+    #[test]
+    fn chained_actions() {
+        let transitions: StateTransitions = parse_quote! {
+            Idle + Plug / (log_start, async open_relay, start_meter,) = Charging
+        };
+
+        let actions: Vec<_> = transitions
+            .actions
+            .iter()
+            .map(|action| (action.ident.to_string(), action.is_async))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                ("log_start".to_string(), false),
+                ("open_relay".to_string(), true),
+                ("start_meter".to_string(), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn choice() {
+        let transitions: StateTransitions = parse_quote! {
+            Idle + Plug / log = ?{ [has_rfid] => Authorizing, else => Rejected }
+        };
+
+        assert!(transitions.out_state.is_none());
+        assert_eq!(transitions.actions.len(), 1);
+        assert_eq!(transitions.choice.unwrap().branches.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Transitions to a choice can not have a guard")]
+    fn guarded_choice() {
+        let _: StateTransitions = parse_quote! {
+            Idle + Plug [cable_ok] = ?{ [has_rfid] => Authorizing, else => Rejected }
+        };
+    }
+
+    #[test]
+    fn targets() {
+        let transitions: StateTransitions = parse_quote! {
+            WaitingForResponse + Response(Bytes) / parse_response = {Accepted | Rejected(Reason)!}
+        };
+
+        assert_eq!(transitions.out_state.unwrap().ident, "Accepted");
+        assert_eq!(transitions.targets.len(), 2);
+        assert!(transitions.targets[1].data_type.is_some());
+        assert!(transitions.targets[1].is_final);
+    }
+
+    #[test]
+    #[should_panic(expected = "Transitions to several targets need an action")]
+    fn targets_without_action() {
+        let _: StateTransitions = parse_quote! {
+            WaitingForResponse + Response = {Accepted | Rejected}
+        };
+    }
+
+    #[test]
+    fn docs() {
+        let transitions: StateTransitions = parse_quote! {
+            /// Starts charging once the cable is plugged in
+            /// and the driver is authorized
+            Idle + Plug [is_authorized] / start = Charging
+        };
+
+        assert_eq!(transitions.docs.len(), 2);
+        assert_eq!(transitions.in_states[0].ident, "Idle");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only doc comments can be given here.")]
+    fn attribute() {
+        let _: StateTransitions = parse_quote! {
+            #[inline]
+            Idle + Plug = Charging
+        };
+    }
+
+    #[test]
+    fn event_named_after() {
+        let transitions: StateTransitions = parse_quote! {
+            Idle + after(u32) = Idle
+        };
+
+        assert_eq!(transitions.event.unwrap().ident, "after");
+        assert!(transitions.after.is_none());
+    }
+}
@@ -1,7 +1,11 @@
 use crate::parser::{AsyncIdent, ParsedStateMachine};
 use proc_macro2::Span;
+use quote::{format_ident, quote};
 use std::collections::HashMap;
-use syn::parse;
+use syn::{parse, parse_quote};
+
+/// The number of values of the `#[repr(u8)]` state and event ids.
+const MAX_IDS: usize = 256;
 
 /// A basic representation an action call signature.
 #[derive(PartialEq, Clone)]
@@ -56,42 +60,105 @@ impl FunctionSignature {
 fn validate_action_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
     // Collect all of the action call signatures.
     let mut actions = HashMap::new();
+    let mut action_targets = HashMap::new();
 
-    let all_transitions = &sm.states_events_mapping;
-
-    for (in_state_name, from_transitions) in all_transitions.iter() {
-        let in_state_data = sm.state_data.data_types.get(in_state_name);
+    for event_mapping in sm.all_transitions() {
+        let in_state_data = sm
+            .state_data
+            .data_types
+            .get(&event_mapping.in_state.to_string());
 
-        for (out_state_name, event_mapping) in from_transitions.iter() {
-            let out_state_data = sm.state_data.data_types.get(out_state_name);
+        // An action which chooses the target of its transition returns an enum of the targets,
+        // so it must always choose from the same targets
+        let mut target_type: Option<syn::Type> = None;
+        if let (Some(action), false) = (
+            event_mapping.actions.last(),
+            event_mapping.targets.is_empty(),
+        ) {
+            let targets: Vec<String> = event_mapping
+                .targets
+                .iter()
+                .map(|target| target.to_string())
+                .collect();
+            let known_targets = action_targets
+                .entry(action.ident.to_string())
+                .or_insert_with(|| targets.clone());
+            if known_targets != &targets {
+                return Err(parse::Error::new(
+                    Span::call_site(),
+                    format!(
+                        "Action `{}` can only be reused when it chooses from the same targets",
+                        action.ident
+                    ),
+                ));
+            }
 
-            // Get the data associated with this event.
-            let event_data = sm
-                .event_data
+            let target_type_name = sm.target_type_name(&action.ident);
+            target_type = Some(parse_quote! { #target_type_name });
+        }
+        let out_state_data = match &target_type {
+            Some(target_type) => Some(target_type),
+            None => sm
+                .state_data
                 .data_types
-                .get(&event_mapping.event.to_string());
+                .get(&sm.initial_leaf(&event_mapping.out_state).to_string()),
+        };
 
-            if let Some(AsyncIdent {
+        // Get the data associated with this event.
+        let event_data = sm.event_data_type(event_mapping);
+
+        // Chained actions and the actions run before the guards of a choice borrow the data, and
+        // only the last one produces the output data
+        let last_action = event_mapping.actions.len().saturating_sub(1);
+        for (
+            index,
+            AsyncIdent {
                 ident: action,
                 is_async,
-            }) = &event_mapping.action
-            {
-                let signature =
-                    FunctionSignature::new(in_state_data, event_data, out_state_data, *is_async);
-
-                // If the action is not yet known, add it to our tracking list.
-                actions
-                    .entry(action.to_string())
-                    .or_insert_with(|| signature.clone());
-
-                // Check that the call signature is equivalent to the recorded signature for this
-                // action.
-                if actions.get(&action.to_string()).unwrap() != &signature {
-                    return Err(parse::Error::new(
+            },
+        ) in event_mapping.actions.iter().enumerate()
+        {
+            let chained = index < last_action || index < event_mapping.choice_actions;
+            let borrowed = |data: Option<&syn::Type>| -> Option<syn::Type> {
+                data.map(|data| match data {
+                    syn::Type::Reference(_) => data.clone(),
+                    _ => parse_quote! { &#data },
+                })
+            };
+
+            // Internal transitions update the state data in place
+            let signature = if event_mapping.internal {
+                let in_state_data: Option<syn::Type> =
+                    in_state_data.map(|data| parse_quote! { &mut #data });
+                let event_data = if chained {
+                    borrowed(event_data)
+                } else {
+                    event_data.cloned()
+                };
+                FunctionSignature::new(in_state_data.as_ref(), event_data.as_ref(), None, *is_async)
+            } else if chained {
+                FunctionSignature::new(
+                    borrowed(in_state_data).as_ref(),
+                    borrowed(event_data).as_ref(),
+                    None,
+                    *is_async,
+                )
+            } else {
+                FunctionSignature::new(in_state_data, event_data, out_state_data, *is_async)
+            };
+
+            // If the action is not yet known, add it to our tracking list.
+            actions
+                .entry(action.to_string())
+                .or_insert_with(|| signature.clone());
+
+            // Check that the call signature is equivalent to the recorded signature for this
+            // action.
+            if actions.get(&action.to_string()).unwrap() != &signature {
+                return Err(parse::Error::new(
                         Span::call_site(),
                         format!("Action `{}` can only be reused when all input states, events, and output states have the same data", action),
                     ));
-                }
             }
         }
     }
@@ -103,38 +170,34 @@ fn validate_guard_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error
     // Collect all of the guard call signatures.
     let mut guards = HashMap::new();
 
-    let all_transitions = &sm.states_events_mapping;
-
-    for (in_state_name, from_transitions) in all_transitions.iter() {
-        let in_state_data = sm.state_data.data_types.get(in_state_name);
+    for event_mapping in sm.all_transitions() {
+        let in_state_data = sm
+            .state_data
+            .data_types
+            .get(&event_mapping.in_state.to_string());
 
-        for (_out_state_name, event_mapping) in from_transitions.iter() {
-            // Get the data associated with this event.
-            let event_data = sm
-                .event_data
-                .data_types
-                .get(&event_mapping.event.to_string());
+        // Get the data associated with this event.
+        let event_data = sm.event_data_type(event_mapping);
 
-            if let Some(AsyncIdent {
-                ident: guard,
-                is_async,
-            }) = &event_mapping.guard
-            {
-                let signature = FunctionSignature::new_guard(in_state_data, event_data, *is_async);
+        for AsyncIdent {
+            ident: guard,
+            is_async,
+        } in event_mapping.guard.iter().flat_map(|guard| guard.guards())
+        {
+            let signature = FunctionSignature::new_guard(in_state_data, event_data, *is_async);
 
-                // If the action is not yet known, add it to our tracking list.
-                guards
-                    .entry(guard.to_string())
-                    .or_insert_with(|| signature.clone());
+            // If the action is not yet known, add it to our tracking list.
+            guards
+                .entry(guard.to_string())
+                .or_insert_with(|| signature.clone());
 
-                // Check that the call signature is equivalent to the recorded signature for this
-                // guard.
-                if guards.get(&guard.to_string()).unwrap() != &signature {
-                    return Err(parse::Error::new(
+            // Check that the call signature is equivalent to the recorded signature for this
+            // guard.
+            if guards.get(&guard.to_string()).unwrap() != &signature {
+                return Err(parse::Error::new(
                         Span::call_site(),
                         format!("Guard `{}` can only be reused when all input states and events have the same data", guard),
                     ));
-                }
             }
         }
     }
@@ -142,9 +205,218 @@ fn validate_guard_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error
     Ok(())
 }
 
+// Verify that entry and exit actions are only shared by states with the same data, and that they
+// are not also used as a guard or transition action.
+fn validate_entry_exit_signatures(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    let mut hooks = HashMap::new();
+
+    // Sort the hooks so errors are reported in a stable order
+    let mut all_hooks: Vec<_> = sm
+        .entry_actions
+        .iter()
+        .chain(sm.exit_actions.iter())
+        .collect();
+    all_hooks.sort_by_key(|(state_name, _)| state_name.to_string());
+
+    for (state_name, hook) in all_hooks {
+        let state_data = sm.state_data.data_types.get(state_name);
+        let signature = FunctionSignature::new(state_data, None, None, hook.is_async);
+
+        hooks
+            .entry(hook.ident.to_string())
+            .or_insert_with(|| signature.clone());
+
+        if hooks.get(&hook.ident.to_string()).unwrap() != &signature {
+            return Err(parse::Error::new(
+                hook.ident.span(),
+                format!(
+                    "Entry or exit action `{}` can only be reused when all states have the same data",
+                    hook.ident
+                ),
+            ));
+        }
+
+        let used_in_transition = sm
+            .all_transitions()
+            .flat_map(|event_mapping| {
+                event_mapping
+                    .guard
+                    .iter()
+                    .flat_map(|guard| guard.guards())
+                    .chain(&event_mapping.actions)
+            })
+            .any(|function| function.ident == hook.ident);
+
+        if used_in_transition {
+            return Err(parse::Error::new(
+                hook.ident.span(),
+                format!(
+                    "`{}` is used as an entry or exit action and can not also be used as a guard or action",
+                    hook.ident
+                ),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Verify that the events can be stored in the event queue.
+fn validate_event_queue(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    if sm.event_queue && !sm.event_data.all_lifetimes.is_empty() {
+        return Err(parse::Error::new(
+            Span::call_site(),
+            "The event queue can not be used when the data of an event has a lifetime.",
+        ));
+    }
+
+    Ok(())
+}
+
+// Verify that the events handled by several regions can be cloned, as each of these regions is
+// given its own copy of the event.
+fn validate_shared_events(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    let derives_clone = sm
+        .derive_events
+        .iter()
+        .any(|derive| matches!(derive.segments.last(), Some(last) if last.ident == "Clone"));
+    if derives_clone {
+        return Ok(());
+    }
+
+    let mut handled_events: Vec<&String> = Vec::new();
+    for (_, region) in sm.regions.iter() {
+        let mut region_events: Vec<_> = sm
+            .events
+            .iter()
+            .filter(|(event_name, _)| region.handles_event(event_name))
+            .collect();
+        region_events.sort_by_key(|(event_name, _)| event_name.to_string());
+
+        for &(event_name, event) in region_events.iter() {
+            if handled_events.contains(&event_name) {
+                return Err(parse::Error::new(
+                    event.span(),
+                    format!(
+                        "Event `{}` is handled by several regions, which each get a clone of it. Add `Clone` to `derive_events`.",
+                        event
+                    ),
+                ));
+            }
+        }
+        handled_events.extend(region_events.into_iter().map(|(event_name, _)| event_name));
+    }
+
+    Ok(())
+}
+
+// Verify that the completion transitions of the starting states can be taken when the state
+// machine is created, which can neither await nor be given a temporary context.
+fn validate_starting_completions(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    if !sm.is_async() && sm.temporary_context_type.is_none() {
+        return Ok(());
+    }
+
+    let machines = std::iter::once(sm).chain(sm.regions.iter().map(|(_, region)| region));
+    for machine in machines {
+        if let Some(starting_state) = &machine.starting_state {
+            if machine
+                .completion_transitions
+                .contains_key(&starting_state.to_string())
+            {
+                return Err(parse::Error::new(
+                    starting_state.span(),
+                    "The starting state can not have completion transitions when the state machine is async or has a temporary context, as they are taken when it is created.",
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Verify that the states and events fit in their ids, which are `#[repr(u8)]`.
+fn validate_ids(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
+    let too_many = |mut names: Vec<&syn::Ident>, id_type_name: String, kind: &str| {
+        names.sort_by_key(|name| name.to_string());
+        match names.get(MAX_IDS) {
+            Some(name) => Err(parse::Error::new(
+                name.span(),
+                format!(
+                    "Too many {}s, `{}` is `#[repr(u8)]` and can only hold {} of them.",
+                    kind, id_type_name, MAX_IDS
+                ),
+            )),
+            None => Ok(()),
+        }
+    };
+
+    if sm.regions.is_empty() {
+        too_many(
+            sm.states.values().collect(),
+            sm.state_id_type_name().to_string(),
+            "state",
+        )?;
+    }
+    for (region_name, region) in sm.regions.iter() {
+        let sm_name = sm.name.as_ref().map(|name| name.to_string());
+        too_many(
+            region.states.values().collect(),
+            format!("{}{}StateId", sm_name.unwrap_or_default(), region_name),
+            "state",
+        )?;
+    }
+    too_many(
+        sm.events.values().collect(),
+        sm.event_id_type_name().to_string(),
+        "event",
+    )
+}
+
+/// Warn about the transitions leaving final states, which are never taken. Proc macros can not emit
+/// warnings, so a deprecated item is used for each of them instead.
+pub fn warnings(sm: &ParsedStateMachine) -> proc_macro2::TokenStream {
+    let mut warnings = proc_macro2::TokenStream::new();
+
+    for final_state in sm.final_states.iter() {
+        let transition = sm
+            .all_transitions()
+            .find(|event_mapping| &event_mapping.in_state == final_state);
+        let transition = match transition {
+            Some(transition) => transition,
+            None => continue,
+        };
+
+        let note = format!(
+            "`{}` is a final state, so its transitions are never taken",
+            final_state
+        );
+        let warning = format_ident!(
+            "final_state_{}_has_transitions",
+            final_state,
+            span = transition.in_state.span()
+        );
+        warnings.extend(quote! {
+            const _: () = {
+                #[deprecated(note = #note)]
+                #[allow(non_upper_case_globals)]
+                const #warning: () = ();
+                #warning
+            };
+        });
+    }
+
+    warnings
+}
+
 /// Validate coherency of the state machine.
 pub fn validate(sm: &ParsedStateMachine) -> Result<(), parse::Error> {
     validate_action_signatures(sm)?;
     validate_guard_signatures(sm)?;
+    validate_entry_exit_signatures(sm)?;
+    validate_event_queue(sm)?;
+    validate_shared_events(sm)?;
+    validate_starting_completions(sm)?;
+    validate_ids(sm)?;
     Ok(())
 }
@@ -0,0 +1,18 @@
+//! The source of time for timed transitions.
+
+use core::ops::Add;
+
+/// A source of time for the timed transitions of a state machine, given with `after(<duration>)`.
+///
+/// Any monotonic time source can be used, such as the tick counter of an RTOS, a hardware timer,
+/// `std::time::Instant`, or a virtual clock which is advanced by a test.
+pub trait Clock {
+    /// A point in time.
+    type Instant: Copy + Ord + Add<Self::Duration, Output = Self::Instant>;
+
+    /// A span of time, which the durations of the timed transitions evaluate to.
+    type Duration;
+
+    /// Returns the current time.
+    fn now(&self) -> Self::Instant;
+}
@@ -66,5 +66,12 @@
 
 #![no_std]
 
+mod clock;
+mod queue;
+mod transition;
+
 pub use async_trait::async_trait;
+pub use clock::Clock;
+pub use queue::{EventQueue, EventSender, RunToCompletion};
 pub use smlang_macros::statemachine;
+pub use transition::TransitionInfo;
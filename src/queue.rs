@@ -0,0 +1,138 @@
+//! A bounded first-in first-out queue which does not need an allocator.
+
+/// A first-in first-out queue holding up to `N` items, used by the generated state machines to
+/// store events.
+#[derive(Debug)]
+pub struct EventQueue<E, const N: usize> {
+    items: [Option<E>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<E, const N: usize> EventQueue<E, N> {
+    const EMPTY: Option<E> = None;
+
+    /// Creates an empty queue.
+    pub const fn new() -> Self {
+        EventQueue {
+            items: [Self::EMPTY; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of items in the queue.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the queue holds no items.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of items the queue can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Adds an item to the back of the queue, or hands it back if the queue is full.
+    pub fn push(&mut self, item: E) -> Result<(), E> {
+        push(&mut self.items, self.head, &mut self.len, item)
+    }
+
+    /// Removes the item at the front of the queue.
+    pub fn pop(&mut self) -> Option<E> {
+        self.remove(0)
+    }
+
+    /// Returns a reference to the item at `index`, counting from the front of the queue.
+    pub fn get(&self, index: usize) -> Option<&E> {
+        if index >= self.len {
+            return None;
+        }
+
+        self.items[(self.head + index) % N].as_ref()
+    }
+
+    /// Removes the item at `index`, counting from the front of the queue, keeping the order of the
+    /// other items.
+    pub fn remove(&mut self, index: usize) -> Option<E> {
+        if index >= self.len {
+            return None;
+        }
+
+        let item = self.items[(self.head + index) % N].take();
+        if index == 0 {
+            self.head = (self.head + 1) % N;
+        } else {
+            for index in index..self.len - 1 {
+                let next = self.items[(self.head + index + 1) % N].take();
+                self.items[(self.head + index) % N] = next;
+            }
+        }
+        self.len -= 1;
+
+        item
+    }
+}
+
+impl<E, const N: usize> Default for EventQueue<E, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// helper function for adding an item to the back of a queue stored in `items`, shared by the
+// queue and the handle posting to it
+fn push<E>(items: &mut [Option<E>], head: usize, len: &mut usize, item: E) -> Result<(), E> {
+    if *len == items.len() {
+        return Err(item);
+    }
+
+    let index = (head + *len) % items.len();
+    items[index] = Some(item);
+    *len += 1;
+    Ok(())
+}
+
+/// A handle through which the actions of a state machine post events to its event queue. The
+/// posted events are processed after the event which is being processed.
+pub struct EventSender<'q, E> {
+    items: &'q mut [Option<E>],
+    head: usize,
+    len: &'q mut usize,
+    overflowed: &'q mut bool,
+}
+
+impl<'q, E> EventSender<'q, E> {
+    /// Creates a handle posting to `queue`, which sets `overflowed` when an event does not fit.
+    pub fn new<const N: usize>(queue: &'q mut EventQueue<E, N>, overflowed: &'q mut bool) -> Self {
+        EventSender {
+            items: &mut queue.items,
+            head: queue.head,
+            len: &mut queue.len,
+            overflowed,
+        }
+    }
+
+    /// Posts an event to the queue, or hands it back if the queue is full.
+    pub fn post(&mut self, event: E) -> Result<(), E> {
+        let result = push(self.items, self.head, self.len, event);
+        if result.is_err() {
+            *self.overflowed = true;
+        }
+        result
+    }
+}
+
+/// Reports how `process_event` of a state machine with an event queue went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunToCompletion {
+    /// The number of events which were processed, starting with the event passed to
+    /// `process_event` and followed by the events posted by the actions.
+    pub processed: usize,
+    /// `true` if an action posted an event while the queue was full, in which case that event was
+    /// dropped.
+    pub overflowed: bool,
+}
@@ -0,0 +1,22 @@
+//! The static description of the transitions of a state machine.
+
+/// A transition taken on an event, as listed in the `TRANSITIONS` table of a state machine.
+///
+/// `S` and `E` are the generated `StateId` and `EventId` enums. A transition of a composite state
+/// is listed for each of its substates, and a transition whose action chooses the target is listed
+/// once for every target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransitionInfo<S, E> {
+    /// The state the transition is taken in.
+    pub source: S,
+    /// The event the transition is taken on.
+    pub event: E,
+    /// The guard expression of the transition, such as `is_ready && !is_locked`.
+    pub guard: Option<&'static str>,
+    /// The action of the transition, with chained actions as `(a, b, c)`.
+    pub action: Option<&'static str>,
+    /// The state which is active after the transition. Internal transitions stay in `source`.
+    pub target: S,
+    /// Whether the transition was added for a wildcard input state or event.
+    pub wildcard: bool,
+}
@@ -0,0 +1,13 @@
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Waiting + Response / parse_response = {Accepted | Rejected},
+
+        // This transition is not valid because `parse_response` chooses from other targets here.
+        Accepted + Response / parse_response = {Waiting | Rejected},
+        Rejected + Reset = Waiting,
+    }
+}
+
+fn main() {}
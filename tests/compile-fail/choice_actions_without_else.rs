@@ -0,0 +1,14 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug / log_plug = ?{ //~ The actions of a transition to a choice run before its guards, so the choice needs an `else` branch.
+            [has_rfid] => Authorizing,
+            [free_mode] => Charging,
+        },
+    }
+}
+
+fn main() {}
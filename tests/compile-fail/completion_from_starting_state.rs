@@ -0,0 +1,13 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    temporary_context: &mut u32,
+    transitions: {
+        *Idle [ready] = Running, //~ The starting state can not have completion transitions when the state machine is async or has a temporary context, as they are taken when it is created.
+        Running + Stop = Idle,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Start = Running,
+        Running / update, //~ Completion transitions need an output state.
+    }
+}
+
+fn main() {}
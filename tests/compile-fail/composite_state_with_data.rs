@@ -0,0 +1,12 @@
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug / start = Session(u32), //~ Composite states can not have data associated with them.
+    },
+    states: {
+        Session: { substates: { *Charging } },
+    }
+}
+
+fn main() {}
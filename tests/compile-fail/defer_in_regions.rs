@@ -0,0 +1,19 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    regions: {
+        Connectivity: {
+            transitions: {
+                *Offline + Connect = Online,
+                Online + Heartbeat = Online,
+            },
+            states: {
+                Offline: { defer: [Heartbeat] }, //~ Deferred events can not be used with regions, as another region may handle the deferred event.
+            },
+        },
+    }
+}
+
+fn main() {}
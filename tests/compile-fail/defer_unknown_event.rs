@@ -0,0 +1,15 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug = Authorizing,
+        Authorizing + Authorized = Idle,
+    },
+    states: {
+        Authorizing: { defer: [MeterValue] }, //~ Unknown event, only events which are used in a transition can be deferred.
+    }
+}
+
+fn main() {}
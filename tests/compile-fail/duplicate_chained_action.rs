@@ -0,0 +1,13 @@
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Init + Event / (log, action) = State1(u32),
+
+        // This transition is not valid because `log` borrows the data of `State1` when chained,
+        // while it takes no arguments earlier.
+        State1(u32) + Event / (log, finish) = State2,
+    }
+}
+
+fn main() {}
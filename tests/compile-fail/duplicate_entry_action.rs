@@ -0,0 +1,15 @@
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Init + Event / action = State1(u32),
+        State1(u32) + Event = State2,
+    },
+    states: {
+        // This is not valid because `enter` would have different input arguments for each state.
+        State1: { entry: enter },
+        State2: { entry: enter },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    regions: {
+        Connectivity: {
+            transitions: {
+                *Offline + Connect = Online,
+                Online + Reset = Offline,
+            },
+        },
+        ChargePoint: {
+            transitions: {
+                *Available + Plug = Charging,
+                Charging + Reset(u32) = Available, //~ This event's type does not match its definition in another region.
+            },
+        },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! { //~ The event queue can not be used when the data of an event has a lifetime.
+    event_queue: true,
+    transitions: {
+        *Idle + Receive(&'a [u8]) = Idle,
+    }
+}
+
+fn main() {}
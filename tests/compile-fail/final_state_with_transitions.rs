@@ -0,0 +1,15 @@
+#![deny(deprecated)]
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug = Charging,
+        Charging + Unplug = Done!,
+
+        // This transition is never taken, as `Done` is a final state.
+        Done + Plug = Charging,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Pause = Paused,
+        Paused + Resume = Idle.H, //~ History can only be used with composite states.
+    }
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug = Session,
+        Session + Pause = Paused,
+        Paused + Resume = Session.H, //~ History can only be used with composite states whose substates have no data.
+        Authorizing + Authorized / start = Charging(u32),
+    },
+    states: {
+        Session: { substates: { *Authorizing, Charging } },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,23 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    derive_events: [Debug],
+    regions: {
+        Connectivity: {
+            transitions: {
+                *Offline + Connect = Online,
+                _ + PowerLoss = Offline,
+            },
+        },
+        ChargePoint: {
+            transitions: {
+                *Available + Plug = Charging,
+                Charging + PowerLoss = Faulted, //~ Event `PowerLoss` is handled by several regions, which each get a clone of it. Add `Clone` to `derive_events`.
+            },
+        },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,17 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    state_id_type: StateKind, //~ `state_id_type` can not be used with regions, the state ids of each region are named after the region.
+    regions: {
+        Connectivity: {
+            transitions: {
+                *Offline + Connect = Online,
+                Online + Disconnect = Offline,
+            },
+        },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,22 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    regions: {
+        Connectivity: {
+            transitions: {
+                *Offline + Connect = Online,
+                Online + Disconnect = Offline,
+            },
+        },
+        ChargePoint: {
+            transitions: {
+                *Available + Plug = Charging,
+                Charging + PowerLoss = Offline, //~ State is used in multiple regions, the states of each region must be unique.
+            },
+        },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug = Authorizing,
+        Authorizing = Charging,
+    },
+    states: {
+        Authorizing: { submachine: Auth }, //~ The state of a submachine needs the submachine as its data
+    }
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Plug = WaitingForCar,
+        WaitingForCar after(30) = Idle, //~ Timed transitions need a clock, given with `clock: <type>`.
+    }
+}
+
+fn main() {}
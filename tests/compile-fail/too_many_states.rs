@@ -0,0 +1,266 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *S000 + Next = S001,
+        S001 + Next = S002,
+        S002 + Next = S003,
+        S003 + Next = S004,
+        S004 + Next = S005,
+        S005 + Next = S006,
+        S006 + Next = S007,
+        S007 + Next = S008,
+        S008 + Next = S009,
+        S009 + Next = S010,
+        S010 + Next = S011,
+        S011 + Next = S012,
+        S012 + Next = S013,
+        S013 + Next = S014,
+        S014 + Next = S015,
+        S015 + Next = S016,
+        S016 + Next = S017,
+        S017 + Next = S018,
+        S018 + Next = S019,
+        S019 + Next = S020,
+        S020 + Next = S021,
+        S021 + Next = S022,
+        S022 + Next = S023,
+        S023 + Next = S024,
+        S024 + Next = S025,
+        S025 + Next = S026,
+        S026 + Next = S027,
+        S027 + Next = S028,
+        S028 + Next = S029,
+        S029 + Next = S030,
+        S030 + Next = S031,
+        S031 + Next = S032,
+        S032 + Next = S033,
+        S033 + Next = S034,
+        S034 + Next = S035,
+        S035 + Next = S036,
+        S036 + Next = S037,
+        S037 + Next = S038,
+        S038 + Next = S039,
+        S039 + Next = S040,
+        S040 + Next = S041,
+        S041 + Next = S042,
+        S042 + Next = S043,
+        S043 + Next = S044,
+        S044 + Next = S045,
+        S045 + Next = S046,
+        S046 + Next = S047,
+        S047 + Next = S048,
+        S048 + Next = S049,
+        S049 + Next = S050,
+        S050 + Next = S051,
+        S051 + Next = S052,
+        S052 + Next = S053,
+        S053 + Next = S054,
+        S054 + Next = S055,
+        S055 + Next = S056,
+        S056 + Next = S057,
+        S057 + Next = S058,
+        S058 + Next = S059,
+        S059 + Next = S060,
+        S060 + Next = S061,
+        S061 + Next = S062,
+        S062 + Next = S063,
+        S063 + Next = S064,
+        S064 + Next = S065,
+        S065 + Next = S066,
+        S066 + Next = S067,
+        S067 + Next = S068,
+        S068 + Next = S069,
+        S069 + Next = S070,
+        S070 + Next = S071,
+        S071 + Next = S072,
+        S072 + Next = S073,
+        S073 + Next = S074,
+        S074 + Next = S075,
+        S075 + Next = S076,
+        S076 + Next = S077,
+        S077 + Next = S078,
+        S078 + Next = S079,
+        S079 + Next = S080,
+        S080 + Next = S081,
+        S081 + Next = S082,
+        S082 + Next = S083,
+        S083 + Next = S084,
+        S084 + Next = S085,
+        S085 + Next = S086,
+        S086 + Next = S087,
+        S087 + Next = S088,
+        S088 + Next = S089,
+        S089 + Next = S090,
+        S090 + Next = S091,
+        S091 + Next = S092,
+        S092 + Next = S093,
+        S093 + Next = S094,
+        S094 + Next = S095,
+        S095 + Next = S096,
+        S096 + Next = S097,
+        S097 + Next = S098,
+        S098 + Next = S099,
+        S099 + Next = S100,
+        S100 + Next = S101,
+        S101 + Next = S102,
+        S102 + Next = S103,
+        S103 + Next = S104,
+        S104 + Next = S105,
+        S105 + Next = S106,
+        S106 + Next = S107,
+        S107 + Next = S108,
+        S108 + Next = S109,
+        S109 + Next = S110,
+        S110 + Next = S111,
+        S111 + Next = S112,
+        S112 + Next = S113,
+        S113 + Next = S114,
+        S114 + Next = S115,
+        S115 + Next = S116,
+        S116 + Next = S117,
+        S117 + Next = S118,
+        S118 + Next = S119,
+        S119 + Next = S120,
+        S120 + Next = S121,
+        S121 + Next = S122,
+        S122 + Next = S123,
+        S123 + Next = S124,
+        S124 + Next = S125,
+        S125 + Next = S126,
+        S126 + Next = S127,
+        S127 + Next = S128,
+        S128 + Next = S129,
+        S129 + Next = S130,
+        S130 + Next = S131,
+        S131 + Next = S132,
+        S132 + Next = S133,
+        S133 + Next = S134,
+        S134 + Next = S135,
+        S135 + Next = S136,
+        S136 + Next = S137,
+        S137 + Next = S138,
+        S138 + Next = S139,
+        S139 + Next = S140,
+        S140 + Next = S141,
+        S141 + Next = S142,
+        S142 + Next = S143,
+        S143 + Next = S144,
+        S144 + Next = S145,
+        S145 + Next = S146,
+        S146 + Next = S147,
+        S147 + Next = S148,
+        S148 + Next = S149,
+        S149 + Next = S150,
+        S150 + Next = S151,
+        S151 + Next = S152,
+        S152 + Next = S153,
+        S153 + Next = S154,
+        S154 + Next = S155,
+        S155 + Next = S156,
+        S156 + Next = S157,
+        S157 + Next = S158,
+        S158 + Next = S159,
+        S159 + Next = S160,
+        S160 + Next = S161,
+        S161 + Next = S162,
+        S162 + Next = S163,
+        S163 + Next = S164,
+        S164 + Next = S165,
+        S165 + Next = S166,
+        S166 + Next = S167,
+        S167 + Next = S168,
+        S168 + Next = S169,
+        S169 + Next = S170,
+        S170 + Next = S171,
+        S171 + Next = S172,
+        S172 + Next = S173,
+        S173 + Next = S174,
+        S174 + Next = S175,
+        S175 + Next = S176,
+        S176 + Next = S177,
+        S177 + Next = S178,
+        S178 + Next = S179,
+        S179 + Next = S180,
+        S180 + Next = S181,
+        S181 + Next = S182,
+        S182 + Next = S183,
+        S183 + Next = S184,
+        S184 + Next = S185,
+        S185 + Next = S186,
+        S186 + Next = S187,
+        S187 + Next = S188,
+        S188 + Next = S189,
+        S189 + Next = S190,
+        S190 + Next = S191,
+        S191 + Next = S192,
+        S192 + Next = S193,
+        S193 + Next = S194,
+        S194 + Next = S195,
+        S195 + Next = S196,
+        S196 + Next = S197,
+        S197 + Next = S198,
+        S198 + Next = S199,
+        S199 + Next = S200,
+        S200 + Next = S201,
+        S201 + Next = S202,
+        S202 + Next = S203,
+        S203 + Next = S204,
+        S204 + Next = S205,
+        S205 + Next = S206,
+        S206 + Next = S207,
+        S207 + Next = S208,
+        S208 + Next = S209,
+        S209 + Next = S210,
+        S210 + Next = S211,
+        S211 + Next = S212,
+        S212 + Next = S213,
+        S213 + Next = S214,
+        S214 + Next = S215,
+        S215 + Next = S216,
+        S216 + Next = S217,
+        S217 + Next = S218,
+        S218 + Next = S219,
+        S219 + Next = S220,
+        S220 + Next = S221,
+        S221 + Next = S222,
+        S222 + Next = S223,
+        S223 + Next = S224,
+        S224 + Next = S225,
+        S225 + Next = S226,
+        S226 + Next = S227,
+        S227 + Next = S228,
+        S228 + Next = S229,
+        S229 + Next = S230,
+        S230 + Next = S231,
+        S231 + Next = S232,
+        S232 + Next = S233,
+        S233 + Next = S234,
+        S234 + Next = S235,
+        S235 + Next = S236,
+        S236 + Next = S237,
+        S237 + Next = S238,
+        S238 + Next = S239,
+        S239 + Next = S240,
+        S240 + Next = S241,
+        S241 + Next = S242,
+        S242 + Next = S243,
+        S243 + Next = S244,
+        S244 + Next = S245,
+        S245 + Next = S246,
+        S246 + Next = S247,
+        S247 + Next = S248,
+        S248 + Next = S249,
+        S249 + Next = S250,
+        S250 + Next = S251,
+        S251 + Next = S252,
+        S252 + Next = S253,
+        S253 + Next = S254,
+        S254 + Next = S255,
+        S255 + Next = S256, //~ Too many states, `StateId` is `#[repr(u8)]` and can only hold 256 of them.
+    }
+}
+
+fn main() {}
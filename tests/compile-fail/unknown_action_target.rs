@@ -0,0 +1,11 @@
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Waiting + Response(u32) / parse_response = {Accepted | Rejcted}, //~ Unknown state
+        Accepted + Reset = Waiting,
+        Rejected + Reset = Waiting,
+    }
+}
+
+fn main() {}
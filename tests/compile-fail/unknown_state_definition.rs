@@ -0,0 +1,12 @@
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *State1 + Event1 = State2,
+    },
+    states: {
+        State3: { entry: enter }, //~ This state is not used in any transition.
+    }
+}
+
+fn main() {}
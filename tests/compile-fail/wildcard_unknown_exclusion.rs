@@ -0,0 +1,13 @@
+extern crate smlang;
+
+use smlang::statemachine;
+
+statemachine! {
+    transitions: {
+        *Idle + Update = Updating,
+        Updating + Done = Idle,
+        _ - (Updating | Flashing) + Reset = Idle, //~ Unknown state
+    }
+}
+
+fn main() {}
@@ -163,3 +163,2657 @@ fn async_guards_and_actions() {
         assert!(matches!(sm.state(), Ok(&States::Fault)));
     });
 }
+
+#[test]
+fn guarded_transitions_for_same_state_and_event() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug [cable_ok] = Charging,
+            Idle + Plug [cable_locked] = Locked,
+            Charging + Unplug = Idle,
+            Locked + Unplug = Idle,
+        }
+    }
+
+    struct Context {
+        cable_ok: bool,
+        cable_locked: bool,
+    }
+
+    impl StateMachineContext for Context {
+        fn cable_ok(&mut self) -> Result<(), ()> {
+            if self.cable_ok {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn cable_locked(&mut self) -> Result<(), ()> {
+            if self.cable_locked {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    let mut sm = StateMachine::new(Context {
+        cable_ok: true,
+        cable_locked: true,
+    });
+
+    // The first guard in declaration order wins
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Charging)));
+    sm.process_event(Events::Unplug).unwrap();
+
+    // When the first guard rejects, the next candidate is tried
+    sm.context_mut().cable_ok = false;
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Locked)));
+    sm.process_event(Events::Unplug).unwrap();
+
+    // Only when every candidate rejects is the guard failure reported
+    sm.context_mut().cable_locked = false;
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Err(Error::GuardFailed(()))
+    ));
+    assert!(matches!(sm.state(), Ok(&States::Idle)));
+}
+
+#[test]
+fn entry_and_exit_actions() {
+    #[derive(Debug, PartialEq)]
+    pub struct Meter(u32);
+
+    statemachine! {
+        transitions: {
+            *Idle + Plug / start = Charging(Meter),
+            Charging(Meter) + Unplug = Idle,
+            Charging(Meter) + Restart / restart = Charging(Meter),
+        },
+        states: {
+            Idle: { exit: leave_idle },
+            Charging: { entry: start_meter, exit: stop_meter },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        calls: Vec<String>,
+    }
+
+    impl StateMachineContext for Context {
+        fn start(&mut self) -> Meter {
+            self.calls.push("start".into());
+            Meter(0)
+        }
+
+        fn restart(&mut self, state_data: Meter) -> Meter {
+            self.calls.push("restart".into());
+            Meter(state_data.0 + 1)
+        }
+
+        fn leave_idle(&mut self) {
+            self.calls.push("leave_idle".into());
+        }
+
+        fn start_meter(&mut self, state_data: &Meter) {
+            self.calls.push(format!("start_meter({})", state_data.0));
+        }
+
+        fn stop_meter(&mut self, state_data: &Meter) {
+            self.calls.push(format!("stop_meter({})", state_data.0));
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    sm.process_event(Events::Plug).unwrap();
+    assert_eq!(
+        sm.context().calls,
+        ["leave_idle", "start", "start_meter(0)"]
+    );
+
+    // Self transitions leave and re-enter the state
+    sm.context_mut().calls.clear();
+    sm.process_event(Events::Restart).unwrap();
+    assert_eq!(
+        sm.context().calls,
+        ["stop_meter(0)", "restart", "start_meter(1)"]
+    );
+
+    sm.context_mut().calls.clear();
+    sm.process_event(Events::Unplug).unwrap();
+    assert_eq!(sm.context().calls, ["stop_meter(1)"]);
+    assert!(matches!(sm.state(), Ok(&States::Idle)));
+}
+
+#[test]
+fn async_entry_and_exit_actions() {
+    use smol;
+
+    smol::block_on(async {
+        statemachine! {
+            transitions: {
+                *Idle + Plug = Charging,
+                Charging + Unplug = Idle,
+            },
+            states: {
+                Idle: { exit: async leave_idle },
+                Charging: { entry: async start_meter, exit: stop_meter },
+            }
+        }
+
+        #[derive(Default)]
+        struct Context {
+            calls: Vec<&'static str>,
+        }
+
+        #[smlang::async_trait]
+        impl StateMachineContext for Context {
+            async fn leave_idle(&mut self) {
+                self.calls.push("leave_idle");
+            }
+
+            async fn start_meter(&mut self) {
+                self.calls.push("start_meter");
+            }
+
+            fn stop_meter(&mut self) {
+                self.calls.push("stop_meter");
+            }
+        }
+
+        let mut sm = StateMachine::new(Context::default());
+
+        sm.process_event(Events::Plug).await.unwrap();
+        assert!(matches!(sm.state(), Ok(&States::Charging)));
+        assert_eq!(sm.context().calls, ["leave_idle", "start_meter"]);
+
+        sm.context_mut().calls.clear();
+        sm.process_event(Events::Unplug).await.unwrap();
+        assert!(matches!(sm.state(), Ok(&States::Idle)));
+        assert_eq!(sm.context().calls, ["stop_meter"]);
+    });
+}
+
+#[test]
+fn hierarchical_states() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug = Session,
+            Session + EmergencyStop / emergency_stop = Fault,
+            Authorizing + Authorized = Charging,
+            Charging + EmergencyStop [stop_ignored] = Charging,
+            Charging + Unplug = Idle,
+            Bulk + Full = Float,
+            Fault + Reset = Idle,
+        },
+        states: {
+            Session: {
+                entry: enter_session,
+                exit: exit_session,
+                substates: {
+                    *Authorizing,
+                    Charging: {
+                        exit: exit_charging,
+                        substates: { *Bulk, Float },
+                    },
+                },
+            },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        ignore_stop: bool,
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn stop_ignored(&mut self) -> Result<(), ()> {
+            if self.ignore_stop {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn emergency_stop(&mut self) {
+            self.calls.push("emergency_stop");
+        }
+
+        fn enter_session(&mut self) {
+            self.calls.push("enter_session");
+        }
+
+        fn exit_session(&mut self) {
+            self.calls.push("exit_session");
+        }
+
+        fn exit_charging(&mut self) {
+            self.calls.push("exit_charging");
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(!sm.state().unwrap().is_in(SuperStates::Session));
+
+    // Entering a composite state enters its initial substate
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Authorizing)));
+    assert!(sm.state().unwrap().is_in(SuperStates::Session));
+    assert!(!sm.state().unwrap().is_in(SuperStates::Charging));
+    assert_eq!(sm.context().calls, ["enter_session"]);
+
+    // Transitions between substates do not leave the composite state
+    sm.context_mut().calls.clear();
+    sm.process_event(Events::Authorized).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Bulk)));
+    assert!(sm.state().unwrap().is_in(SuperStates::Session));
+    assert!(sm.state().unwrap().is_in(SuperStates::Charging));
+    assert!(sm.context().calls.is_empty());
+
+    sm.process_event(Events::Full).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Float)));
+    assert!(sm.state().unwrap().is_in(SuperStates::Charging));
+
+    // Substates get the first chance to handle an event
+    sm.context_mut().ignore_stop = true;
+    sm.process_event(Events::EmergencyStop).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Bulk)));
+    assert_eq!(sm.context().calls, ["exit_charging"]);
+
+    // Events not handled by a substate bubble up to its ancestors
+    sm.context_mut().calls.clear();
+    sm.context_mut().ignore_stop = false;
+    sm.process_event(Events::EmergencyStop).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Fault)));
+    assert_eq!(
+        sm.context().calls,
+        ["exit_charging", "exit_session", "emergency_stop"]
+    );
+
+    // Events not handled by any ancestor are still invalid
+    sm.process_event(Events::Reset).unwrap();
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(sm.state(), Ok(&States::Authorizing)));
+}
+
+#[test]
+fn orthogonal_regions() {
+    statemachine! {
+        derive_states: [Debug],
+        derive_events: [Clone, Debug],
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Offline + Connect = Online,
+                    Online + Disconnect = Offline,
+                    _ + PowerLoss = Offline,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Available + Plug(u32) [accept] / start_session = Charging,
+                    Charging(u32) + Unplug / stop_session = Available,
+                    Charging(u32) + PowerLoss / stop_session = Faulted,
+                },
+                states: {
+                    Charging: { entry: enter_charging },
+                },
+            },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn accept(&mut self, connector: &u32) -> Result<(), ()> {
+            if *connector == 1 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn start_session(&mut self, connector: u32) -> u32 {
+            connector
+        }
+
+        fn stop_session(&mut self, _connector: u32) {
+            self.calls.push("stop_session");
+        }
+
+        fn enter_charging(&mut self, _connector: &u32) {
+            self.calls.push("enter_charging");
+        }
+
+        fn log_connectivity_state_change(&self, _new_state: &ConnectivityStates) {}
+
+        fn log_charge_point_state_change(&self, _new_state: &ChargePointStates) {}
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    let state = sm.state().unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Available));
+
+    // Events are only processed by the regions which handle them
+    let state = sm.process_event(Events::Connect).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Online));
+    assert!(matches!(state.charge_point, &ChargePointStates::Available));
+
+    assert!(matches!(
+        sm.process_event(Events::Plug(2)),
+        Err(Error::GuardFailed(()))
+    ));
+    let state = sm.process_event(Events::Plug(1)).unwrap();
+    assert!(matches!(
+        state.charge_point,
+        &ChargePointStates::Charging(1)
+    ));
+    assert_eq!(sm.context().calls, ["enter_charging"]);
+
+    // Events handled by several regions are processed by all of them
+    let state = sm.process_event(Events::PowerLoss).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Faulted));
+    assert_eq!(sm.context().calls, ["enter_charging", "stop_session"]);
+
+    // An event is accepted as long as one of the regions takes a transition
+    sm.process_event(Events::PowerLoss).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Disconnect),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Err(Error::InvalidEvent)
+    ));
+
+    let sm = StateMachine::new_with_state(
+        Context::default(),
+        ConnectivityStates::Online,
+        ChargePointStates::Charging(2),
+    );
+    assert_eq!(
+        format!("{:?}", sm.state().unwrap()),
+        "States { connectivity: Online, charge_point: Charging(2) }"
+    );
+
+    // Each region has its own transition table
+    assert_eq!(StateMachine::<Context>::CHARGE_POINT_TRANSITIONS.len(), 3);
+    assert_eq!(
+        StateMachine::<Context>::connectivity_successors(ConnectivityStateId::Online),
+        [ConnectivityStateId::Offline]
+    );
+}
+
+#[test]
+fn history_states() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug = Session,
+            Idle + Pause = Paused,
+            Session + Pause = Paused,
+            Paused + Resume = Session.H,
+            Paused + ResumeDeep = Session.H*,
+            Authorizing + Authorized = Charging,
+            Bulk + Full = Float,
+        },
+        states: {
+            Session: {
+                entry: enter_session,
+                substates: {
+                    *Authorizing,
+                    Charging: {
+                        entry: enter_charging,
+                        substates: { *Bulk, Float },
+                    },
+                },
+            },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn enter_session(&mut self) {
+            self.calls.push("enter_session");
+        }
+
+        fn enter_charging(&mut self) {
+            self.calls.push("enter_charging");
+        }
+    }
+
+    // Without history the initial substates are entered
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Pause).unwrap();
+    sm.process_event(Events::ResumeDeep).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Authorizing)));
+
+    sm.process_event(Events::Authorized).unwrap();
+    sm.process_event(Events::Full).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Float)));
+
+    // Deep history enters the last active substate at any depth
+    sm.process_event(Events::Pause).unwrap();
+    sm.context_mut().calls.clear();
+    sm.process_event(Events::ResumeDeep).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Float)));
+    assert_eq!(sm.context().calls, ["enter_session", "enter_charging"]);
+
+    // Shallow history enters the last active direct substate through its initial substates
+    sm.process_event(Events::Pause).unwrap();
+    sm.context_mut().calls.clear();
+    sm.process_event(Events::Resume).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Bulk)));
+    assert_eq!(sm.context().calls, ["enter_session", "enter_charging"]);
+
+    // The history is kept when re-entering the composite state
+    sm.process_event(Events::Pause).unwrap();
+    sm.process_event(Events::Resume).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Bulk)));
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Plug).unwrap();
+    sm.process_event(Events::Pause).unwrap();
+    sm.process_event(Events::Resume).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Authorizing)));
+}
+
+#[test]
+fn internal_transitions() {
+    #[derive(Debug, PartialEq)]
+    pub struct Meter(u32);
+
+    statemachine! {
+        transitions: {
+            *Idle + Plug / start = Charging(Meter),
+            Charging(Meter) + Tick(u32) [metering] / update_meter,
+            Charging(Meter) + Unplug / stop = Idle,
+            Session + Ping / ping,
+        },
+        states: {
+            Session: { entry: enter_session, exit: exit_session, substates: { *Charging } },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        metering: bool,
+        calls: Vec<&'static str>,
+        state_changes: core::cell::Cell<usize>,
+    }
+
+    impl StateMachineContext for Context {
+        fn metering(&mut self, _state_data: &Meter, _event_data: &u32) -> Result<(), ()> {
+            if self.metering {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn start(&mut self) -> Meter {
+            Meter(0)
+        }
+
+        fn update_meter(&mut self, state_data: &mut Meter, event_data: u32) {
+            state_data.0 += event_data;
+        }
+
+        fn stop(&mut self, _state_data: Meter) {}
+
+        fn ping(&mut self) {
+            self.calls.push("ping");
+        }
+
+        fn enter_session(&mut self) {
+            self.calls.push("enter_session");
+        }
+
+        fn exit_session(&mut self) {
+            self.calls.push("exit_session");
+        }
+
+        fn log_state_change(&self, _new_state: &States) {
+            self.state_changes.set(self.state_changes.get() + 1);
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Plug).unwrap();
+    sm.context_mut().calls.clear();
+    sm.context_mut().metering = true;
+
+    // The action updates the data of the active state, which is not left
+    sm.process_event(Events::Tick(5)).unwrap();
+    sm.process_event(Events::Tick(2)).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Charging(Meter(7)))));
+    assert!(sm.context().calls.is_empty());
+    assert_eq!(sm.context().state_changes.get(), 1);
+
+    // Internal transitions can be guarded
+    sm.context_mut().metering = false;
+    assert!(matches!(
+        sm.process_event(Events::Tick(1)),
+        Err(Error::GuardFailed(()))
+    ));
+    assert!(matches!(sm.state(), Ok(&States::Charging(Meter(7)))));
+
+    // Internal transitions of composite states do not run their exit or entry actions
+    sm.process_event(Events::Ping).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Charging(Meter(7)))));
+    assert_eq!(sm.context().calls, ["ping"]);
+    assert_eq!(sm.context().state_changes.get(), 1);
+
+    sm.process_event(Events::Unplug).unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Idle)));
+    assert_eq!(sm.context().calls, ["ping", "exit_session"]);
+}
+
+#[test]
+fn completion_transitions() {
+    #[derive(Debug, PartialEq)]
+    pub struct Reading(u32);
+
+    statemachine! {
+        transitions: {
+            *Idle + Measure / measure = Evaluating(Reading),
+            Evaluating(Reading) [in_range] / accept = Accepted,
+            Evaluating(Reading) [too_high] = Rejected,
+            Evaluating(Reading) = Retrying,
+            Accepted + Reset = Idle,
+            Rejected + Reset = Idle,
+            Retrying + Reset = Idle,
+        },
+        states: {
+            Accepted: { entry: enter_accepted },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        reading: u32,
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn in_range(&mut self, state_data: &Reading) -> Result<(), ()> {
+            if state_data.0 < 10 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn too_high(&mut self, state_data: &Reading) -> Result<(), ()> {
+            if state_data.0 < 100 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn measure(&mut self) -> Reading {
+            Reading(self.reading)
+        }
+
+        fn accept(&mut self, _state_data: Reading) {
+            self.calls.push("accept");
+        }
+
+        fn enter_accepted(&mut self) {
+            self.calls.push("enter_accepted");
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // The completion transition is taken as soon as its state is entered
+    sm.context_mut().reading = 5;
+    assert!(matches!(
+        sm.process_event(Events::Measure),
+        Ok(&States::Accepted)
+    ));
+    assert_eq!(sm.context().calls, ["accept", "enter_accepted"]);
+    sm.process_event(Events::Reset).unwrap();
+
+    // The guards are tried in declaration order
+    sm.context_mut().reading = 50;
+    assert!(matches!(
+        sm.process_event(Events::Measure),
+        Ok(&States::Rejected)
+    ));
+    sm.process_event(Events::Reset).unwrap();
+
+    sm.context_mut().reading = 500;
+    assert!(matches!(
+        sm.process_event(Events::Measure),
+        Ok(&States::Retrying)
+    ));
+}
+
+#[test]
+fn completion_transitions_without_passing_guard() {
+    statemachine! {
+        transitions: {
+            *Idle + Start = Waiting,
+            Waiting [ready] = Running,
+            Waiting + Poll = Waiting,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        ready: bool,
+    }
+
+    impl StateMachineContext for Context {
+        fn ready(&mut self) -> Result<(), ()> {
+            if self.ready {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // When the guard fails the entered state is kept
+    assert!(matches!(
+        sm.process_event(Events::Start),
+        Ok(&States::Waiting)
+    ));
+
+    // Entering the state again takes the completion transition once its guard passes
+    sm.context_mut().ready = true;
+    assert!(matches!(
+        sm.process_event(Events::Poll),
+        Ok(&States::Running)
+    ));
+}
+
+#[test]
+fn completion_limit() {
+    statemachine! {
+        completion_limit: 5,
+        transitions: {
+            *Idle + Start = Ping,
+            Ping [bouncing] = Pong,
+            Pong = Ping,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        bounces: usize,
+    }
+
+    impl StateMachineContext for Context {
+        fn bouncing(&mut self) -> Result<(), ()> {
+            self.bounces += 1;
+            Ok(())
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // A cycle of completion transitions stops at the completion limit
+    assert!(matches!(
+        sm.process_event(Events::Start),
+        Err(Error::CompletionLimitReached)
+    ));
+    assert_eq!(sm.context().bounces, 3);
+    assert!(matches!(sm.state(), Ok(&States::Pong)));
+}
+
+#[test]
+fn completion_from_starting_state() {
+    statemachine! {
+        transitions: {
+            *Booting [configured] = Idle,
+            Booting + Configure = Booting,
+            Idle + Start = Running,
+        },
+        states: {
+            Idle: { entry: enter_idle },
+        },
+    }
+
+    #[derive(Default)]
+    struct Context {
+        configured: bool,
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn configured(&mut self) -> Result<(), ()> {
+            if self.configured {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn enter_idle(&mut self) {
+            self.calls.push("enter_idle");
+        }
+    }
+
+    // The completion transitions of the starting state are taken when the state machine is created
+    let mut sm = StateMachine::new(Context {
+        configured: true,
+        ..Context::default()
+    });
+    assert!(matches!(sm.state(), Ok(&States::Idle)));
+    assert_eq!(sm.context().calls, ["enter_idle"]);
+    assert!(matches!(
+        sm.process_event(Events::Start),
+        Ok(&States::Running)
+    ));
+
+    // When the guard fails the starting state is kept until it is entered again
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(sm.state(), Ok(&States::Booting)));
+    sm.context_mut().configured = true;
+    assert!(matches!(
+        sm.process_event(Events::Configure),
+        Ok(&States::Idle)
+    ));
+
+    // An initial state is taken as given
+    let sm = StateMachine::new_with_state(
+        Context {
+            configured: true,
+            ..Context::default()
+        },
+        States::Booting,
+    );
+    assert!(matches!(sm.state(), Ok(&States::Booting)));
+}
+
+#[test]
+fn completion_from_starting_states_in_regions() {
+    statemachine! {
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Booting = Offline,
+                    Offline + Connect = Online,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Available + Plug = Charging,
+                },
+            },
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context);
+    let state = sm.state().unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Available));
+
+    sm.process_event(Events::Connect).unwrap();
+    sm.process_event(Events::Plug).unwrap();
+}
+
+#[test]
+fn deferred_events() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug = Authorizing,
+            Authorizing + Authorized = Charging,
+            Charging + MeterValue(u32) [metering] / record = Charging,
+            Charging + Suspend = Suspended,
+            Suspended + Resume = Charging,
+            Session + Unplug = Idle,
+        },
+        states: {
+            Session: { defer: [MeterValue], substates: { *Charging, Suspended } },
+            Authorizing: { defer: [MeterValue] },
+        },
+        defer_capacity: 2,
+    }
+
+    #[derive(Default)]
+    struct Context {
+        metering: bool,
+        meter_values: Vec<u32>,
+    }
+
+    impl StateMachineContext for Context {
+        fn metering(&mut self, _event_data: &u32) -> Result<(), ()> {
+            if self.metering {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn record(&mut self, event_data: u32) {
+            self.meter_values.push(event_data);
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.context_mut().metering = true;
+
+    // Deferred events are kept instead of being rejected
+    assert!(matches!(
+        sm.process_event(Events::MeterValue(1)),
+        Err(Error::InvalidEvent)
+    ));
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::MeterValue(2)),
+        Ok(&States::Authorizing)
+    ));
+    sm.process_event(Events::MeterValue(3)).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::MeterValue(4)),
+        Err(Error::DeferredEventsFull)
+    ));
+    assert!(sm.context().meter_values.is_empty());
+
+    // They are replayed in order once a state is entered which handles them
+    assert!(matches!(
+        sm.process_event(Events::Authorized),
+        Ok(&States::Charging)
+    ));
+    assert_eq!(sm.context().meter_values, [2, 3]);
+
+    // Substates defer the events deferred by their composite state
+    sm.process_event(Events::Suspend).unwrap();
+    sm.process_event(Events::MeterValue(5)).unwrap();
+    sm.process_event(Events::Resume).unwrap();
+    assert_eq!(sm.context().meter_values, [2, 3, 5]);
+
+    // Replayed events which are rejected are dropped
+    sm.process_event(Events::Suspend).unwrap();
+    sm.process_event(Events::MeterValue(6)).unwrap();
+    sm.context_mut().metering = false;
+    sm.process_event(Events::Resume).unwrap();
+    sm.context_mut().metering = true;
+    sm.process_event(Events::Unplug).unwrap();
+    sm.process_event(Events::Plug).unwrap();
+    sm.process_event(Events::Authorized).unwrap();
+    assert_eq!(sm.context().meter_values, [2, 3, 5]);
+}
+
+#[test]
+fn event_queue() {
+    let mut queue: smlang::EventQueue<u32, 3> = smlang::EventQueue::new();
+    assert!(queue.is_empty());
+
+    queue.push(1).unwrap();
+    queue.push(2).unwrap();
+    queue.push(3).unwrap();
+    assert_eq!(queue.push(4), Err(4));
+    assert_eq!(queue.len(), 3);
+
+    // Items keep their order when one is removed from the middle, also when wrapping around
+    assert_eq!(queue.pop(), Some(1));
+    queue.push(4).unwrap();
+    assert_eq!(queue.remove(1), Some(3));
+    assert_eq!(queue.get(0), Some(&2));
+    assert_eq!(queue.get(1), Some(&4));
+    assert_eq!(queue.get(2), None);
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), Some(4));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn event_queue_run_to_completion() {
+    statemachine! {
+        event_queue: true,
+        transitions: {
+            *D0 + ToD1 / to_d2 = D1,
+            D1 + ToD2 / to_d3 = D2,
+            D2 + ToD3 = D3,
+            D3 + Burst / burst = D3,
+            D3 + Reset = D0,
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn to_d2(&mut self, queue: &mut smlang::EventSender<'_, Events>) {
+            assert!(queue.post(Events::ToD2).is_ok());
+        }
+
+        fn to_d3(&mut self, queue: &mut smlang::EventSender<'_, Events>) {
+            assert!(queue.post(Events::ToD3).is_ok());
+        }
+
+        fn burst(&mut self, queue: &mut smlang::EventSender<'_, Events>) {
+            // The queue holds two events, so the third one is dropped
+            assert!(queue.post(Events::ToD1).is_ok());
+            assert!(queue.post(Events::Reset).is_ok());
+            assert!(queue.post(Events::ToD1).is_err());
+        }
+    }
+
+    let mut sm: StateMachine<Context, 2> = StateMachine::new(Context);
+
+    // The posted events are processed before returning
+    let result = sm.process_event(Events::ToD1).unwrap();
+    assert_eq!(result.processed, 3);
+    assert!(!result.overflowed);
+    assert!(matches!(sm.state(), Ok(&States::D3)));
+
+    // Posted events which are rejected are dropped, and overflowing the queue is reported
+    let result = sm.process_event(Events::Burst).unwrap();
+    assert_eq!(result.processed, 3);
+    assert!(result.overflowed);
+    assert!(matches!(sm.state(), Ok(&States::D0)));
+}
+
+#[test]
+fn timed_transitions() {
+    use std::cell::Cell;
+
+    statemachine! {
+        clock: VirtualClock,
+        transitions: {
+            *Idle after(10) = Blinking,
+            Blinking + Plug / start_session = Session,
+            Session after(30) = Idle,
+            Authorizing(u32) after(5) [can_retry] / retry = Authorizing(u32),
+        },
+        states: {
+            Session: {
+                substates: {
+                    *Authorizing: {},
+                },
+            },
+        },
+    }
+
+    #[derive(Default)]
+    struct VirtualClock(Cell<u64>);
+
+    impl smlang::Clock for VirtualClock {
+        type Instant = u64;
+        type Duration = u64;
+
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        clock: VirtualClock,
+    }
+
+    impl StateMachineContext for Context {
+        fn clock(&self) -> &VirtualClock {
+            &self.clock
+        }
+
+        fn start_session(&mut self) -> u32 {
+            0
+        }
+
+        fn can_retry(&mut self, retries: &u32) -> Result<(), ()> {
+            if *retries < 2 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn retry(&mut self, retries: u32) -> u32 {
+            retries + 1
+        }
+    }
+
+    impl StateMachine<Context> {
+        fn advance(&mut self, now: u64) -> Result<Option<&States>, Error> {
+            self.context().clock.0.set(now);
+            self.tick(now)
+        }
+    }
+
+    let context = Context::default();
+    context.clock.0.set(100);
+    let mut sm = StateMachine::new(context);
+
+    // The timers of the starting state start when the state machine is created
+    assert_eq!(sm.next_deadline(), Some(110));
+    assert!(matches!(sm.advance(109), Ok(None)));
+    assert!(matches!(sm.advance(110), Ok(Some(&States::Blinking))));
+    assert_eq!(sm.next_deadline(), None);
+
+    // Entering a state records its entry time from the clock
+    sm.context().clock.0.set(120);
+    sm.process_event(Events::Plug).unwrap();
+    assert_eq!(sm.next_deadline(), Some(125));
+
+    // Re-entering a substate restarts its timers, but not the ones of its composite state
+    assert!(matches!(sm.advance(125), Ok(Some(&States::Authorizing(1)))));
+    assert_eq!(sm.next_deadline(), Some(130));
+    assert!(matches!(sm.advance(130), Ok(Some(&States::Authorizing(2)))));
+
+    // A timed transition whose guard fails is not taken, nor tried again until its state is
+    // re-entered, so the next deadline is the one of the composite state
+    assert!(matches!(sm.advance(135), Ok(None)));
+    assert_eq!(sm.next_deadline(), Some(150));
+    assert!(matches!(sm.advance(140), Ok(None)));
+    assert!(matches!(sm.advance(150), Ok(Some(&States::Idle))));
+}
+
+#[test]
+fn timed_starting_state() {
+    use std::cell::Cell;
+
+    statemachine! {
+        clock: VirtualClock,
+        transitions: {
+            *Idle after(10) = Blinking,
+            Blinking after(5) = Idle,
+            Session after(30) = Idle,
+            Charging + Unplug = Idle,
+        },
+        states: {
+            Session: {
+                substates: {
+                    *Charging: {},
+                },
+            },
+        },
+    }
+
+    struct VirtualClock(Cell<u64>);
+
+    impl smlang::Clock for VirtualClock {
+        type Instant = u64;
+        type Duration = u64;
+
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    struct Context {
+        clock: VirtualClock,
+    }
+
+    impl StateMachineContext for Context {
+        fn clock(&self) -> &VirtualClock {
+            &self.clock
+        }
+    }
+
+    // The deadline of the starting state is known before the first tick
+    let sm = StateMachine::new(Context {
+        clock: VirtualClock(Cell::new(40)),
+    });
+    assert_eq!(sm.next_deadline(), Some(50));
+
+    // An initial state starts its timers, and the ones of its composite states
+    let mut sm = StateMachine::new_with_state(
+        Context {
+            clock: VirtualClock(Cell::new(40)),
+        },
+        States::Charging,
+    );
+    assert_eq!(sm.next_deadline(), Some(70));
+    assert!(matches!(sm.tick(69), Ok(None)));
+    assert!(matches!(sm.tick(70), Ok(Some(&States::Idle))));
+}
+
+#[test]
+fn timed_transitions_in_regions() {
+    use std::cell::Cell;
+
+    statemachine! {
+        clock: VirtualClock,
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Connecting after(10) = Offline,
+                    Offline + Retry = Connecting,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Available + Reserve = Reserved,
+                    Reserved after(30) = Available,
+                },
+            },
+            Display: {
+                transitions: {
+                    *Idle + Touch = Idle,
+                },
+            },
+        }
+    }
+
+    struct VirtualClock(Cell<u64>);
+
+    impl smlang::Clock for VirtualClock {
+        type Instant = u64;
+        type Duration = u64;
+
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    struct Context {
+        clock: VirtualClock,
+    }
+
+    impl StateMachineContext for Context {
+        fn clock(&self) -> &VirtualClock {
+            &self.clock
+        }
+    }
+
+    let mut sm = StateMachine::new(Context {
+        clock: VirtualClock(Cell::new(0)),
+    });
+
+    // The timers of the starting states start when the state machine is created
+    assert_eq!(sm.next_deadline(), Some(10));
+
+    // The earliest deadline of all regions is reported
+    sm.context().clock.0.set(5);
+    sm.process_event(Events::Reserve).unwrap();
+    assert_eq!(sm.next_deadline(), Some(10));
+
+    // Each region takes its own timed transitions
+    assert!(matches!(sm.tick(9), Ok(None)));
+    let state = sm.tick(10).unwrap().unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Reserved));
+    assert_eq!(sm.next_deadline(), Some(35));
+
+    let state = sm.tick(35).unwrap().unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Available));
+    assert_eq!(sm.next_deadline(), None);
+
+    // A region without timed transitions does not affect the timers of the others
+    sm.process_event(Events::Touch).unwrap();
+    sm.context().clock.0.set(40);
+    sm.process_event(Events::Retry).unwrap();
+    assert_eq!(sm.next_deadline(), Some(50));
+}
+
+#[test]
+fn guard_expressions() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug [cable_ok && !locked] = Charging,
+            Idle + Reset [locked || (cable_ok && !faulted)] = Idle,
+            Charging + Unplug [!(locked || faulted)] = Idle,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        cable_ok: bool,
+        locked: bool,
+        faulted: bool,
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn cable_ok(&mut self) -> Result<(), ()> {
+            self.calls.push("cable_ok");
+            if self.cable_ok {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn locked(&mut self) -> Result<(), ()> {
+            self.calls.push("locked");
+            if self.locked {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn faulted(&mut self) -> Result<(), ()> {
+            self.calls.push("faulted");
+            if self.faulted {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // A failing guard short-circuits `&&`, and is reported with its error
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Err(Error::GuardExpressionFailed {
+            guard: "cable_ok",
+            error: Some(())
+        })
+    ));
+    assert_eq!(sm.context().calls, ["cable_ok"]);
+
+    // A negated guard which passes is reported without an error
+    sm.context_mut().cable_ok = true;
+    sm.context_mut().locked = true;
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Err(Error::GuardExpressionFailed {
+            guard: "locked",
+            error: None
+        })
+    ));
+
+    // A passing guard short-circuits `||`
+    sm.context_mut().calls.clear();
+    assert!(matches!(sm.process_event(Events::Reset), Ok(&States::Idle)));
+    assert_eq!(sm.context().calls, ["locked"]);
+
+    sm.context_mut().calls.clear();
+    sm.context_mut().locked = false;
+    assert!(matches!(sm.process_event(Events::Reset), Ok(&States::Idle)));
+    assert_eq!(sm.context().calls, ["locked", "cable_ok", "faulted"]);
+
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Ok(&States::Charging)
+    ));
+
+    sm.context_mut().faulted = true;
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Err(Error::GuardExpressionFailed {
+            guard: "faulted",
+            error: None
+        })
+    ));
+
+    sm.context_mut().faulted = false;
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Ok(&States::Idle)
+    ));
+}
+
+#[test]
+fn chained_actions() {
+    pub struct Session {
+        id: u32,
+        energy: u32,
+    }
+
+    statemachine! {
+        transitions: {
+            *Idle + Plug(u32) / (log_plug, start_session) = Charging(Session),
+            Charging(Session) + Meter(u32) / (log_meter, add_energy),
+            Charging(Session) + Unplug / (log_unplug, stop_meter, stop_session) = Idle,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        log: Vec<String>,
+    }
+
+    impl StateMachineContext for Context {
+        fn log_plug(&mut self, id: &u32) {
+            self.log.push(format!("plug {}", id));
+        }
+
+        fn start_session(&mut self, id: u32) -> Session {
+            self.log.push("start".to_string());
+            Session { id, energy: 0 }
+        }
+
+        fn log_meter(&mut self, session: &mut Session, energy: &u32) {
+            self.log
+                .push(format!("meter {} {}", session.id, session.energy + energy));
+        }
+
+        fn add_energy(&mut self, session: &mut Session, energy: u32) {
+            session.energy += energy;
+        }
+
+        fn log_unplug(&mut self, session: &Session) {
+            self.log
+                .push(format!("unplug {} {}", session.id, session.energy));
+        }
+
+        fn stop_meter(&mut self, _session: &Session) {
+            self.log.push("stop".to_string());
+        }
+
+        fn stop_session(&mut self, session: Session) {
+            self.log.push(format!("end {}", session.id));
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    assert!(sm.process_event(Events::Plug(7)).is_ok());
+    assert!(matches!(
+        sm.state(),
+        Ok(&States::Charging(Session { id: 7, energy: 0 }))
+    ));
+
+    assert!(sm.process_event(Events::Meter(5)).is_ok());
+    assert!(sm.process_event(Events::Meter(3)).is_ok());
+    assert!(matches!(
+        sm.state(),
+        Ok(&States::Charging(Session { id: 7, energy: 8 }))
+    ));
+
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Ok(&States::Idle)
+    ));
+
+    // The actions of a chain are run in order, and only the last one consumes the data
+    assert_eq!(
+        sm.context().log,
+        [
+            "plug 7",
+            "start",
+            "meter 7 5",
+            "meter 7 8",
+            "unplug 7 8",
+            "stop",
+            "end 7"
+        ]
+    );
+}
+
+#[test]
+fn final_states() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug = Charging,
+            Charging + Fault(u32) / record_fault = Faulted(u32)!,
+            _ + Decommission = Decommissioned!,
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn record_fault(&mut self, code: u32) -> u32 {
+            code
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert!(!sm.is_terminated());
+
+    assert!(sm.process_event(Events::Plug).is_ok());
+    assert!(matches!(
+        sm.process_event(Events::Fault(42)),
+        Ok(&States::Faulted(42))
+    ));
+    assert!(sm.is_terminated());
+
+    // Final states reject every event, including the ones of wildcard transitions
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Err(Error::Terminated)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Decommission),
+        Err(Error::Terminated)
+    ));
+    assert!(matches!(sm.state(), Ok(&States::Faulted(42))));
+
+    let mut sm = StateMachine::new(Context);
+    assert!(matches!(
+        sm.process_event(Events::Decommission),
+        Ok(&States::Decommissioned)
+    ));
+    assert!(sm.is_terminated());
+}
+
+#[test]
+// The transition leaving the final state is reported as never taken
+#[allow(deprecated)]
+fn final_states_in_regions() {
+    statemachine! {
+        derive_events: [Clone],
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Online + Disconnect = Offline!,
+                    Offline + Reconnect = Online,
+                    _ + Decommission = Offline,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Available + Plug = Charging,
+                    Charging + Unplug = Available,
+                    _ + Decommission = Decommissioned!,
+                },
+            },
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context);
+    assert!(!sm.is_terminated());
+
+    // A region in a final state no longer reacts to events, while the other regions still do
+    sm.process_event(Events::Disconnect).unwrap();
+    assert!(!sm.is_terminated());
+    assert!(matches!(
+        sm.process_event(Events::Disconnect),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Reconnect),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.state().unwrap().connectivity,
+        &ConnectivityStates::Offline
+    ));
+    let state = sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(state.charge_point, &ChargePointStates::Charging));
+
+    // The state machine is terminated once every region is in a final state
+    let state = sm.process_event(Events::Decommission).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Decommissioned));
+    assert!(sm.is_terminated());
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Err(Error::Terminated)
+    ));
+}
+
+#[test]
+fn choice_pseudo_states() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug / log_plug = ?{
+                [has_rfid] => Authorizing,
+                [free_mode] / start_session => Charging,
+                else => Rejected,
+            },
+            Authorizing + Unplug = Idle,
+            Charging + Unplug = Idle,
+            Rejected + Unplug = ?{
+                [locked] => Rejected,
+            },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        has_rfid: bool,
+        free_mode: bool,
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn has_rfid(&mut self) -> Result<(), ()> {
+            self.calls.push("has_rfid");
+            if self.has_rfid {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn free_mode(&mut self) -> Result<(), ()> {
+            self.calls.push("free_mode");
+            if self.free_mode {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn locked(&mut self) -> Result<(), ()> {
+            Err(())
+        }
+
+        fn log_plug(&mut self) {
+            self.calls.push("log_plug");
+        }
+
+        fn start_session(&mut self) {
+            self.calls.push("start_session");
+        }
+    }
+
+    // The action of the transition runs once before the guards of the branches, which are tried
+    // in order, and the action of the chosen branch runs last
+    let mut sm = StateMachine::new(Context {
+        free_mode: true,
+        ..Context::default()
+    });
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Ok(&States::Charging)
+    ));
+    assert_eq!(
+        sm.context().calls,
+        ["log_plug", "has_rfid", "free_mode", "start_session"]
+    );
+
+    let mut sm = StateMachine::new(Context {
+        has_rfid: true,
+        free_mode: true,
+        ..Context::default()
+    });
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Ok(&States::Authorizing)
+    ));
+    assert_eq!(sm.context().calls, ["log_plug", "has_rfid"]);
+
+    // The `else` branch is taken when no guard passes
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Ok(&States::Rejected)
+    ));
+    assert_eq!(sm.context().calls, ["log_plug", "has_rfid", "free_mode"]);
+
+    // Without an `else` branch, the guard of the last branch is reported
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Err(Error::GuardFailed(()))
+    ));
+}
+
+#[test]
+fn action_selected_targets() {
+    statemachine! {
+        transitions: {
+            *Idle + Send / send = Waiting,
+            Waiting + Response(u32) / (log, parse_response) = {Accepted | Retrying(u8) | Rejected(u32)!},
+            Accepted + Reset = Idle,
+            Retrying(u8) + Send / resend = Waiting,
+        },
+        states: {
+            Accepted: { entry: enter_accepted },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        retries: u8,
+        calls: Vec<&'static str>,
+    }
+
+    impl StateMachineContext for Context {
+        fn send(&mut self) {}
+
+        fn resend(&mut self, retries: u8) {
+            self.retries = retries;
+        }
+
+        fn log(&mut self, _code: &u32) {
+            self.calls.push("log");
+        }
+
+        fn parse_response(&mut self, code: u32) -> ParseResponseTarget {
+            self.calls.push("parse_response");
+            match code {
+                200 => ParseResponseTarget::Accepted,
+                503 => ParseResponseTarget::Retrying(self.retries + 1),
+                _ => ParseResponseTarget::Rejected(code),
+            }
+        }
+
+        fn enter_accepted(&mut self) {
+            self.calls.push("enter_accepted");
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    sm.process_event(Events::Send).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Response(200)),
+        Ok(&States::Accepted)
+    ));
+    assert_eq!(
+        sm.context().calls,
+        ["log", "parse_response", "enter_accepted"]
+    );
+
+    // The data of the chosen target is given by the action
+    sm.process_event(Events::Reset).unwrap();
+    sm.process_event(Events::Send).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Response(503)),
+        Ok(&States::Retrying(1))
+    ));
+    sm.process_event(Events::Send).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Response(503)),
+        Ok(&States::Retrying(2))
+    ));
+    sm.process_event(Events::Send).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Response(404)),
+        Ok(&States::Rejected(404))
+    ));
+    assert!(sm.is_terminated());
+}
+
+#[test]
+fn event_wildcards() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug = Charging,
+            Charging + Unplug = Idle,
+            Charging + Fault(u8) = Faulted,
+            Faulted + Reset = Idle,
+            Faulted + _ / log_ignored = Faulted,
+            Idle + _ [is_known] = Idle,
+        },
+        states: {
+            Faulted: { defer: [Plug] },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        ignored: Vec<Events>,
+    }
+
+    impl StateMachineContext for Context {
+        fn is_known(&mut self, event: &Events) -> Result<(), ()> {
+            match event {
+                Events::Fault(_) => Err(()),
+                _ => Ok(()),
+            }
+        }
+
+        fn log_ignored(&mut self, event: Events) {
+            self.ignored.push(event);
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+
+    // The guards of the wildcard event get the whole event
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Ok(&States::Idle)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Fault(1)),
+        Err(Error::GuardFailed(()))
+    ));
+
+    // Without a wildcard, unhandled events are still rejected
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Err(Error::InvalidEvent)
+    ));
+
+    // Explicit and deferred events take priority over the wildcard
+    sm.process_event(Events::Fault(2)).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Ok(&States::Faulted)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Ok(&States::Faulted)
+    ));
+    assert!(matches!(sm.context().ignored[..], [Events::Unplug]));
+
+    // The deferred event is replayed once the state is left
+    assert!(matches!(
+        sm.process_event(Events::Reset),
+        Ok(&States::Charging)
+    ));
+}
+
+#[test]
+fn wildcard_exclusions() {
+    statemachine! {
+        transitions: {
+            *Idle + Update = Updating,
+            Updating + Flash = Flashing,
+            Idle + Plug = Charging,
+            _ - (Updating | Flashing) + Reset = Idle,
+            _ - Idle + Fault = Faulted,
+        }
+    }
+
+    struct Context;
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context);
+    assert!(matches!(
+        sm.process_event(Events::Fault),
+        Err(Error::InvalidEvent)
+    ));
+
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(sm.process_event(Events::Reset), Ok(&States::Idle)));
+
+    // The excluded states do not take the transitions of the wildcard
+    sm.process_event(Events::Update).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Reset),
+        Err(Error::InvalidEvent)
+    ));
+    sm.process_event(Events::Flash).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Reset),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Fault),
+        Ok(&States::Faulted)
+    ));
+}
+
+#[test]
+fn event_wildcards_in_regions() {
+    statemachine! {
+        name: Charger,
+        derive_events: [Clone],
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Offline + Connect = Online,
+                    Online + Disconnect = Offline,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Available + Fault(u8) = Faulted,
+                    Faulted + Reset = Available,
+                    Faulted + _ / log_ignored = Faulted,
+                },
+            },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        ignored: Vec<ChargerEvents>,
+    }
+
+    impl ChargerStateMachineContext for Context {
+        fn log_ignored(&mut self, event: ChargerEvents) {
+            self.ignored.push(event);
+        }
+    }
+
+    let mut sm = ChargerStateMachine::new(Context::default());
+    sm.process_event(ChargerEvents::Fault(1)).unwrap();
+
+    // The wildcard gets every event its state does not handle, even the ones handled by
+    // another region
+    let state = sm.process_event(ChargerEvents::Connect).unwrap();
+    assert!(matches!(
+        state.connectivity,
+        &ChargerConnectivityStates::Online
+    ));
+    assert!(matches!(
+        state.charge_point,
+        &ChargerChargePointStates::Faulted
+    ));
+    sm.process_event(ChargerEvents::Fault(2)).unwrap();
+    assert!(matches!(
+        sm.context().ignored[..],
+        [ChargerEvents::Connect, ChargerEvents::Fault(2)]
+    ));
+
+    // Explicit events take priority over the wildcard
+    let state = sm.process_event(ChargerEvents::Reset).unwrap();
+    assert!(matches!(
+        state.charge_point,
+        &ChargerChargePointStates::Available
+    ));
+    sm.process_event(ChargerEvents::Disconnect).unwrap();
+    assert_eq!(sm.context().ignored.len(), 2);
+}
+
+#[test]
+fn unhandled_events() {
+    statemachine! {
+        transitions: {
+            *Idle + Plug = Charging,
+            Charging + Unplug = Idle,
+            Charging + MeterValue(u32) / record = Charging,
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        unhandled: Vec<Events>,
+    }
+
+    impl StateMachineContext for Context {
+        fn record(&mut self, _value: u32) {}
+
+        fn on_unhandled(&mut self, state: &States, event: Events) -> Result<(), Error> {
+            // Meter values are only expected while charging, but are harmless otherwise
+            match (state, event) {
+                (States::Idle, Events::MeterValue(_)) => Ok(()),
+                (_, event) => {
+                    self.unhandled.push(event);
+                    Err(Error::InvalidEvent)
+                }
+            }
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(matches!(
+        sm.process_event(Events::MeterValue(10)),
+        Ok(&States::Idle)
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Err(Error::InvalidEvent)
+    ));
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Plug),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.context().unhandled[..],
+        [Events::Unplug, Events::Plug]
+    ));
+}
+
+#[test]
+fn unhandled_events_in_regions() {
+    statemachine! {
+        derive_events: [Clone],
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Offline + Connect = Online,
+                    Online + Heartbeat = Online,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Available + Plug = Charging,
+                    Charging + Heartbeat = Charging,
+                },
+            },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        unhandled: Vec<Events>,
+    }
+
+    impl StateMachineContext for Context {
+        fn on_unhandled(&mut self, state: &States, event: Events) -> Result<(), Error> {
+            // Heartbeats are harmless while offline
+            match (state.connectivity, event) {
+                (ConnectivityStates::Offline, Events::Heartbeat) => Ok(()),
+                (_, event) => {
+                    self.unhandled.push(event);
+                    Err(Error::InvalidEvent)
+                }
+            }
+        }
+    }
+
+    let mut sm = StateMachine::new(Context::default());
+    assert!(sm.process_event(Events::Heartbeat).is_ok());
+
+    // The context is only called when no region handles the event
+    sm.process_event(Events::Connect).unwrap();
+    sm.process_event(Events::Heartbeat).unwrap();
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Connect),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.context().unhandled[..],
+        [Events::Connect]
+    ));
+}
+
+#[test]
+fn guarded_shared_events_in_regions() {
+    statemachine! {
+        derive_events: [Clone],
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Online + PowerLoss [no_backup] = Offline,
+                    Offline + Connect = Online,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Charging + PowerLoss = Faulted,
+                    Faulted + Reset = Charging,
+                },
+            },
+        }
+    }
+
+    #[derive(Default)]
+    struct Context {
+        backup: bool,
+        unhandled: Vec<Events>,
+    }
+
+    impl StateMachineContext for Context {
+        fn no_backup(&mut self) -> Result<(), ()> {
+            if self.backup {
+                Err(())
+            } else {
+                Ok(())
+            }
+        }
+
+        fn on_unhandled(&mut self, _state: &States, event: Events) -> Result<(), Error> {
+            self.unhandled.push(event);
+            Err(Error::InvalidEvent)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context {
+        backup: true,
+        ..Context::default()
+    });
+
+    // A region whose guards fail does not handle the event, which another region takes
+    let state = sm.process_event(Events::PowerLoss).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Online));
+    assert!(matches!(state.charge_point, &ChargePointStates::Faulted));
+
+    // When no region takes the event, the failed guard is reported instead of calling the context
+    assert!(matches!(
+        sm.process_event(Events::PowerLoss),
+        Err(Error::GuardFailed(()))
+    ));
+    assert!(sm.context().unhandled.is_empty());
+
+    sm.context_mut().backup = false;
+    sm.process_event(Events::Reset).unwrap();
+    let state = sm.process_event(Events::PowerLoss).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Offline));
+    assert!(matches!(state.charge_point, &ChargePointStates::Faulted));
+
+    // An event which no region has a transition for is handed to the context
+    assert!(matches!(
+        sm.process_event(Events::PowerLoss),
+        Err(Error::InvalidEvent)
+    ));
+    assert!(matches!(sm.context().unhandled[..], [Events::PowerLoss]));
+
+    sm.process_event(Events::Connect).unwrap();
+}
+
+#[test]
+fn submachines() {
+    statemachine! {
+        name: Auth,
+        transitions: {
+            *WaitingForCard + CardPresented(u32) [known_card] = WaitingForPin,
+            WaitingForPin + PinEntered(u16) [correct_pin] = Accepted!,
+        }
+    }
+
+    struct AuthContext;
+
+    impl AuthStateMachineContext for AuthContext {
+        fn known_card(&mut self, card: &u32) -> Result<(), ()> {
+            if *card == 42 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn correct_pin(&mut self, pin: &u16) -> Result<(), ()> {
+            if *pin == 1234 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    statemachine! {
+        transitions: {
+            *Idle + Plug / start_auth = Authorizing(AuthStateMachine<AuthContext>),
+            Authorizing(AuthStateMachine<AuthContext>) = Charging,
+            Authorizing(AuthStateMachine<AuthContext>) + Unplug = Idle,
+            Charging + Unplug = Idle,
+        },
+        states: {
+            Authorizing: { submachine: Auth },
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn start_auth(&mut self) -> AuthStateMachine<AuthContext> {
+            AuthStateMachine::new(AuthContext)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Plug).unwrap();
+
+    // The state is kept until the submachine reaches a final state
+    sm.process_event(Events::Auth(AuthEvents::CardPresented(42)))
+        .unwrap();
+    match sm.state() {
+        Ok(States::Authorizing(auth)) => {
+            assert!(matches!(auth.state(), Ok(&AuthStates::WaitingForPin)))
+        }
+        _ => panic!("the submachine should still be active"),
+    }
+
+    // Events rejected by the submachine are reported, and do not change the state
+    assert!(matches!(
+        sm.process_event(Events::Auth(AuthEvents::PinEntered(1))),
+        Err(Error::SubmachineRejected)
+    ));
+    assert!(matches!(sm.state(), Ok(&States::Authorizing(_))));
+
+    sm.process_event(Events::Auth(AuthEvents::PinEntered(1234)))
+        .unwrap();
+    assert!(matches!(sm.state(), Ok(&States::Charging)));
+
+    // The submachine is left like any other state
+    sm.process_event(Events::Unplug).unwrap();
+    sm.process_event(Events::Plug).unwrap();
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Ok(&States::Idle)
+    ));
+}
+
+#[test]
+fn submachines_in_regions() {
+    statemachine! {
+        name: Auth,
+        transitions: {
+            *WaitingForCard + CardPresented(u32) [known_card] = Accepted!,
+        }
+    }
+
+    struct AuthContext;
+
+    impl AuthStateMachineContext for AuthContext {
+        fn known_card(&mut self, card: &u32) -> Result<(), ()> {
+            if *card == 42 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    statemachine! {
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Offline + Connect = Online,
+                },
+            },
+            ChargePoint: {
+                transitions: {
+                    *Idle + Plug / start_auth = Authorizing(AuthStateMachine<AuthContext>),
+                    Authorizing(AuthStateMachine<AuthContext>) = Charging,
+                },
+                states: {
+                    Authorizing: { submachine: Auth },
+                },
+            },
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn start_auth(&mut self) -> AuthStateMachine<AuthContext> {
+            AuthStateMachine::new(AuthContext)
+        }
+    }
+
+    let mut sm = StateMachine::new(Context);
+    sm.process_event(Events::Plug).unwrap();
+    sm.process_event(Events::Connect).unwrap();
+
+    // The events of the submachine are forwarded to it by its region
+    assert!(matches!(
+        sm.process_event(Events::Auth(AuthEvents::CardPresented(1))),
+        Err(Error::SubmachineRejected)
+    ));
+    let state = sm
+        .process_event(Events::Auth(AuthEvents::CardPresented(42)))
+        .unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Online));
+    assert!(matches!(state.charge_point, &ChargePointStates::Charging));
+}
+
+#[test]
+fn generics() {
+    pub trait Transport {
+        type Handle: Copy + PartialEq + core::fmt::Debug;
+
+        fn open(&mut self) -> Self::Handle;
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Buffer<const N: usize>([u8; N]);
+
+    statemachine! {
+        generics: <T: Transport, M: Into<u32>, const N: usize>,
+        transitions: {
+            *Idle + Connect / connect = Connected(T::Handle),
+            Connected(T::Handle) + Send(M) / send = Connected(T::Handle),
+            Connected(T::Handle) + Receive(Buffer<N>) / receive = Received(Buffer<N>),
+            Received(Buffer<N>) + Disconnect = Idle,
+        }
+    }
+
+    struct Loopback {
+        next_handle: u8,
+    }
+
+    impl Transport for Loopback {
+        type Handle = u8;
+
+        fn open(&mut self) -> u8 {
+            self.next_handle += 1;
+            self.next_handle
+        }
+    }
+
+    struct Context<T: Transport> {
+        transport: T,
+        sent: u32,
+    }
+
+    impl<T: Transport, M: Into<u32>, const N: usize> StateMachineContext<T, M, N> for Context<T> {
+        fn connect(&mut self) -> T::Handle {
+            self.transport.open()
+        }
+
+        fn send(&mut self, _handle: T::Handle, message: M) -> T::Handle {
+            self.sent += message.into();
+            self.transport.open()
+        }
+
+        fn receive(&mut self, _handle: T::Handle, buffer: Buffer<N>) -> Buffer<N> {
+            buffer
+        }
+    }
+
+    let mut sm = StateMachine::new(Context {
+        transport: Loopback { next_handle: 0 },
+        sent: 0,
+    });
+
+    assert!(matches!(
+        sm.process_event(Events::Connect),
+        Ok(&States::Connected(1))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Send(5u8)),
+        Ok(&States::Connected(2))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Receive(Buffer([1, 2, 3]))),
+        Ok(States::Received(Buffer([1, 2, 3])))
+    ));
+    assert!(matches!(
+        sm.process_event(Events::Disconnect),
+        Ok(&States::Idle)
+    ));
+    assert_eq!(sm.context().sent, 5);
+}
+
+#[test]
+fn generics_in_regions() {
+    pub trait Transport {
+        type Handle: Copy + PartialEq + core::fmt::Debug;
+
+        fn open(&mut self) -> Self::Handle;
+    }
+
+    #[derive(Debug, PartialEq)]
+    pub struct Buffer<const N: usize>([u8; N]);
+
+    statemachine! {
+        generics: <T: Transport, M: Into<u32>, const N: usize>,
+        derive_states: [Debug],
+        regions: {
+            Connectivity: {
+                transitions: {
+                    *Offline + Connect / connect = Online(T::Handle),
+                    Online(T::Handle) + Send(M) / send = Online(T::Handle),
+                },
+            },
+            Reception: {
+                transitions: {
+                    *Waiting + Receive(Buffer<N>) / receive = Received(Buffer<N>),
+                    Received(Buffer<N>) + Clear = Waiting,
+                },
+            },
+        }
+    }
+
+    #[derive(Debug)]
+    struct Loopback {
+        next_handle: u8,
+    }
+
+    impl Transport for Loopback {
+        type Handle = u8;
+
+        fn open(&mut self) -> u8 {
+            self.next_handle += 1;
+            self.next_handle
+        }
+    }
+
+    struct Context<T: Transport> {
+        transport: T,
+        sent: u32,
+    }
+
+    impl<T: Transport, M: Into<u32>, const N: usize> StateMachineContext<T, M, N> for Context<T> {
+        fn connect(&mut self) -> T::Handle {
+            self.transport.open()
+        }
+
+        fn send(&mut self, _handle: T::Handle, message: M) -> T::Handle {
+            self.sent += message.into();
+            self.transport.open()
+        }
+
+        fn receive(&mut self, buffer: Buffer<N>) -> Buffer<N> {
+            buffer
+        }
+    }
+
+    let mut sm = StateMachine::new(Context {
+        transport: Loopback { next_handle: 0 },
+        sent: 0,
+    });
+
+    // Each region only takes the generic parameters used by the data of its states
+    let state = sm.process_event(Events::Connect).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Online(1)));
+    let state = sm.process_event(Events::Send(5u8)).unwrap();
+    assert!(matches!(state.connectivity, &ConnectivityStates::Online(2)));
+    let state = sm.process_event(Events::Receive(Buffer([1, 2, 3]))).unwrap();
+    assert_eq!(
+        format!("{:?}", state),
+        "States { connectivity: Online(2), reception: Received(Buffer([1, 2, 3])) }"
+    );
+    let state = sm.process_event(Events::Clear).unwrap();
+    assert!(matches!(state.reception, &ReceptionStates::Waiting));
+    assert_eq!(sm.context().sent, 5);
+}
+
+#[test]
+fn visibility_and_type_names() {
+    mod charger {
+        use smlang::statemachine;
+
+        statemachine! {
+            name: Charger,
+            visibility: pub(crate),
+            states_type: ChargerState,
+            error_type: ChargerError,
+            state_id_type: ChargerStateKind,
+            event_id_type: ChargerEventKind,
+            transitions: {
+                *Idle + Plug [is_authorized] = Charging,
+                Charging + Unplug = Idle,
+            }
+        }
+
+        pub(crate) struct Context;
+
+        impl ChargerStateMachineContext for Context {
+            fn is_authorized(&mut self) -> Result<(), ()> {
+                Ok(())
+            }
+        }
+    }
+
+    use charger::*;
+
+    let mut sm = ChargerStateMachine::new(Context);
+    assert!(matches!(
+        sm.process_event(ChargerEvents::Plug),
+        Ok(&ChargerState::Charging)
+    ));
+    assert!(matches!(
+        sm.process_event(ChargerEvents::Plug),
+        Err(ChargerError::InvalidEvent)
+    ));
+    assert!(matches!(
+        sm.process_event(ChargerEvents::Unplug),
+        Ok(&ChargerState::Idle)
+    ));
+    assert_eq!(sm.state().unwrap().id(), ChargerStateKind::Idle);
+    assert_eq!(ChargerEvents::Plug.id(), ChargerEventKind::Plug);
+}
+
+#[test]
+fn attributes() {
+    statemachine! {
+        derive_states: [core::fmt::Debug, Clone, Copy],
+        derive_events: [core::fmt::Debug],
+        states_attributes: [#[repr(u8)]],
+        events_attributes: [#[cfg_attr(test, derive(Clone))]],
+        error_attributes: [#[derive(PartialEq)]],
+        state_machine_attributes: [#[must_use]],
+        transitions: {
+            *Idle + Plug = Charging,
+            Charging + Unplug = Idle,
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {}
+
+    let mut sm = StateMachine::new(Context);
+    let event = Events::Plug;
+    assert_eq!(
+        *sm.process_event(event.clone()).unwrap() as u8,
+        States::Charging as u8
+    );
+    assert_eq!(format!("{:?}", sm.state().unwrap()), "Charging");
+    assert!(sm.process_event(event) == Err(Error::InvalidEvent));
+    assert_eq!(
+        *sm.process_event(Events::Unplug).unwrap() as u8,
+        States::Idle as u8
+    );
+}
+
+#[test]
+fn doc_comments() {
+    statemachine! {
+        states: {
+            /// Waiting for a car to be plugged in
+            Idle: { defer: [MeterValue] },
+            /// Charging the car
+            Session: {
+                substates: {
+                    /// Charging with a constant current
+                    *Bulk,
+                    Float,
+                }
+            },
+        },
+        events: {
+            /// The cable was plugged in
+            Plug,
+            /// A new meter value in Wh
+            MeterValue,
+        },
+        transitions: {
+            /// Starts charging once the driver is authorized
+            *Idle + Plug [is_authorized] / start = Session,
+            /// Switches to a lower current once the battery is almost full
+            Bulk + MeterValue(u32) [is_almost_full] = Float,
+            /// Stops charging
+            Session + Unplug = Idle,
+            Idle + Start / start = Session,
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn is_authorized(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn is_almost_full(&mut self, value: &u32) -> Result<(), ()> {
+            if *value > 90 {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+
+        fn start(&mut self) {}
+    }
+
+    let mut sm = StateMachine::new(Context);
+    assert!(matches!(sm.process_event(Events::Plug), Ok(&States::Bulk)));
+    assert!(matches!(
+        sm.process_event(Events::MeterValue(95)),
+        Ok(&States::Float)
+    ));
+    assert!(sm.state().unwrap().is_in(SuperStates::Session));
+    assert!(matches!(
+        sm.process_event(Events::Unplug),
+        Ok(&States::Idle)
+    ));
+    assert!(matches!(sm.process_event(Events::Start), Ok(&States::Bulk)));
+}
+
+#[test]
+fn state_and_event_ids() {
+    use core::convert::TryFrom;
+    use std::collections::HashMap;
+
+    statemachine! {
+        name: Charger,
+        transitions: {
+            *Idle + Plug(u32) / start = Charging(u32),
+            Charging(u32) + Unplug = Idle,
+        }
+    }
+
+    struct Context;
+
+    impl ChargerStateMachineContext for Context {
+        fn start(&mut self, current: u32) -> u32 {
+            current
+        }
+    }
+
+    let mut sm = ChargerStateMachine::new(Context);
+    assert_eq!(sm.state().unwrap().id(), ChargerStateId::Idle);
+    sm.process_event(ChargerEvents::Plug(16)).unwrap();
+    assert_eq!(sm.state().unwrap().id(), ChargerStateId::Charging);
+    assert_eq!(ChargerEvents::Plug(32).id(), ChargerEventId::Plug);
+
+    assert_eq!(
+        ChargerStateId::ALL,
+        [ChargerStateId::Charging, ChargerStateId::Idle]
+    );
+    assert_eq!(
+        ChargerEventId::ALL,
+        [ChargerEventId::Plug, ChargerEventId::Unplug]
+    );
+    assert_eq!(ChargerStateId::Charging.name(), "Charging");
+    assert_eq!(ChargerEventId::Unplug.name(), "Unplug");
+
+    for id in ChargerStateId::ALL {
+        assert_eq!(ChargerStateId::try_from(id as u8), Ok(id));
+    }
+    assert_eq!(ChargerEventId::try_from(2), Err(2));
+    assert!(ChargerStateId::Charging < ChargerStateId::Idle);
+
+    let mut entered = HashMap::new();
+    *entered.entry(sm.state().unwrap().id()).or_insert(0) += 1;
+    sm.process_event(ChargerEvents::Unplug).unwrap();
+    *entered.entry(sm.state().unwrap().id()).or_insert(0) += 1;
+    assert_eq!(entered[&ChargerStateId::Charging], 1);
+    assert_eq!(entered[&ChargerStateId::Idle], 1);
+}
+
+#[test]
+fn transition_table() {
+    use smlang::TransitionInfo;
+
+    statemachine! {
+        states: {
+            Session: {
+                substates: {
+                    *Bulk,
+                    Float,
+                }
+            },
+        },
+        transitions: {
+            *Idle + Plug [is_authorized && !is_locked] / (log, start) = Session,
+            Bulk + Full = Float,
+            Bulk + MeterValue / count,
+            Session + Pause = Paused,
+            Paused + Resume = Session.H,
+            _ + Fault = Faulted,
+            Faulted + _ / log_ignored = Idle,
+        }
+    }
+
+    struct Context;
+
+    impl StateMachineContext for Context {
+        fn is_authorized(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn is_locked(&mut self) -> Result<(), ()> {
+            Err(())
+        }
+
+        fn log(&mut self) {}
+
+        fn start(&mut self) {}
+
+        fn count(&mut self) {}
+
+        fn log_ignored(&mut self, _event: Events) {}
+    }
+
+    type Machine = StateMachine<Context>;
+
+    // The transitions of composite states are listed for their substates, wildcards for each
+    // state and event they take, and history targets for each state which can be resumed
+    assert_eq!(Machine::TRANSITIONS.len(), 17);
+    assert!(Machine::TRANSITIONS.contains(&TransitionInfo {
+        source: StateId::Idle,
+        event: EventId::Plug,
+        guard: Some("is_authorized && !is_locked"),
+        action: Some("(log, start)"),
+        target: StateId::Bulk,
+        wildcard: false,
+    }));
+    assert!(Machine::TRANSITIONS.contains(&TransitionInfo {
+        source: StateId::Bulk,
+        event: EventId::MeterValue,
+        guard: None,
+        action: Some("count"),
+        target: StateId::Bulk,
+        wildcard: false,
+    }));
+    assert!(Machine::TRANSITIONS.contains(&TransitionInfo {
+        source: StateId::Float,
+        event: EventId::Fault,
+        guard: None,
+        action: None,
+        target: StateId::Faulted,
+        wildcard: true,
+    }));
+    assert!(Machine::TRANSITIONS.contains(&TransitionInfo {
+        source: StateId::Faulted,
+        event: EventId::Resume,
+        guard: None,
+        action: Some("log_ignored"),
+        target: StateId::Idle,
+        wildcard: true,
+    }));
+
+    assert_eq!(
+        Machine::accepted_events(StateId::Idle),
+        [EventId::Fault, EventId::Plug]
+    );
+    assert_eq!(Machine::accepted_events(StateId::Faulted), EventId::ALL);
+    assert_eq!(
+        Machine::successors(StateId::Bulk),
+        [
+            StateId::Bulk,
+            StateId::Faulted,
+            StateId::Float,
+            StateId::Paused
+        ]
+    );
+    assert_eq!(
+        Machine::successors(StateId::Paused),
+        [StateId::Bulk, StateId::Faulted, StateId::Float]
+    );
+
+    // Every transition taken is listed in the table
+    let mut sm = Machine::new(Context);
+    for event in [
+        Events::Plug,
+        Events::MeterValue,
+        Events::Full,
+        Events::Pause,
+        Events::Resume,
+        Events::Fault,
+        Events::Pause,
+    ] {
+        let source = sm.state().unwrap().id();
+        let event_id = event.id();
+        let target = sm.process_event(event).unwrap().id();
+        assert!(Machine::TRANSITIONS
+            .iter()
+            .any(|t| t.source == source && t.event == event_id && t.target == target));
+    }
+    assert_eq!(sm.state().unwrap().id(), StateId::Idle);
+}